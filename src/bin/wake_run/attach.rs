@@ -0,0 +1,134 @@
+use std::{error::Error, io, mem, net::SocketAddr};
+
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    select,
+    signal::unix::{signal, SignalKind},
+};
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    http::{header::AUTHORIZATION, HeaderValue},
+    Message,
+};
+use wake_runner::{os::pty::WindowSize, server::wake::console::AttachMessage};
+
+/// Ctrl-], like telnet, leaves the run going and the terminal to the user
+const DETACH_KEY: u8 = 0x1d;
+
+/// Puts the local terminal in raw mode until dropped, so every key reaches the wake
+struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    fn enable() -> io::Result<RawTerminal> {
+        // SAFETY: termios is plain data, tcgetattr fills it in
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = original;
+        // SAFETY: `raw` is a valid termios for both calls
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(RawTerminal { original })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        // SAFETY: `original` is what tcgetattr returned
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+fn window_size() -> Option<WindowSize> {
+    // SAFETY: winsize is plain data, the ioctl fills it in
+    let mut winsize: libc::winsize = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut winsize) } < 0 {
+        return None;
+    }
+
+    Some(WindowSize {
+        cols: winsize.ws_col,
+        rows: winsize.ws_row,
+    })
+}
+
+fn resize_message(size: WindowSize) -> Message {
+    let resize = AttachMessage::Resize {
+        cols: size.cols,
+        rows: size.rows,
+    };
+    Message::Text(serde_json::to_string(&resize).unwrap())
+}
+
+/// Connects the local terminal to the run, returning its exit code once it exited or `None`
+/// when detaching or the connection was lost
+pub async fn run(
+    address: SocketAddr,
+    uri: &str,
+    id: &str,
+    auth_token: Option<&str>,
+) -> Result<Option<i32>, Box<dyn Error>> {
+    let stream = TcpStream::connect(address).await?;
+    let url = format!("{}/wake/{id}/attach", uri.replacen("http", "ws", 1));
+    let mut request = url.into_client_request()?;
+    if let Some(auth_token) = auth_token {
+        let bearer = HeaderValue::from_str(&format!("Bearer {auth_token}"))?;
+        request.headers_mut().insert(AUTHORIZATION, bearer);
+    }
+    let (socket, _) = tokio_tungstenite::client_async(request, stream).await?;
+    let (mut sender, mut receiver) = socket.split();
+
+    println!("Attached to {id}, press Ctrl-] to detach\r");
+    let _raw_terminal = RawTerminal::enable()?;
+    if let Some(size) = window_size() {
+        sender.send(resize_message(size)).await?;
+    }
+
+    let mut window_changes = signal(SignalKind::window_change())?;
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut input = [0u8; 1024];
+    loop {
+        select! {
+            read = stdin.read(&mut input) => {
+                let read = read?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                if let Some(detach) = input[..read].iter().position(|byte| *byte == DETACH_KEY) {
+                    sender.send(Message::Binary(input[..detach].to_vec())).await?;
+                    return Ok(None);
+                }
+                sender.send(Message::Binary(input[..read].to_vec())).await?;
+            }
+            _ = window_changes.recv() => {
+                if let Some(size) = window_size() {
+                    sender.send(resize_message(size)).await?;
+                }
+            }
+            message = receiver.next() => match message {
+                Some(Ok(Message::Binary(output))) => {
+                    stdout.write_all(&output).await?;
+                    stdout.flush().await?;
+                }
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(AttachMessage::Exited { exit_code }) = serde_json::from_str(&text) {
+                        return Ok(exit_code);
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(None),
+            },
+        }
+    }
+}
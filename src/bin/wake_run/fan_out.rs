@@ -0,0 +1,126 @@
+use std::{fmt, net::SocketAddr};
+
+use wake_runner::client::{
+    address_cache::{self, AddressCache},
+    inventory::Host,
+};
+use wake_runner::net::interface_filter::InterfaceFilter;
+
+use super::{
+    awake_host, send_wake_to_address, wait_for_ready_wake_run, wait_for_wake_run, StartArgs,
+};
+
+enum HostOutcome {
+    /// No runner answered discovery before the boot timeout
+    Unreachable,
+    /// The runner was found but did not start the wake
+    NotStarted,
+    /// The runner stopped answering while the wake was running
+    Lost,
+    /// Started with `--wait-ready` and ready
+    Ready,
+    Exited(Option<i32>),
+    /// Waking the host panicked or was cancelled
+    Failed(String),
+}
+
+impl HostOutcome {
+    fn is_success(&self) -> bool {
+        matches!(self, HostOutcome::Ready | HostOutcome::Exited(Some(0)))
+    }
+}
+
+impl fmt::Display for HostOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostOutcome::Unreachable => write!(f, "unreachable"),
+            HostOutcome::NotStarted => write!(f, "not started"),
+            HostOutcome::Lost => write!(f, "lost contact"),
+            HostOutcome::Ready => write!(f, "ready"),
+            HostOutcome::Exited(Some(code)) => write!(f, "exit {code}"),
+            HostOutcome::Exited(None) => write!(f, "killed by signal"),
+            HostOutcome::Failed(reason) => write!(f, "failed ({reason})"),
+        }
+    }
+}
+
+/// Wakes every host concurrently, runs the wake on each and prints a summary once all are done
+pub async fn run(hosts: Vec<Host>, args: StartArgs, interfaces: InterfaceFilter) -> Option<()> {
+    let handles: Vec<_> = hosts
+        .into_iter()
+        .map(|host| {
+            let args = args.clone();
+            let interfaces = interfaces.clone();
+            let task_host = host.clone();
+            let handle =
+                tokio::spawn(async move { run_on_host(&task_host, &args, &interfaces).await });
+            (host, handle)
+        })
+        .collect();
+
+    let mut results = vec![];
+    let mut announced = AddressCache::default();
+    for (host, handle) in handles {
+        let (address, outcome) = match handle.await {
+            Ok((address, outcome, host_announced)) => {
+                announced.extend(host_announced);
+                (address, outcome)
+            }
+            Err(e) => (None, HostOutcome::Failed(e.to_string())),
+        };
+        results.push((host, address, outcome));
+    }
+    results.sort_by(|(a, ..), (b, ..)| a.name.cmp(&b.name));
+
+    let found: Vec<_> = results
+        .iter()
+        .map(|(host, address, _)| (host.mac_addresses.as_slice(), *address))
+        .collect();
+    address_cache::remember(announced, &found).await;
+
+    println!();
+    println!("{:<16} {:<40} RESULT", "HOST", "ADDRESS");
+    for (host, address, outcome) in &results {
+        let address = address
+            .map(|address| address.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!("{:<16} {:<40} {}", host.name, address, outcome);
+    }
+
+    results
+        .iter()
+        .all(|(_, _, outcome)| outcome.is_success())
+        .then_some(())
+}
+
+/// How it went on `host` along with the runner addresses announced while waking it, those are
+/// saved once all hosts are done
+async fn run_on_host(
+    host: &Host,
+    args: &StartArgs,
+    interfaces: &InterfaceFilter,
+) -> (Option<SocketAddr>, HostOutcome, AddressCache) {
+    let awake = awake_host(args, Some(host), &host.mac_addresses, interfaces, None);
+    let (address, announced) = awake.await;
+    let Some(address) = address else {
+        return (None, HostOutcome::Unreachable, announced);
+    };
+
+    let auth_token = host.auth_token.as_deref();
+    let Some(id) = send_wake_to_address(address, &args.wake, auth_token).await else {
+        return (Some(address), HostOutcome::NotStarted, announced);
+    };
+
+    let outcome = match args.wait_ready {
+        true => match wait_for_ready_wake_run(address, &id, auth_token).await {
+            Some(Ok(())) => HostOutcome::Ready,
+            Some(Err(exit_code)) => HostOutcome::Exited(exit_code),
+            None => HostOutcome::Lost,
+        },
+        false => match wait_for_wake_run(address, &id, auth_token).await {
+            Some(exit_code) => HostOutcome::Exited(exit_code),
+            None => HostOutcome::Lost,
+        },
+    };
+    (Some(address), outcome, announced)
+}
@@ -0,0 +1,177 @@
+use std::{error::Error, net::SocketAddr, path::PathBuf};
+
+use clap::Subcommand;
+use mac_address::MacAddress;
+use wake_runner::{
+    client::{
+        address_cache::{load_address_cache, save_address_cache},
+        inventory::{
+            inventory_path, load_inventory, load_inventory_or_default, parse_import,
+            save_inventory, Group, Host,
+        },
+    },
+    net::wake_on_lan::{SecureOnPassword, DEFAULT_PORT},
+};
+
+#[derive(Subcommand)]
+pub enum HostsCommand {
+    /// Add a host, replacing any host with the same name
+    Add(AddHostArgs),
+    List,
+    Remove {
+        name: String,
+    },
+    /// Import hosts from an inventory toml file or `/etc/ethers` style `<mac> <name>` lines
+    Import {
+        file: PathBuf,
+    },
+    /// Manage named groups of hosts, `wake_run start <group>` wakes all of them
+    #[command(subcommand)]
+    Groups(GroupsCommand),
+}
+
+#[derive(Subcommand)]
+pub enum GroupsCommand {
+    /// Add a group, replacing any group with the same name
+    Add {
+        name: String,
+        #[arg(required = true)]
+        hosts: Vec<String>,
+    },
+    List,
+    Remove {
+        name: String,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct AddHostArgs {
+    name: String,
+
+    /// Can be given several times for hosts with more than one NIC
+    #[arg(long = "mac", required = true)]
+    mac_addresses: Vec<MacAddress>,
+
+    /// Known `ip:port` of the runner, tried directly before waking it like a discovered address
+    #[arg(long)]
+    address: Option<SocketAddr>,
+
+    /// Only send magic packets and discovery probes from this interface
+    #[arg(long)]
+    interface: Option<String>,
+
+    #[arg(long)]
+    secureon: Option<SecureOnPassword>,
+
+    #[arg(long, default_value_t = DEFAULT_PORT)]
+    wol_port: u16,
+
+    /// Bearer token for a proxy in front of the runner, the runner doesn't check it
+    #[arg(long)]
+    auth_token: Option<String>,
+
+    /// Recorded in the inventory, not verified yet
+    #[arg(long)]
+    tls_fingerprint: Option<String>,
+
+    /// Seconds to wait for the host to boot, the other retry settings live in the inventory file
+    #[arg(long)]
+    boot_timeout: Option<u64>,
+}
+
+pub async fn run(command: HostsCommand) -> Result<(), Box<dyn Error>> {
+    let read_only = matches!(
+        command,
+        HostsCommand::List | HostsCommand::Groups(GroupsCommand::List)
+    );
+    // Saving an inventory that failed to parse would replace the user's file with an empty one
+    let mut inventory = match read_only {
+        true => load_inventory_or_default().await,
+        false => match load_inventory().await {
+            Ok(inventory) => inventory,
+            Err(e) => {
+                println!("{e}");
+                println!("Fix it before changing the inventory");
+                std::process::exit(1);
+            }
+        },
+    };
+
+    match command {
+        HostsCommand::Add(args) => {
+            if let Some(address) = args.address {
+                let mut cache = load_address_cache().await;
+                for mac_address in &args.mac_addresses {
+                    cache.insert(*mac_address, address);
+                }
+                save_address_cache(&cache).await?;
+            }
+
+            let mut host = Host::new(args.name, args.mac_addresses);
+            host.interface = args.interface;
+            host.secureon = args.secureon;
+            host.wol_port = args.wol_port;
+            host.auth_token = args.auth_token;
+            host.tls_fingerprint = args.tls_fingerprint;
+            if let Some(boot_timeout) = args.boot_timeout {
+                host.backoff.boot_timeout_secs = boot_timeout;
+            }
+
+            inventory.upsert(host);
+        }
+        HostsCommand::List => {
+            println!("Inventory {:?}", inventory_path());
+            let cache = load_address_cache().await;
+            for host in &inventory.hosts {
+                let mac_addresses = host
+                    .mac_addresses
+                    .iter()
+                    .map(|mac| mac.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let address = host
+                    .mac_addresses
+                    .iter()
+                    .find_map(|mac| cache.last_known(*mac))
+                    .map(|address| address.to_string())
+                    .unwrap_or_else(|| "-".to_string());
+
+                println!("{:<16} {:<40} {}", host.name, mac_addresses, address);
+            }
+            return Ok(());
+        }
+        HostsCommand::Remove { name } => {
+            if inventory.remove(&name).is_none() {
+                return Err(format!("No host named {name:?}").into());
+            }
+        }
+        HostsCommand::Import { file } => {
+            let hosts = parse_import(&tokio::fs::read_to_string(&file).await?)?;
+            println!("Importing {} hosts from {:?}", hosts.len(), file);
+            for host in hosts {
+                inventory.upsert(host);
+            }
+        }
+        HostsCommand::Groups(GroupsCommand::Add { name, hosts }) => {
+            let group = Group { name, hosts };
+            // Catch typos now rather than at wake time
+            if let Err(missing) = inventory.group_hosts(&group) {
+                return Err(format!("No host named {missing:?}").into());
+            }
+            inventory.upsert_group(group);
+        }
+        HostsCommand::Groups(GroupsCommand::List) => {
+            for group in &inventory.groups {
+                println!("{:<16} {}", group.name, group.hosts.join(", "));
+            }
+            return Ok(());
+        }
+        HostsCommand::Groups(GroupsCommand::Remove { name }) => {
+            if inventory.remove_group(&name).is_none() {
+                return Err(format!("No group named {name:?}").into());
+            }
+        }
+    }
+
+    save_inventory(&inventory).await
+}
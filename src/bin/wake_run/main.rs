@@ -0,0 +1,544 @@
+use std::{error::Error, net::SocketAddr, time::Duration};
+
+use clap::{Parser, Subcommand};
+use mac_address::MacAddress;
+use serde::Serialize;
+use tokio::select;
+use wake_runner::client::{
+    address_cache::{self, load_address_cache, AddressCache},
+    discovery::receive_announcements,
+    inventory::{load_inventory_or_default, Host, Inventory},
+    wake_up::{report, wake_up, LanWakeUp, ProgressSender, WakeBackoff, WakeProgress},
+};
+use wake_runner::net::{
+    interface_filter::{FilteredNetwork, InterfaceFilter},
+    mdns::{self, MdnsQuery},
+    network::SystemNetwork,
+    wake_on_lan::{self, MagicPacket, SecureOnPassword, SendStrategy},
+};
+
+mod attach;
+mod fan_out;
+mod hosts;
+mod progress;
+
+/// A runner that is awake answers well within this, a sleeping one usually never does
+const DIRECT_PING_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long mDNS is asked for a runner discovery did not find
+const MDNS_FALLBACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct WakeRunBody {
+    name: String,
+}
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Wake a runner and start one of its wakes
+    Start(StartArgs),
+    /// Connect the terminal to a running wake started with `pty = true`
+    Attach(AttachArgs),
+    /// Manage the host inventory
+    #[command(subcommand)]
+    Hosts(hosts::HostsCommand),
+}
+
+#[derive(clap::Args)]
+struct AttachArgs {
+    /// Id of the run, as printed by `start`
+    id: String,
+
+    /// Runner to connect to, otherwise every runner address known from earlier wake ups is
+    /// asked for the run
+    #[arg(long)]
+    address: Option<SocketAddr>,
+}
+
+#[derive(clap::Args, Clone)]
+struct StartArgs {
+    /// Host or group name from the inventory or a mac address, anything else is resolved as a
+    /// runner name over mDNS
+    target: String,
+    wake: String,
+
+    /// 4 byte (`a.b.c.d`) or 6 byte (`aa:bb:cc:dd:ee:ff`) SecureOn password
+    #[arg(long)]
+    secureon: Option<SecureOnPassword>,
+
+    /// Destination udp port of the magic packet, usually 0, 7 or 9
+    #[arg(long)]
+    wol_port: Option<u16>,
+
+    /// Send raw ethernet frames (EtherType 0x0842) instead of udp, needs CAP_NET_RAW
+    #[arg(long)]
+    raw_ethernet: bool,
+
+    /// Seconds a discovered runner address is tried directly before waking and discovering again
+    #[arg(long, default_value_t = address_cache::DEFAULT_TTL.as_secs())]
+    address_ttl: u64,
+
+    /// Seconds to wait for the runner to boot, overrides the host's backoff settings
+    #[arg(long)]
+    boot_timeout: Option<u64>,
+
+    /// Return only once the run's output marked it ready, see the wake's `output_rules`
+    #[arg(long)]
+    wait_ready: bool,
+}
+
+impl StartArgs {
+    /// Command line options take precedence over the host's entry in the inventory
+    fn magic_packet(&self, mac_address: MacAddress, host: Option<&Host>) -> MagicPacket {
+        let port = self
+            .wol_port
+            .or(host.map(|host| host.wol_port))
+            .unwrap_or(wake_on_lan::DEFAULT_PORT);
+
+        let mut packet = MagicPacket::new(mac_address.bytes()).with_port(port);
+        if let Some(password) = self.secureon.or(host.and_then(|host| host.secureon)) {
+            packet = packet.with_password(password);
+        }
+        if self.raw_ethernet {
+            packet = packet.over_ethernet();
+        }
+        packet
+    }
+
+    fn backoff(&self, host: Option<&Host>) -> WakeBackoff {
+        let mut backoff = host.map(|host| host.backoff.clone()).unwrap_or_default();
+        if let Some(boot_timeout) = self.boot_timeout {
+            backoff.boot_timeout_secs = boot_timeout;
+        }
+        backoff
+    }
+}
+
+async fn start(args: StartArgs) -> Option<()> {
+    let inventory = load_inventory_or_default().await;
+    if let Some(group) = inventory.group(&args.target) {
+        return match inventory.group_hosts(group) {
+            Ok(hosts) => fan_out::run(hosts, args, inventory.interfaces.clone()).await,
+            Err(missing) => {
+                println!("Group member {missing:?} is not in the inventory");
+                None
+            }
+        };
+    }
+
+    let target_mac = args.target.parse::<MacAddress>().ok();
+    let host = inventory
+        .host(&args.target)
+        .or_else(|| target_mac.and_then(|mac| inventory.host_by_mac(mac)))
+        .cloned();
+
+    let mac_addresses = match (&host, target_mac) {
+        (Some(host), _) => host.mac_addresses.clone(),
+        (None, Some(mac)) => vec![mac],
+        (None, None) => return send_wake_by_mdns_name(&args).await,
+    };
+
+    let (progress, progress_events) = tokio::sync::mpsc::unbounded_channel();
+    let progress_bar = progress::render(progress_events, args.backoff(host.as_ref()));
+    let (address, announced) = awake_host(
+        &args,
+        host.as_ref(),
+        &mac_addresses,
+        &inventory.interfaces,
+        Some(progress),
+    )
+    .await;
+    let _ = progress_bar.await;
+    address_cache::remember(announced, &[(&mac_addresses, address)]).await;
+    let address = address?;
+
+    let auth_token = host.as_ref().and_then(|host| host.auth_token.as_deref());
+    let id = send_wake_to_address(address, &args.wake, auth_token).await?;
+    started(address, &id, auth_token, &args).await;
+    Some(())
+}
+
+/// Reports the new run, waiting for it to be ready when asked to
+async fn started(address: SocketAddr, id: &str, auth_token: Option<&str>, args: &StartArgs) {
+    println!("Started {:?} as run {id}", args.wake);
+    if !args.wait_ready {
+        return;
+    }
+
+    match wait_for_ready_wake_run(address, id, auth_token).await {
+        Some(Ok(())) => println!("Run {id} is ready"),
+        Some(Err(exit_code)) => {
+            println!("Run {id} exited with {exit_code:?} before it was ready");
+            std::process::exit(1);
+        }
+        None => {
+            println!("Lost contact with the runner while waiting for run {id}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Attaches to the run and exits with its exit code
+async fn attach(args: AttachArgs) -> Result<(), Box<dyn Error>> {
+    let inventory = load_inventory_or_default().await;
+    let cache = load_address_cache().await;
+    let address = match args.address {
+        Some(address) => Some(address),
+        None => find_runner_of(&args.id, &inventory, &cache).await,
+    };
+    let Some(address) = address else {
+        println!("No known runner has a run with id {:?}", args.id);
+        std::process::exit(1);
+    };
+
+    let uri = runner_uri_from_socket(address);
+    let auth_token = auth_token_of(address, &inventory, &cache);
+    let exit_code = attach::run(address, &uri, &args.id, auth_token).await?;
+    match exit_code {
+        Some(exit_code) => println!("\r\nRun {} exited with {exit_code}\r", args.id),
+        None => println!("\r\nDetached from {}\r", args.id),
+    }
+
+    // Reading stdin blocks a thread the runtime would otherwise wait for on shutdown
+    std::process::exit(exit_code.unwrap_or(0));
+}
+
+/// The auth token of the inventory host last discovered at `address`
+fn auth_token_of<'a>(
+    address: SocketAddr,
+    inventory: &'a Inventory,
+    cache: &AddressCache,
+) -> Option<&'a str> {
+    inventory
+        .hosts
+        .iter()
+        .find(|host| {
+            host.mac_addresses
+                .iter()
+                .any(|mac| cache.last_known(*mac) == Some(address))
+        })
+        .and_then(|host| host.auth_token.as_deref())
+}
+
+/// The runner among the known addresses that is running the run `id`
+async fn find_runner_of(
+    id: &str,
+    inventory: &Inventory,
+    cache: &AddressCache,
+) -> Option<SocketAddr> {
+    let mut addresses = cache.addresses();
+    addresses.sort();
+    addresses.dedup();
+
+    for address in addresses {
+        let http = runner_http_client(address);
+        let uri = runner_uri_from_socket(address);
+        let mut request = http.get(format!("{uri}/wake/{id}"));
+        if let Some(auth_token) = auth_token_of(address, inventory, cache) {
+            request = request.bearer_auth(auth_token);
+        }
+        let request = request.send();
+        let Ok(Ok(response)) = tokio::time::timeout(DIRECT_PING_TIMEOUT, request).await else {
+            continue;
+        };
+
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        if body["status"] == "running" {
+            return Some(address);
+        }
+    }
+
+    None
+}
+
+async fn awake_host(
+    args: &StartArgs,
+    host: Option<&Host>,
+    mac_addresses: &[MacAddress],
+    interfaces: &InterfaceFilter,
+    progress: Option<ProgressSender>,
+) -> (Option<SocketAddr>, AddressCache) {
+    let cache = load_address_cache().await;
+    let ttl = Duration::from_secs(args.address_ttl);
+    let cached = mac_addresses
+        .iter()
+        .filter_map(|mac| cache.get(*mac, ttl))
+        .collect();
+    let mut announced = AddressCache::default();
+    if let Some(address) = find_awake_runner(cached).await {
+        report(progress.as_ref(), WakeProgress::ApiReachable(address));
+        return (Some(address), announced);
+    }
+
+    let magic_packets = mac_addresses
+        .iter()
+        .map(|mac| args.magic_packet(*mac, host))
+        .collect();
+    let strategies = host
+        .map(|host| host.send_strategies())
+        .unwrap_or_else(|| vec![SendStrategy::LocalBroadcast]);
+    let interface = host.and_then(|host| host.interface.clone());
+
+    let network = LanWakeUp {
+        network: FilteredNetwork {
+            network: SystemNetwork,
+            filter: interfaces.clone(),
+        },
+        magic_packets,
+        strategies,
+        interface_name: interface,
+    };
+    let woken = async {
+        match wake_up(&network, &args.backoff(host), progress.as_ref()).await {
+            Some(address) => Some(address),
+            None => resolve_by_mac(mac_addresses.first()?).await,
+        }
+    };
+    // A runner that was already awake announces itself when it moved to another address
+    let announcement = receive_announcements(&network.network, mac_addresses, &mut announced);
+    let address = select! {
+        address = woken => address,
+        Some(address) = announcement => Some(address),
+    };
+    let Some(address) = address else {
+        return (None, announced);
+    };
+
+    let http = runner_http_client(address);
+    let uri = runner_uri_from_socket(address);
+    if ping_wake_runner(&http, &uri).await.is_some() {
+        report(progress.as_ref(), WakeProgress::ApiReachable(address));
+    }
+    (Some(address), announced)
+}
+
+/// Networks that filter broadcasts often still pass the multicast mDNS uses
+async fn resolve_by_mac(mac_address: &MacAddress) -> Option<SocketAddr> {
+    let query = MdnsQuery::Mac(*mac_address);
+    match mdns::resolve(&query, MDNS_FALLBACK_TIMEOUT).await {
+        Ok(Some(address)) => {
+            println!("Discovery got no answer, found {mac_address} at {address:?} over mDNS");
+            Some(address)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            println!("mDNS lookup failed: {e:?}");
+            None
+        }
+    }
+}
+
+/// The first of `addresses` answering a ping
+async fn find_awake_runner(mut addresses: Vec<SocketAddr>) -> Option<SocketAddr> {
+    addresses.dedup();
+    for address in addresses {
+        let http = runner_http_client(address);
+        let uri = runner_uri_from_socket(address);
+        let ping = tokio::time::timeout(DIRECT_PING_TIMEOUT, ping_wake_runner(&http, &uri));
+        if let Ok(Some(())) = ping.await {
+            return Some(address);
+        }
+    }
+
+    None
+}
+
+async fn send_wake_by_mdns_name(args: &StartArgs) -> Option<()> {
+    let name = &args.target;
+    let query = MdnsQuery::Name(name.to_string());
+    let address = match mdns::resolve(&query, Duration::from_secs(10)).await {
+        Ok(address) => address?,
+        Err(e) => {
+            println!("mDNS lookup failed: {e:?}");
+            return None;
+        }
+    };
+
+    println!("Resolved {name:?} to {address:?}");
+    let id = send_wake_to_address(address, &args.wake, None).await?;
+    started(address, &id, None, args).await;
+    Some(())
+}
+
+/// Starts the wake on the runner at `address` and returns the id of the run
+async fn send_wake_to_address(
+    address: SocketAddr,
+    wake: &str,
+    auth_token: Option<&str>,
+) -> Option<String> {
+    let http = runner_http_client(address);
+    let uri = runner_uri_from_socket(address);
+    send_wake_to_uri(&http, &uri, wake, auth_token).await
+}
+
+async fn send_wake_to_uri(
+    http: &reqwest::Client,
+    uri: &str,
+    wake: &str,
+    auth_token: Option<&str>,
+) -> Option<String> {
+    let mut request = http.post(format!("{uri}/wake")).json(&WakeRunBody {
+        name: wake.to_string(),
+    });
+    if let Some(auth_token) = auth_token {
+        request = request.bearer_auth(auth_token);
+    }
+
+    let result = request.send().await;
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            println!("Could not start {wake:?}: {e}");
+            return None;
+        }
+    };
+    if !response.status().is_success() {
+        println!("Runner refused to start {wake:?}: {}", response.status());
+        return None;
+    }
+
+    let body: serde_json::Value = response.json().await.ok()?;
+    body["id"].as_str().map(str::to_string)
+}
+
+async fn get_wake_run(
+    address: SocketAddr,
+    id: &str,
+    auth_token: Option<&str>,
+) -> Option<serde_json::Value> {
+    let http = runner_http_client(address);
+    let uri = runner_uri_from_socket(address);
+    let mut request = http.get(format!("{uri}/wake/{id}"));
+    if let Some(auth_token) = auth_token {
+        request = request.bearer_auth(auth_token);
+    }
+
+    request.send().await.ok()?.json().await.ok()
+}
+
+/// Polls the run until it exits, `None` when the runner stops answering
+async fn wait_for_wake_run(
+    address: SocketAddr,
+    id: &str,
+    auth_token: Option<&str>,
+) -> Option<Option<i32>> {
+    loop {
+        let body = get_wake_run(address, id, auth_token).await?;
+        match body["status"].as_str()? {
+            "exited" => return Some(body["exit_code"].as_i64().map(|code| code as i32)),
+            _ => tokio::time::sleep(Duration::from_secs(2)).await,
+        }
+    }
+}
+
+/// Polls the run until it is ready, `Err` with its exit code when it exited first and `None`
+/// when the runner stops answering
+async fn wait_for_ready_wake_run(
+    address: SocketAddr,
+    id: &str,
+    auth_token: Option<&str>,
+) -> Option<Result<(), Option<i32>>> {
+    loop {
+        let body = get_wake_run(address, id, auth_token).await?;
+        match (body["status"].as_str()?, body["ready"].as_bool()) {
+            ("exited", _) => return Some(Err(body["exit_code"].as_i64().map(|code| code as i32))),
+            (_, Some(true)) => return Some(Ok(())),
+            _ => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Host name used in the uri of link-local runners, urls cannot carry a v6 scope id so the
+/// address is handed to the http client through [`runner_http_client`] instead
+const LINK_LOCAL_RUNNER_HOST: &str = "link-local.wake-runner";
+
+fn runner_uri_from_socket(socket: SocketAddr) -> String {
+    match socket {
+        SocketAddr::V4(v4) => format!("http://{}:{}", v4.ip(), v4.port()),
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => {
+            format!("http://{}:{}", LINK_LOCAL_RUNNER_HOST, v6.port())
+        }
+        SocketAddr::V6(v6) => format!("http://[{}]:{}", v6.ip(), v6.port()),
+    }
+}
+
+fn runner_http_client(socket: SocketAddr) -> reqwest::Client {
+    match socket {
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => reqwest::Client::builder()
+            .resolve(LINK_LOCAL_RUNNER_HOST, socket)
+            .build()
+            .unwrap(),
+        _ => reqwest::Client::new(),
+    }
+}
+
+async fn ping_wake_runner(http: &reqwest::Client, uri: &str) -> Option<()> {
+    let request = http.get(format!("{uri}/ping")).send().await;
+    match request {
+        Ok(resp) => {
+            let json: String = resp.json().await.ok()?;
+            if json != "pong" {
+                return None;
+            } else {
+                return Some(());
+            }
+        }
+        Err(err) => println!("{:?}", err),
+    }
+
+    None
+}
+
+#[tokio::main()]
+async fn main() -> Result<(), Box<dyn Error>> {
+    // let config_str = fs::read_to_string("config.toml").await.unwrap();
+    // println!("{:?}", config_str);
+
+    // let config: Config = toml::from_str(&config_str).unwrap();
+
+    // println!("{:?}", config);
+    // Ok(())
+    match Args::parse().command {
+        Command::Start(args) => {
+            let target = args.target.clone();
+            if start(args).await.is_none() {
+                println!("Could not reach a wake runner at {:?}", target);
+                std::process::exit(1);
+            }
+        }
+        Command::Attach(args) => attach(args).await?,
+        Command::Hosts(command) => hosts::run(command).await?,
+    }
+
+    // let res = awake_wake_runner(target_address, Duration::from_secs(20)).await;
+    // println!("Result of awaking: {res:?}");
+
+    // println!("Waiting before exiting");
+    // tokio::time::sleep(Duration::from_secs(20)).await;
+
+    // let wake_runner_address = ping_for_wake_runner_address(target_address, Duration::from_secs(20))
+    //     .await
+    //     .ok_or("Could not get wake runner address")?;
+
+    // let uri = match wake_runner_address {
+    //     SocketAddr::V4(v4) => runner_uri_from_socket(v4),
+    //     SocketAddr::V6(_) => todo!(),
+    // };
+
+    // println!("Wake runner is on: {uri}");
+    // if let None = ping_wake_runner(uri).await {
+    //     println!("Warning: ping failed");
+    // }
+
+    // send_wake_to_uri("sleep", &uri).await;
+
+    // ping_for_wake_runner_address(target_address).await;
+    // wake_on_lan::send(target_address).await?;
+    Ok(())
+}
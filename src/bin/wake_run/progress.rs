@@ -0,0 +1,64 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use tokio::{select, sync::mpsc::UnboundedReceiver, task::JoinHandle};
+use wake_runner::client::wake_up::{WakeBackoff, WakeProgress};
+
+const BAR_WIDTH: usize = 30;
+
+/// Draws a single-line progress bar until the runner answers or the sender is dropped
+pub fn render(mut events: UnboundedReceiver<WakeProgress>, backoff: WakeBackoff) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let boot_timeout = backoff.boot_timeout();
+        let mut packets = 0;
+        let mut probes = 0;
+        let mut status = "waking".to_string();
+
+        loop {
+            select! {
+                event = events.recv() => match event {
+                    Some(WakeProgress::PacketSent { attempt }) => packets = attempt,
+                    Some(WakeProgress::DiscoveryAttempt { attempt }) => probes = attempt,
+                    Some(WakeProgress::RunnerFound(address)) => status = format!("found {address}"),
+                    Some(WakeProgress::ApiReachable(address)) => {
+                        status = format!("runner ready at {address}");
+                        draw(started.elapsed(), boot_timeout, packets, probes, &status, 1.0);
+                        break;
+                    }
+                    None => break,
+                },
+                _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+            }
+
+            let elapsed = started.elapsed();
+            let fraction = elapsed.as_secs_f64() / boot_timeout.as_secs_f64().max(1.0);
+            draw(elapsed, boot_timeout, packets, probes, &status, fraction);
+        }
+        println!();
+    })
+}
+
+fn draw(
+    elapsed: Duration,
+    boot_timeout: Duration,
+    packets: usize,
+    probes: usize,
+    status: &str,
+    fraction: f64,
+) {
+    let filled = ((fraction.clamp(0.0, 1.0)) * BAR_WIDTH as f64) as usize;
+    print!(
+        "\r[{}{}] {:>5.1}s/{}s  {} packets  {} probes  {:<40}",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        elapsed.as_secs_f64(),
+        boot_timeout.as_secs(),
+        packets,
+        probes,
+        status,
+    );
+    let _ = io::stdout().flush();
+}
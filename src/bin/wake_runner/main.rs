@@ -1,53 +1,79 @@
-use axum::{
-    extract::State,
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
-    Json, Router,
-};
+use chrono::Utc;
+use clap::{Parser, Subcommand};
 use listenfd::ListenFd;
 
-use futures::stream::{Stream, StreamExt};
-
-use network_interface::{NetworkInterface, NetworkInterfaceConfig};
-use notify::Config;
-use std::{
-    collections::HashMap,
-    error::Error,
-    future::{self, IntoFuture},
-    net::{Ipv4Addr, SocketAddrV4},
-    process::Stdio,
-    sync::{Arc, Mutex},
-    time::Duration,
-    vec,
-};
+use std::{error::Error, future::IntoFuture, time::Duration};
 use system_shutdown::shutdown;
-use tokio::process::Child;
-use tokio::{
-    fs,
-    net::TcpListener,
-    process::{ChildStdout, Command},
-    select,
-    sync::watch,
-};
+use tokio::{net::TcpListener, select, sync::watch};
 use wake_runner::{
-    os::users::watch_active_user_count,
-    server::{app::create_app, config::init_config, server_state::ServerState},
+    net::{
+        dual_stack, interface_filter::FilteredNetwork, interfaces::get_local_mac_addresses, mdns,
+        network::SystemNetwork, sniffer,
+    },
+    os::{
+        boot::{read_uptime, track_boot_context, BootConfig, BootContext},
+        rtc::{boot_time, RtcAlarm},
+        users::watch_active_user_count,
+    },
+    server::{
+        app::{create_app, create_state},
+        config::{init_config, Config},
+        discovery::discovery_server_reporting,
+        relay::udp_relay_listener,
+        wake::schedule::{next_scheduled_fire, run_scheduler},
+    },
 };
 
 use tokio_util::sync::CancellationToken;
 
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Report magic packets reaching this machine instead of running the server
+    Sniff {
+        #[arg(long, value_delimiter = ',', default_values_t = sniffer::DEFAULT_PORTS)]
+        ports: Vec<u16>,
+    },
+}
+
+/// Whether a scheduled run is close, once that changes. A scheduler that is gone has no runs
+/// coming up, after reporting that once this never resolves again.
+async fn imminent_change(run_imminent: &mut Option<watch::Receiver<bool>>) -> bool {
+    let Some(receiver) = run_imminent else {
+        return std::future::pending().await;
+    };
+    match receiver.changed().await {
+        Ok(()) => *receiver.borrow(),
+        Err(_) => {
+            *run_imminent = None;
+            false
+        }
+    }
+}
+
 async fn shutdown_condition(
     mut user_count: watch::Receiver<usize>,
     mut wake_process_count: watch::Receiver<usize>,
+    run_imminent: watch::Receiver<bool>,
+    mut boot_context: watch::Receiver<BootContext>,
+    boot_config: BootConfig,
     shutdown_token: CancellationToken,
 ) {
+    let mut run_imminent = Some(run_imminent);
     loop {
-        // Wait for the user and wake count to be 0
+        // Wait for the user and wake count to be 0, and no scheduled run to be close
         let mut users_zero = *user_count.borrow() == 0;
         let mut wakes_zero = *wake_process_count.borrow() == 0;
+        let mut imminent = run_imminent
+            .as_ref()
+            .is_some_and(|run_imminent| *run_imminent.borrow());
         println!("Waiting for users, wakes to be 0");
-        while !users_zero || !wakes_zero {
+        while !users_zero || !wakes_zero || imminent {
             select! {
                 result = user_count.changed() => {
                     result.unwrap();
@@ -56,16 +82,24 @@ async fn shutdown_condition(
                 result = wake_process_count.changed() => {
                     result.unwrap();
                     wakes_zero = *wake_process_count.borrow() == 0;
-                }
+                },
+                now_imminent = imminent_change(&mut run_imminent) => imminent = now_imminent,
             }
         }
 
-        // Wait for 60 seconds and if there has been no changes to the counts, we should shutdown
-        println!("No users, no wakes, waiting for shutdown");
+        // Wait for the idle timeout and if there has been no changes to the counts, we should shutdown.
+        // Boots by hand get longer than ones by a client, which are attributed once discovery is seen
+        let wake_source = boot_context.borrow().wake_source;
+        let idle_timeout = boot_config.idle_timeout(wake_source);
+        println!(
+            "No users, no wakes, waiting {idle_timeout:?} for shutdown ({wake_source:?} boot)"
+        );
         select! {
             _ = user_count.changed() => {},
             _ = wake_process_count.changed() => {},
-            _ = tokio::time::sleep(Duration::from_secs(60)) => {
+            _ = imminent_change(&mut run_imminent) => {},
+            _ = boot_context.changed() => {},
+            _ = tokio::time::sleep(idle_timeout) => {
                 break;
             }
         }
@@ -78,8 +112,44 @@ async fn shutdown_condition(
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    if let Some(Command::Sniff { ports }) = Args::parse().command {
+        sniffer::sniff(ports, |packet| println!("{packet}")).await?;
+        return Ok(());
+    }
+
     let config = init_config().await;
 
+    let rtc_alarm = config.rtc.enabled.then(|| RtcAlarm::new(&config.rtc));
+    let booted_at = match boot_time().await {
+        Ok(booted_at) => booted_at,
+        Err(e) => {
+            println!("Could not read the boot time: {e:?}");
+            Utc::now().timestamp()
+        }
+    };
+    let woke_by_alarm = match &rtc_alarm {
+        Some(rtc_alarm) => alarm_caused_boot(rtc_alarm, booted_at).await,
+        None => false,
+    };
+
+    let (boot_context_setter, boot_context) =
+        watch::channel(BootContext::detect(booted_at, woke_by_alarm).await);
+    println!("Boot context {:?}", *boot_context.borrow());
+    let uptime = match read_uptime().await {
+        Ok(uptime) => uptime,
+        Err(e) => {
+            println!("Could not read the uptime: {e:?}");
+            Duration::from_secs(Utc::now().timestamp().saturating_sub(booted_at).max(0) as u64)
+        }
+    };
+    let (answered_requests, answered_requests_receiver) = watch::channel(0);
+    tokio::spawn(track_boot_context(
+        boot_context_setter,
+        answered_requests_receiver,
+        Duration::from_secs(config.boot.discovery_window_secs),
+        uptime,
+    ));
+
     let (wake_process_count_setter, active_wake_process_count) = watch::channel(0);
     let tmp_active_wake = active_wake_process_count.clone();
 
@@ -87,21 +157,64 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let shutdown_signal = CancellationToken::new();
     let active_user_count = watch_active_user_count(shutdown_signal.clone()).await;
 
+    let (run_imminent_setter, run_imminent) = watch::channel(false);
     tokio::spawn(shutdown_condition(
         active_user_count,
         tmp_active_wake,
+        run_imminent,
+        boot_context.clone(),
+        config.boot.clone(),
         shutdown_signal.clone(),
     ));
 
     let mut listenfd = ListenFd::from_env();
     let listener = match listenfd.take_tcp_listener(0).unwrap() {
         Some(listener) => TcpListener::from_std(listener).unwrap(),
-        None => TcpListener::bind("0.0.0.0:0").await.unwrap(),
+        None => dual_stack::bind_tcp(0).unwrap(),
     };
 
     let server_listen_port = listener.local_addr().unwrap().port();
 
-    let app = create_app(wake_process_count_setter, active_wake_process_count, config);
+    let mdns_daemon = match get_local_mac_addresses().and_then(|mac_addresses| {
+        let tls_fingerprint = config.tls_fingerprint.as_deref();
+        mdns::advertise(server_listen_port, &mac_addresses, tls_fingerprint)
+    }) {
+        Ok(daemon) => Some(daemon),
+        Err(e) => {
+            println!("Could not advertise on mDNS: {e:?}");
+            None
+        }
+    };
+
+    let network = FilteredNetwork {
+        network: SystemNetwork,
+        filter: config.interfaces.clone(),
+    };
+
+    if let Some(relay_config) = config.relay.clone() {
+        if let Some(port) = relay_config.udp_port {
+            tokio::spawn(udp_relay_listener(
+                relay_config,
+                port,
+                network.clone(),
+                shutdown_signal.clone(),
+            ));
+        }
+    }
+
+    let app_state = create_state(
+        wake_process_count_setter,
+        active_wake_process_count,
+        boot_context,
+        config,
+    );
+    tokio::spawn(run_scheduler(
+        app_state.clone(),
+        run_imminent_setter,
+        shutdown_signal.clone(),
+    ));
+
+    let app = create_app(app_state.clone());
     // let app = Router::new().with_state(Arc::new(config));
     println!("Listening on: {}", listener.local_addr().unwrap());
     let mut should_shutoff = false;
@@ -110,66 +223,47 @@ async fn main() -> Result<(), Box<dyn Error>> {
         _ = shutdown_signal.cancelled() => {
            should_shutoff = true;
         },
-        _ = discovery_server(&shutdown_signal, server_listen_port) => {
+        _ = discovery_server_reporting(&network, &shutdown_signal, server_listen_port, &answered_requests) => {
             println!("discovery_server shut down");
         },
     }
 
+    if let Some(daemon) = mdns_daemon {
+        let _ = daemon.shutdown();
+    }
+
     if should_shutoff {
+        if let Some(rtc_alarm) = &rtc_alarm {
+            arm_for_next_run(rtc_alarm, &app_state.config).await;
+        }
         shutdown().unwrap();
     }
 
     Ok(())
 }
 
-pub fn get_addresses(
-    include_local: bool,
-) -> Result<Vec<network_interface::V4IfAddr>, Box<dyn Error>> {
-    let mut networks = vec![];
-    let ifs = NetworkInterface::show()?;
-    for nif in ifs {
-        for addr in &nif.addr {
-            match addr {
-                network_interface::Addr::V4(v4_addr) => {
-                    if !include_local && v4_addr.ip.is_loopback() {
-                        continue;
-                    }
-                    networks.push(v4_addr.clone());
-                }
-                _ => {}
-            }
+async fn alarm_caused_boot(rtc_alarm: &RtcAlarm, booted_at: i64) -> bool {
+    match rtc_alarm.check_boot(booted_at).await {
+        Ok(caused) => caused,
+        Err(e) => {
+            println!("Could not check the rtc alarm: {e:?}");
+            false
         }
-        println!("{:?}", nif.addr);
     }
-
-    Ok(networks)
 }
 
-const UDP_DISCOVERY_PORT: u16 = 23032;
-async fn discovery_server(shutdown_token: &CancellationToken, server_listen_port: u16) {
-    println!("Starting discovery server on {}", UDP_DISCOVERY_PORT);
-    let udp_socket =
-        tokio::net::UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, UDP_DISCOVERY_PORT))
-            .await
-            .unwrap();
-
-    let response_bytes = server_listen_port.to_le_bytes();
-    println!("Response bytes: {:?}", response_bytes);
+/// Sets the rtc alarm ahead of the next scheduled run, or clears ours when nothing is scheduled
+async fn arm_for_next_run(rtc_alarm: &RtcAlarm, config: &Config) {
+    let result = match next_scheduled_fire(&config.wakes, Utc::now()) {
+        Some(next_fire) => {
+            let at = next_fire.timestamp() - config.rtc.lead_secs as i64;
+            println!("Setting rtc alarm for {at}, next scheduled run at {next_fire}");
+            rtc_alarm.arm(at).await
+        }
+        None => rtc_alarm.disarm().await,
+    };
 
-    let mut buf = Vec::with_capacity(1024);
-    loop {
-        println!(
-            "Listening on discovery on {:?}",
-            udp_socket.local_addr().unwrap()
-        );
-        if let Ok((n_bytes, from)) = udp_socket.recv_buf_from(&mut buf).await {
-            println!("Got {}, from {:?}", n_bytes, from);
-            udp_socket.send_to(&response_bytes, from).await.unwrap();
-            println!("Local address {:?}", udp_socket.local_addr());
-        };
+    if let Err(e) = result {
+        println!("Could not set the rtc alarm: {e:?}");
     }
 }
-
-async fn get_wakes(state: State<Arc<ServerState>>) -> impl IntoResponse {
-    Json(state.config.wakes.clone())
-}
@@ -0,0 +1,128 @@
+use std::{
+    error::Error,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use directories::ProjectDirs;
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Runner addresses found by discovery, so an awake runner can be reached without broadcasting
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct AddressCache {
+    #[serde(default)]
+    entries: Vec<CachedAddress>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CachedAddress {
+    mac_address: MacAddress,
+    address: SocketAddr,
+    /// Seconds since the unix epoch
+    discovered_at: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl AddressCache {
+    /// The address discovered for `mac_address`, unless it is older than `ttl`
+    pub fn get(&self, mac_address: MacAddress, ttl: Duration) -> Option<SocketAddr> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.mac_address == mac_address)?;
+
+        (now().saturating_sub(entry.discovered_at) <= ttl.as_secs()).then_some(entry.address)
+    }
+
+    /// The address last discovered for `mac_address`, however old
+    pub fn last_known(&self, mac_address: MacAddress) -> Option<SocketAddr> {
+        self.entries
+            .iter()
+            .find(|entry| entry.mac_address == mac_address)
+            .map(|entry| entry.address)
+    }
+
+    pub fn insert(&mut self, mac_address: MacAddress, address: SocketAddr) {
+        self.forget(mac_address);
+        self.entries.push(CachedAddress {
+            mac_address,
+            address,
+            discovered_at: now(),
+        });
+    }
+
+    /// Every address discovered so far, however old
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.entries.iter().map(|entry| entry.address).collect()
+    }
+
+    /// Takes over the entries of `other`, they replace the ones for the same mac address
+    pub fn extend(&mut self, other: AddressCache) {
+        for entry in other.entries {
+            self.forget(entry.mac_address);
+            self.entries.push(entry);
+        }
+    }
+
+    pub fn forget(&mut self, mac_address: MacAddress) {
+        self.entries
+            .retain(|entry| entry.mac_address != mac_address);
+    }
+}
+
+pub fn address_cache_path() -> PathBuf {
+    let mut path = ProjectDirs::from("com", "ngodag", "wake_runner")
+        .unwrap()
+        .cache_dir()
+        .to_owned();
+
+    path.push("addresses.toml");
+    path
+}
+
+pub async fn load_address_cache() -> AddressCache {
+    let Ok(cache_str) = fs::read_to_string(address_cache_path()).await else {
+        return AddressCache::default();
+    };
+
+    // The cache is only an optimisation, a broken one is as good as none
+    toml::from_str(&cache_str).unwrap_or_default()
+}
+
+pub async fn save_address_cache(cache: &AddressCache) -> Result<(), Box<dyn Error>> {
+    let path = address_cache_path();
+    fs::create_dir_all(path.parent().unwrap()).await?;
+    fs::write(&path, toml::to_string(cache)?).await?;
+    Ok(())
+}
+
+/// Saves what waking hosts found out: the addresses runners `announced` and for each host where
+/// the runner owning its mac addresses was found, `None` drops the stale entries. Done once for
+/// all hosts woken together so they don't overwrite each other's updates.
+pub async fn remember(announced: AddressCache, found: &[(&[MacAddress], Option<SocketAddr>)]) {
+    let mut cache = load_address_cache().await;
+    cache.extend(announced);
+    for (mac_addresses, address) in found {
+        for mac_address in *mac_addresses {
+            match address {
+                Some(address) => cache.insert(*mac_address, *address),
+                None => cache.forget(*mac_address),
+            }
+        }
+    }
+
+    if let Err(e) = save_address_cache(&cache).await {
+        println!("Could not update the address cache: {e:?}");
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    time::Duration,
+};
+
+use mac_address::MacAddress;
+use tokio::{select, task::JoinSet};
+
+use crate::net::{
+    discovery::{
+        Announcement, DiscoveryRequest, IPV6_DISCOVERY_MULTICAST_GROUP, UDP_DISCOVERY_PORT,
+    },
+    network::{DatagramSocket, Network},
+};
+
+use super::address_cache::AddressCache;
+
+/// Sends one discovery probe on every interface of `network` and returns the first runner
+/// answering for `mac_address`. Dropping the future closes all probe sockets
+pub async fn discover_runner<N: Network>(
+    network: &N,
+    mac_address: MacAddress,
+    interface_name: Option<&str>,
+    timeout_duration: Duration,
+) -> Option<SocketAddr> {
+    let selected = |name: &str| interface_name.is_none_or(|selected| selected == name);
+    let interfaces = network.v4_interfaces().unwrap_or_else(|e| {
+        println!("Could not list v4 interfaces for discovery: {e:?}");
+        vec![]
+    });
+    // Aborts the receivers when dropped
+    let mut handles = JoinSet::new();
+
+    let request = DiscoveryRequest::Mac(mac_address).to_bytes();
+
+    for (name, interface) in interfaces {
+        let Some(broadcast_addr) = interface.broadcast.filter(|_| selected(&name)) else {
+            continue;
+        };
+
+        let socket = match send_v4_discovery_request(
+            network,
+            &request,
+            interface.ip,
+            broadcast_addr,
+        )
+        .await
+        {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("Could not send discovery on {name}: {e:?}");
+                continue;
+            }
+        };
+
+        handles.spawn(receive_discovery_response(socket));
+    }
+
+    let v6_interfaces = network.v6_interfaces().unwrap_or_else(|e| {
+        println!("Could not list v6 interfaces for discovery: {e:?}");
+        vec![]
+    });
+    for interface in v6_interfaces
+        .iter()
+        .filter(|interface| selected(&interface.name))
+    {
+        let socket = match send_v6_discovery_request(network, &request, interface.index).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("Could not send v6 discovery on {}: {e:?}", interface.name);
+                continue;
+            }
+        };
+
+        handles.spawn(receive_discovery_response(socket));
+    }
+
+    let udp_response = async {
+        while let Some(res) = handles.join_next().await {
+            if let Ok(Some(addr)) = res {
+                return Some(addr);
+            }
+        }
+        None
+    };
+
+    select! {
+        _ = tokio::time::sleep(timeout_duration) => None,
+        resp = udp_response => resp
+    }
+}
+
+/// Records where runners announce they moved to in `cache` and returns once the runner owning
+/// one of `mac_addresses` announced itself. `None` when the discovery port can not be bound,
+/// which is the case with a runner on this machine
+pub async fn receive_announcements<N: Network>(
+    network: &N,
+    mac_addresses: &[MacAddress],
+    cache: &mut AddressCache,
+) -> Option<SocketAddr> {
+    let socket = match network.bind_dual_stack(UDP_DISCOVERY_PORT) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("Not listening for runner announcements: {e:?}");
+            return None;
+        }
+    };
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let (len, mut from) = socket.recv_from(&mut buf).await.ok()?;
+        let Some(announcement) = Announcement::parse(&buf[..len]) else {
+            continue;
+        };
+
+        from.set_port(announcement.port);
+        println!("Runner {:?} announced {from:?}", announcement.mac_addresses);
+        for mac_address in &announcement.mac_addresses {
+            cache.insert(*mac_address, from);
+        }
+
+        if announcement
+            .mac_addresses
+            .iter()
+            .any(|mac_address| mac_addresses.contains(mac_address))
+        {
+            return Some(from);
+        }
+    }
+}
+
+async fn send_v4_discovery_request<N: Network>(
+    network: &N,
+    request: &[u8],
+    source: Ipv4Addr,
+    broadcast: Ipv4Addr,
+) -> io::Result<N::Socket> {
+    let socket = network.bind(SocketAddrV4::new(source, 0).into()).await?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(
+            request,
+            SocketAddrV4::new(broadcast, UDP_DISCOVERY_PORT).into(),
+        )
+        .await?;
+    Ok(socket)
+}
+
+async fn send_v6_discovery_request<N: Network>(
+    network: &N,
+    request: &[u8],
+    interface_index: u32,
+) -> io::Result<N::Socket> {
+    let socket = network
+        .bind(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0).into())
+        .await?;
+
+    let group = SocketAddrV6::new(
+        IPV6_DISCOVERY_MULTICAST_GROUP,
+        UDP_DISCOVERY_PORT,
+        0,
+        interface_index,
+    );
+    socket.send_to(request, group.into()).await?;
+    Ok(socket)
+}
+
+async fn receive_discovery_response<S: DatagramSocket>(socket: S) -> Option<SocketAddr> {
+    let mut read = [0u8; 2048];
+    let (len, mut remote_sock) = socket.recv_from(&mut read).await.ok()?;
+    if len != 2 {
+        return None;
+    }
+
+    // Keeps the scope id of link-local v6 responders
+    remote_sock.set_port(u16::from_le_bytes([read[0], read[1]]));
+    Some(remote_sock)
+}
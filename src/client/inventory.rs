@@ -0,0 +1,231 @@
+use std::{error::Error, io, path::PathBuf};
+
+use directories::ProjectDirs;
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::wake_up::WakeBackoff;
+use crate::net::{
+    interface_filter::InterfaceFilter,
+    wake_on_lan::{SecureOnPassword, SendStrategy, DEFAULT_PORT},
+};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Inventory {
+    /// Interfaces magic packets and discovery probes are sent from
+    #[serde(default)]
+    pub interfaces: InterfaceFilter,
+
+    #[serde(default)]
+    pub hosts: Vec<Host>,
+
+    #[serde(default)]
+    pub groups: Vec<Group>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Group {
+    pub name: String,
+    /// Names of hosts in the inventory
+    pub hosts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Host {
+    pub name: String,
+
+    /// Magic packets go to every address, discovery uses the first one
+    pub mac_addresses: Vec<MacAddress>,
+
+    /// Only send magic packets and discovery probes from this interface
+    #[serde(default)]
+    pub interface: Option<String>,
+
+    /// Every strategy is tried on each magic packet round
+    #[serde(default = "default_wake_on_lan")]
+    pub wake_on_lan: Vec<SendStrategy>,
+
+    #[serde(default)]
+    pub secureon: Option<SecureOnPassword>,
+
+    #[serde(default = "default_wol_port")]
+    pub wol_port: u16,
+
+    /// Sent as a bearer token with every request to the runner, for a proxy in front of it.
+    /// The runner itself doesn't check it, anyone reaching its port can start and attach to
+    /// wakes.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+
+    /// To compare with the `tls_fingerprint` the runner publishes over mDNS, only recorded for
+    /// now, nothing verifies the runner's certificate against it
+    #[serde(default)]
+    pub tls_fingerprint: Option<String>,
+
+    /// Magic packet retries and how long the host may take to boot
+    #[serde(default)]
+    pub backoff: WakeBackoff,
+}
+
+fn default_wake_on_lan() -> Vec<SendStrategy> {
+    vec![SendStrategy::LocalBroadcast]
+}
+
+fn default_wol_port() -> u16 {
+    DEFAULT_PORT
+}
+
+impl Host {
+    pub fn new(name: String, mac_addresses: Vec<MacAddress>) -> Host {
+        Self {
+            name,
+            mac_addresses,
+            interface: None,
+            wake_on_lan: default_wake_on_lan(),
+            secureon: None,
+            wol_port: default_wol_port(),
+            auth_token: None,
+            tls_fingerprint: None,
+            backoff: WakeBackoff::default(),
+        }
+    }
+
+    /// The configured strategies, with local broadcasts narrowed to the preferred interface
+    pub fn send_strategies(&self) -> Vec<SendStrategy> {
+        self.wake_on_lan
+            .iter()
+            .map(|strategy| match (strategy, &self.interface) {
+                (SendStrategy::LocalBroadcast, Some(name)) => {
+                    SendStrategy::Interface { name: name.clone() }
+                }
+                _ => strategy.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Inventory {
+    pub fn host(&self, name: &str) -> Option<&Host> {
+        self.hosts.iter().find(|host| host.name == name)
+    }
+
+    pub fn host_mut(&mut self, name: &str) -> Option<&mut Host> {
+        self.hosts.iter_mut().find(|host| host.name == name)
+    }
+
+    pub fn host_by_mac(&self, mac_address: MacAddress) -> Option<&Host> {
+        self.hosts
+            .iter()
+            .find(|host| host.mac_addresses.contains(&mac_address))
+    }
+
+    /// Adds the host, replacing any host with the same name
+    pub fn upsert(&mut self, host: Host) {
+        match self.host_mut(&host.name) {
+            Some(existing) => *existing = host,
+            None => self.hosts.push(host),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Host> {
+        let index = self.hosts.iter().position(|host| host.name == name)?;
+        Some(self.hosts.remove(index))
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+
+    /// The group's hosts, or the name of the first member missing from the inventory
+    pub fn group_hosts(&self, group: &Group) -> Result<Vec<Host>, String> {
+        group
+            .hosts
+            .iter()
+            .map(|name| self.host(name).cloned().ok_or_else(|| name.clone()))
+            .collect()
+    }
+
+    pub fn upsert_group(&mut self, group: Group) {
+        match self
+            .groups
+            .iter_mut()
+            .find(|existing| existing.name == group.name)
+        {
+            Some(existing) => *existing = group,
+            None => self.groups.push(group),
+        }
+    }
+
+    pub fn remove_group(&mut self, name: &str) -> Option<Group> {
+        let index = self.groups.iter().position(|group| group.name == name)?;
+        Some(self.groups.remove(index))
+    }
+}
+
+pub fn inventory_path() -> PathBuf {
+    let mut path = ProjectDirs::from("com", "ngodag", "wake_runner")
+        .unwrap()
+        .config_dir()
+        .to_owned();
+
+    path.push("inventory.toml");
+    path
+}
+
+/// An empty inventory when there is no inventory file, an error naming the file when it can't
+/// be read or parsed
+pub async fn load_inventory() -> Result<Inventory, String> {
+    let path = inventory_path();
+    let inventory_str = match fs::read_to_string(&path).await {
+        Ok(inventory_str) => inventory_str,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Inventory::default()),
+        Err(e) => return Err(format!("Could not read the inventory {path:?}: {e}")),
+    };
+
+    toml::from_str(&inventory_str).map_err(|e| format!("Invalid inventory {path:?}: {e}"))
+}
+
+/// For commands that only read the inventory, a broken file is reported and treated as empty.
+/// Anything saving the inventory must use [`load_inventory`] so it doesn't overwrite the file.
+pub async fn load_inventory_or_default() -> Inventory {
+    load_inventory().await.unwrap_or_else(|e| {
+        println!("Ignoring it: {e}");
+        Inventory::default()
+    })
+}
+
+pub async fn save_inventory(inventory: &Inventory) -> Result<(), Box<dyn Error>> {
+    let path = inventory_path();
+    fs::create_dir_all(path.parent().unwrap()).await?;
+    fs::write(&path, toml::to_string(inventory)?).await?;
+    Ok(())
+}
+
+/// Reads hosts from an inventory toml file, or from `/etc/ethers` style `<mac> <name>` lines
+pub fn parse_import(content: &str) -> Result<Vec<Host>, Box<dyn Error>> {
+    if let Ok(inventory) = toml::from_str::<Inventory>(content) {
+        return Ok(inventory.hosts);
+    }
+
+    let mut hosts: Vec<Host> = vec![];
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(mac_address), Some(name)) = (fields.next(), fields.next()) else {
+            return Err(format!("Expected `<mac> <name>`, got {line:?}").into());
+        };
+        let mac_address = mac_address.parse::<MacAddress>()?;
+
+        match hosts.iter_mut().find(|host| host.name == name) {
+            Some(host) => host.mac_addresses.push(mac_address),
+            None => hosts.push(Host::new(name.to_string(), vec![mac_address])),
+        }
+    }
+
+    Ok(hosts)
+}
@@ -0,0 +1,4 @@
+pub mod address_cache;
+pub mod discovery;
+pub mod inventory;
+pub mod wake_up;
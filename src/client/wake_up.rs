@@ -0,0 +1,222 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    pin::pin,
+    time::Duration,
+};
+
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    select,
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+use super::discovery::discover_runner;
+use crate::net::{
+    network::{Network, SystemNetwork},
+    wake_on_lan::{MagicPacket, SendStrategy},
+};
+
+/// How long a single discovery probe waits for an answer
+pub const DISCOVERY_PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// How magic packets are retried while waiting for a runner to boot
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WakeBackoff {
+    /// Milliseconds before the first magic packet round
+    pub initial_delay_ms: u64,
+    pub multiplier: f64,
+    /// Upper bound of a single delay in milliseconds
+    pub max_delay_ms: u64,
+    /// Fraction of every delay that is randomly added or removed, between 0 and 1
+    pub jitter: f64,
+    /// Magic packet rounds sent, discovery keeps going until the boot timeout
+    pub max_attempts: usize,
+    /// Seconds to wait for the runner to answer discovery
+    pub boot_timeout_secs: u64,
+}
+
+impl Default for WakeBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 25,
+            multiplier: 2.0,
+            max_delay_ms: 10_000,
+            jitter: 0.1,
+            max_attempts: 10,
+            boot_timeout_secs: 40,
+        }
+    }
+}
+
+impl WakeBackoff {
+    pub fn boot_timeout(&self) -> Duration {
+        Duration::from_secs(self.boot_timeout_secs)
+    }
+
+    /// The delay before each magic packet round
+    pub fn delays(&self) -> impl Iterator<Item = Duration> + Send + 'static {
+        let WakeBackoff {
+            initial_delay_ms,
+            multiplier,
+            max_delay_ms,
+            jitter,
+            max_attempts,
+            ..
+        } = self.clone();
+        let jitter = jitter.clamp(0.0, 1.0);
+
+        let mut delay_ms = initial_delay_ms as f64;
+        (0..max_attempts).map(move |_| {
+            let capped_ms = delay_ms.min(max_delay_ms as f64);
+            delay_ms *= multiplier;
+
+            // Uniform in [-jitter, jitter]
+            let offset = (random_unit() * 2.0 - 1.0) * jitter;
+            Duration::from_secs_f64((capped_ms * (1.0 + offset)).max(0.0) / 1000.0)
+        })
+    }
+}
+
+/// A random number in [0, 1), good enough to keep clients from retrying in lockstep
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Reported while a runner is woken up, for rendering progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeProgress {
+    /// A round of magic packets went out, counting from 1
+    PacketSent {
+        attempt: usize,
+    },
+    /// A discovery probe was sent, counting from 1
+    DiscoveryAttempt {
+        attempt: usize,
+    },
+    RunnerFound(SocketAddr),
+    /// The runner answered a ping on its http api
+    ApiReachable(SocketAddr),
+}
+
+pub type ProgressSender = mpsc::UnboundedSender<WakeProgress>;
+
+/// Sends `event` if anyone is listening, a closed receiver is not an error
+pub fn report(progress: Option<&ProgressSender>, event: WakeProgress) {
+    if let Some(progress) = progress {
+        let _ = progress.send(event);
+    }
+}
+
+/// What waking a runner needs from the network
+pub trait WakeUpNetwork {
+    /// Sends one round of magic packets
+    fn send_magic_packets(&self) -> impl Future<Output = ()> + Send;
+
+    /// Sends one discovery probe and waits up to `timeout` for the runner to answer
+    fn discover(&self, timeout: Duration) -> impl Future<Output = Option<SocketAddr>> + Send;
+}
+
+/// Wakes a host reachable from the interfaces of `network`
+pub struct LanWakeUp<N = SystemNetwork> {
+    pub network: N,
+    pub magic_packets: Vec<MagicPacket>,
+    /// Every strategy is used for every packet in a round
+    pub strategies: Vec<SendStrategy>,
+    /// Only probe from this interface
+    pub interface_name: Option<String>,
+}
+
+impl<N: Network> WakeUpNetwork for LanWakeUp<N> {
+    async fn send_magic_packets(&self) {
+        for magic_packet in &self.magic_packets {
+            for strategy in &self.strategies {
+                let sent = magic_packet
+                    .send_with_strategy_on(&self.network, strategy)
+                    .await;
+                if let Err(e) = sent {
+                    println!("Could not send wake-on-lan with {strategy:?}: {e:?}");
+                }
+            }
+        }
+    }
+
+    async fn discover(&self, timeout: Duration) -> Option<SocketAddr> {
+        // The runner answers discovery for any of its mac addresses
+        let mac_address = MacAddress::new(self.magic_packets.first()?.mac_address());
+        discover_runner(
+            &self.network,
+            mac_address,
+            self.interface_name.as_deref(),
+            timeout,
+        )
+        .await
+    }
+}
+
+/// Sends magic packets on the backoff schedule while probing for the runner, until it answers or
+/// the boot timeout passes.
+///
+/// Nothing is spawned, the packet schedule and the probe in flight are owned by the returned
+/// future. Dropping it, e.g. on ctrl-c or an outer timeout, stops sending right away.
+pub async fn wake_up<N: WakeUpNetwork + Sync>(
+    network: &N,
+    backoff: &WakeBackoff,
+    progress: Option<&ProgressSender>,
+) -> Option<SocketAddr> {
+    let started = Instant::now();
+    let deadline = started + backoff.boot_timeout();
+
+    let mut delays = backoff.delays();
+    let mut next_packet = delays.next().map(|delay| started + delay);
+    let mut packet_attempt = 0;
+
+    let mut discovery_attempt = 1;
+    report(
+        progress,
+        WakeProgress::DiscoveryAttempt {
+            attempt: discovery_attempt,
+        },
+    );
+    let mut discovery = pin!(probe(network));
+
+    loop {
+        select! {
+            _ = sleep_until(deadline) => return None,
+            _ = sleep_until(next_packet.unwrap_or(deadline)), if next_packet.is_some() => {
+                network.send_magic_packets().await;
+                packet_attempt += 1;
+                report(progress, WakeProgress::PacketSent { attempt: packet_attempt });
+                next_packet = delays.next().map(|delay| Instant::now() + delay);
+            }
+            found = &mut discovery => match found {
+                Some(address) => {
+                    report(progress, WakeProgress::RunnerFound(address));
+                    return Some(address);
+                }
+                None => {
+                    discovery_attempt += 1;
+                    report(progress, WakeProgress::DiscoveryAttempt { attempt: discovery_attempt });
+                    discovery.set(probe(network));
+                }
+            },
+        }
+    }
+}
+
+/// A probe that fails fast, e.g. without any usable interface, must not turn into a busy loop
+async fn probe<N: WakeUpNetwork>(network: &N) -> Option<SocketAddr> {
+    let started = Instant::now();
+    let found = network.discover(DISCOVERY_PROBE_TIMEOUT).await;
+    if found.is_none() {
+        sleep_until(started + DISCOVERY_PROBE_TIMEOUT).await;
+    }
+    found
+}
@@ -1,3 +1,4 @@
+pub mod client;
 pub mod net;
 pub mod os;
 pub mod server;
@@ -0,0 +1,81 @@
+use std::net::Ipv6Addr;
+
+use mac_address::MacAddress;
+
+pub const UDP_DISCOVERY_PORT: u16 = 23032;
+
+/// Link-local group runners join on every v6 interface, v6 has no broadcast
+pub const IPV6_DISCOVERY_MULTICAST_GROUP: Ipv6Addr =
+    Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x7761, 0x6b65);
+
+/// Payload a client sends to enumerate every runner, regardless of its mac address
+const ANY_WILDCARD: &[u8] = b"any";
+const ANNOUNCEMENT_PREFIX: &[u8] = b"announce";
+const MAC_ADDRESS_SIZE: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoveryRequest {
+    Any,
+    Mac(MacAddress),
+}
+
+impl DiscoveryRequest {
+    pub fn parse(payload: &[u8]) -> Option<DiscoveryRequest> {
+        if payload == ANY_WILDCARD {
+            return Some(DiscoveryRequest::Any);
+        }
+
+        let bytes: [u8; MAC_ADDRESS_SIZE] = payload.try_into().ok()?;
+        Some(DiscoveryRequest::Mac(MacAddress::new(bytes)))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            DiscoveryRequest::Any => ANY_WILDCARD.to_vec(),
+            DiscoveryRequest::Mac(mac_address) => mac_address.bytes().to_vec(),
+        }
+    }
+
+    /// Whether a runner owning `local_mac_addresses` should answer this request
+    pub fn matches(&self, local_mac_addresses: &[MacAddress]) -> bool {
+        match self {
+            DiscoveryRequest::Any => true,
+            DiscoveryRequest::Mac(mac_address) => local_mac_addresses.contains(mac_address),
+        }
+    }
+}
+
+/// Sent unsolicited to the discovery port by a runner whose addresses changed, the sender's
+/// address together with `port` is where the runner can be reached now
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub port: u16,
+    pub mac_addresses: Vec<MacAddress>,
+}
+
+impl Announcement {
+    pub fn parse(payload: &[u8]) -> Option<Announcement> {
+        let payload = payload.strip_prefix(ANNOUNCEMENT_PREFIX)?;
+        let (port, mac_addresses) = payload.split_first_chunk::<2>()?;
+        if mac_addresses.len() % MAC_ADDRESS_SIZE != 0 {
+            return None;
+        }
+
+        Some(Announcement {
+            port: u16::from_le_bytes(*port),
+            mac_addresses: mac_addresses
+                .chunks_exact(MAC_ADDRESS_SIZE)
+                .map(|mac| MacAddress::new(mac.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = ANNOUNCEMENT_PREFIX.to_vec();
+        bytes.extend_from_slice(&self.port.to_le_bytes());
+        for mac_address in &self.mac_addresses {
+            bytes.extend_from_slice(&mac_address.bytes());
+        }
+        bytes
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UdpSocket};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Binds `[::]:port` accepting both v4 and v6 traffic, falls back to `0.0.0.0:port` on hosts without IPv6
+pub fn bind_udp(port: u16) -> io::Result<UdpSocket> {
+    let socket = match bind_v6(Type::DGRAM, Protocol::UDP, port) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("Could not bind dual stack udp socket, falling back to v4: {e:?}");
+            bind_v4(Type::DGRAM, Protocol::UDP, port)?
+        }
+    };
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Same as [`bind_udp`] but only receiving what arrives on `interface`, several interfaces can
+/// share the port this way
+#[cfg(target_os = "linux")]
+pub fn bind_udp_on_interface(port: u16, interface: &str) -> io::Result<UdpSocket> {
+    let bind = |domain: Domain, ip: IpAddr| -> io::Result<Socket> {
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        if domain == Domain::IPV6 {
+            socket.set_only_v6(false)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.bind_device(Some(interface.as_bytes()))?;
+        socket.bind(&SocketAddr::new(ip, port).into())?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    };
+
+    let socket = match bind(Domain::IPV6, Ipv6Addr::UNSPECIFIED.into()) {
+        Ok(socket) => socket,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return Err(e),
+        Err(e) => {
+            println!(
+                "Could not bind dual stack udp socket on {interface}, falling back to v4: {e:?}"
+            );
+            bind(Domain::IPV4, Ipv4Addr::UNSPECIFIED.into())?
+        }
+    };
+
+    UdpSocket::from_std(socket.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_udp_on_interface(_port: u16, _interface: &str) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is only supported on linux",
+    ))
+}
+
+/// Same as [`bind_udp`] but for a listening tcp socket
+pub fn bind_tcp(port: u16) -> io::Result<TcpListener> {
+    let socket = match bind_v6(Type::STREAM, Protocol::TCP, port) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("Could not bind dual stack tcp socket, falling back to v4: {e:?}");
+            bind_v4(Type::STREAM, Protocol::TCP, port)?
+        }
+    };
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+fn bind_v6(ty: Type, protocol: Protocol, port: u16) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV6, ty, Some(protocol))?;
+    socket.set_only_v6(false)?;
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+fn bind_v4(ty: Type, protocol: Protocol, port: u16) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV4, ty, Some(protocol))?;
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
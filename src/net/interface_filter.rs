@@ -0,0 +1,172 @@
+use std::{error::Error, io, net::IpAddr, net::SocketAddr};
+
+use ipnetwork::IpNetwork;
+use mac_address::MacAddress;
+use network_interface::V4IfAddr;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    interface_watch::InterfaceWatch,
+    interfaces::{InterfaceFlags, V6Interface},
+    network::{DatagramTransport, Interfaces},
+};
+
+/// Which interfaces magic packets and discovery may use. Everything is allowed by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InterfaceFilter {
+    /// Name globs like `eth*` or `enp?s0`, when given an interface has to match one of them
+    pub include: Vec<String>,
+    /// Name globs, e.g. `docker*` or `virbr*`
+    pub exclude: Vec<String>,
+
+    /// When given, only addresses inside one of these networks are used
+    pub include_networks: Vec<IpNetwork>,
+    pub exclude_networks: Vec<IpNetwork>,
+
+    pub require_up: bool,
+    pub require_running: bool,
+    /// Skip bridges, veth pairs, tunnels and other interfaces without a physical device
+    pub exclude_virtual: bool,
+}
+
+impl InterfaceFilter {
+    /// `flags` of `None` means they are unknown, which never excludes an interface
+    pub fn allows_interface(&self, name: &str, flags: Option<InterfaceFlags>) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|glob| glob_matches(glob, name));
+        let excluded = self.exclude.iter().any(|glob| glob_matches(glob, name));
+        if !included || excluded {
+            return false;
+        }
+
+        let Some(flags) = flags else {
+            return true;
+        };
+        !(self.require_up && !flags.up
+            || self.require_running && !flags.running
+            || self.exclude_virtual && flags.is_virtual)
+    }
+
+    pub fn allows_ip(&self, ip: IpAddr) -> bool {
+        let included = self.include_networks.is_empty()
+            || self
+                .include_networks
+                .iter()
+                .any(|network| network.contains(ip));
+        let excluded = self
+            .exclude_networks
+            .iter()
+            .any(|network| network.contains(ip));
+
+        included && !excluded
+    }
+}
+
+/// `*` matches any run of characters, `?` a single one
+fn glob_matches(glob: &str, name: &str) -> bool {
+    fn matches(glob: &[char], name: &[char]) -> bool {
+        match glob.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+            Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+        }
+    }
+
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&glob, &name)
+}
+
+/// A network that only shows the interfaces allowed by `filter`
+#[derive(Debug, Clone)]
+pub struct FilteredNetwork<N> {
+    pub network: N,
+    pub filter: InterfaceFilter,
+}
+
+impl<N: Interfaces> Interfaces for FilteredNetwork<N> {
+    fn v4_interfaces(&self) -> Result<Vec<(String, V4IfAddr)>, Box<dyn Error>> {
+        let interfaces = self.network.v4_interfaces()?;
+        Ok(interfaces
+            .into_iter()
+            .filter(|(name, addr)| {
+                self.filter.allows_interface(name, self.network.flags(name))
+                    && self.filter.allows_ip(addr.ip.into())
+            })
+            .collect())
+    }
+
+    fn v6_interfaces(&self) -> Result<Vec<V6Interface>, Box<dyn Error>> {
+        let interfaces = self.network.v6_interfaces()?;
+        Ok(interfaces
+            .into_iter()
+            .filter(|interface| {
+                self.filter
+                    .allows_interface(&interface.name, self.network.flags(&interface.name))
+                    && self.filter.allows_ip(interface.addr.ip.into())
+            })
+            .collect())
+    }
+
+    fn mac_addresses(&self) -> Result<Vec<MacAddress>, Box<dyn Error>> {
+        self.network.mac_addresses()
+    }
+
+    /// Frames carry no ip, with network rules an interface needs one allowed address
+    fn ethernet_interfaces(&self) -> Result<Vec<(String, MacAddress)>, Box<dyn Error>> {
+        let has_network_rules =
+            !self.filter.include_networks.is_empty() || !self.filter.exclude_networks.is_empty();
+        let allowed_names: Vec<String> = match has_network_rules {
+            true => {
+                let v4 = self.v4_interfaces()?.into_iter().map(|(name, _)| name);
+                let v6 = self
+                    .v6_interfaces()?
+                    .into_iter()
+                    .map(|interface| interface.name);
+                v4.chain(v6).collect()
+            }
+            false => vec![],
+        };
+
+        let interfaces = self.network.ethernet_interfaces()?;
+        Ok(interfaces
+            .into_iter()
+            .filter(|(name, _)| {
+                self.filter.allows_interface(name, self.network.flags(name))
+                    && (!has_network_rules || allowed_names.contains(name))
+            })
+            .collect())
+    }
+
+    fn flags(&self, name: &str) -> Option<InterfaceFlags> {
+        self.network.flags(name)
+    }
+
+    fn watch(&self) -> InterfaceWatch {
+        self.network.watch()
+    }
+}
+
+impl<N: Interfaces + DatagramTransport + Sync> DatagramTransport for FilteredNetwork<N> {
+    type Socket = N::Socket;
+
+    async fn bind(&self, addr: SocketAddr) -> io::Result<N::Socket> {
+        self.network.bind(addr).await
+    }
+
+    fn bind_dual_stack(&self, port: u16) -> io::Result<N::Socket> {
+        self.network.bind_dual_stack(port)
+    }
+
+    fn bind_on_interface(&self, port: u16, interface: &str) -> io::Result<N::Socket> {
+        if !self
+            .filter
+            .allows_interface(interface, self.network.flags(interface))
+        {
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        }
+        self.network.bind_on_interface(port, interface)
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use network_interface::V4IfAddr;
+use tokio::{select, sync::watch};
+
+use super::{interfaces::V6Interface, network::Interfaces};
+
+/// How often interfaces are compared when change notifications are not available
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Catches anything netlink missed, e.g. after the socket buffer overflowed
+const NETLINK_BACKSTOP_INTERVAL: Duration = Duration::from_secs(60);
+/// DHCP and VPN clients usually change several things in a row
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// The addresses discovery depends on, compared to notice changes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceSnapshot {
+    pub v4: Vec<(String, V4IfAddr)>,
+    pub v6: Vec<V6Interface>,
+}
+
+impl InterfaceSnapshot {
+    /// Interfaces that can not be enumerated count as gone
+    pub fn of<N: Interfaces>(network: &N) -> InterfaceSnapshot {
+        InterfaceSnapshot {
+            v4: network.v4_interfaces().unwrap_or_default(),
+            v6: network.v6_interfaces().unwrap_or_default(),
+        }
+    }
+}
+
+/// Wakes up when the interfaces of a machine may have changed
+pub struct InterfaceWatch {
+    source: Source,
+}
+
+enum Source {
+    Polling(Duration),
+    #[cfg(target_os = "linux")]
+    Netlink(netlink::Monitor),
+    Watched(watch::Receiver<()>),
+}
+
+impl InterfaceWatch {
+    pub fn polling(interval: Duration) -> InterfaceWatch {
+        InterfaceWatch {
+            source: Source::Polling(interval),
+        }
+    }
+
+    /// Woken whenever a value is sent on the channel
+    pub fn watched(changes: watch::Receiver<()>) -> InterfaceWatch {
+        InterfaceWatch {
+            source: Source::Watched(changes),
+        }
+    }
+
+    /// Link and address notifications from netlink on linux, polling everywhere else
+    pub fn system() -> InterfaceWatch {
+        #[cfg(target_os = "linux")]
+        match netlink::Monitor::open() {
+            Ok(monitor) => {
+                return InterfaceWatch {
+                    source: Source::Netlink(monitor),
+                }
+            }
+            Err(e) => println!("Could not watch netlink, polling interfaces instead: {e:?}"),
+        }
+
+        InterfaceWatch::polling(DEFAULT_POLL_INTERVAL)
+    }
+
+    /// Resolves when the interfaces may have changed, compare [`InterfaceSnapshot`]s to be sure
+    pub async fn changed(&mut self) {
+        match &mut self.source {
+            Source::Polling(interval) => tokio::time::sleep(*interval).await,
+            #[cfg(target_os = "linux")]
+            Source::Netlink(monitor) => {
+                let received = select! {
+                    received = monitor.changed() => received,
+                    _ = tokio::time::sleep(NETLINK_BACKSTOP_INTERVAL) => Ok(()),
+                };
+                match received {
+                    Ok(()) => tokio::time::sleep(SETTLE_TIME).await,
+                    Err(e) => {
+                        println!("Netlink watch failed, polling interfaces instead: {e:?}");
+                        self.source = Source::Polling(DEFAULT_POLL_INTERVAL);
+                    }
+                }
+            }
+            Source::Watched(changes) => {
+                if changes.changed().await.is_err() {
+                    // Nobody can change the interfaces anymore
+                    std::future::pending::<()>().await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::{
+        io, mem,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    use tokio::io::unix::AsyncFd;
+
+    const RECEIVE_BUFFER_SIZE: usize = 8192;
+
+    pub struct Monitor {
+        fd: AsyncFd<OwnedFd>,
+    }
+
+    impl Monitor {
+        /// Subscribes to link and address changes of every interface
+        pub fn open() -> io::Result<Monitor> {
+            // SAFETY: plain socket creation, the descriptor is owned by `OwnedFd` from here on
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_ROUTE,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: `fd` was just created and is not owned by anything else
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            // SAFETY: sockaddr_nl is plain data, all zeroes is a valid value
+            let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups =
+                (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+            // SAFETY: `address` outlives the call and its length is passed along
+            let bound = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if bound < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Monitor {
+                fd: AsyncFd::new(fd)?,
+            })
+        }
+
+        /// Resolves once any message arrived, the messages themselves are not parsed
+        pub async fn changed(&mut self) -> io::Result<()> {
+            let mut buf = [0u8; RECEIVE_BUFFER_SIZE];
+            loop {
+                let mut guard = self.fd.readable().await?;
+                let mut received = false;
+                loop {
+                    // SAFETY: `buf` outlives the call and its length is passed along
+                    let len = unsafe {
+                        libc::recv(
+                            self.fd.as_raw_fd(),
+                            buf.as_mut_ptr() as *mut libc::c_void,
+                            buf.len(),
+                            0,
+                        )
+                    };
+                    if len >= 0 {
+                        received = true;
+                        continue;
+                    }
+
+                    let e = io::Error::last_os_error();
+                    match e.kind() {
+                        io::ErrorKind::WouldBlock => break,
+                        // The kernel dropped messages, something changed for sure
+                        _ if e.raw_os_error() == Some(libc::ENOBUFS) => received = true,
+                        _ => return Err(e),
+                    }
+                }
+
+                guard.clear_ready();
+                if received {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
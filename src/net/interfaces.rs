@@ -1,26 +1,117 @@
 use std::error::Error;
 
+use mac_address::MacAddress;
 use network_interface::{NetworkInterface, NetworkInterfaceConfig};
 
-pub fn get_broadcastable_v4_interfaces() -> Result<Vec<network_interface::V4IfAddr>, Box<dyn Error>>
-{
-    let mut cast_to_networks = vec![];
-    let ifs = NetworkInterface::show()?;
-    for nif in ifs {
+/// Non-loopback v4 addresses together with the name of their interface
+pub fn get_named_v4_interfaces(
+) -> Result<Vec<(String, network_interface::V4IfAddr)>, Box<dyn Error>> {
+    let mut interfaces = vec![];
+    for nif in NetworkInterface::show()? {
         for addr in &nif.addr {
-            match addr {
-                network_interface::Addr::V4(v4_addr) => {
-                    if v4_addr.ip.is_loopback() || v4_addr.broadcast.is_none() {
-                        continue;
-                    }
+            if let network_interface::Addr::V4(v4_addr) = addr {
+                if !v4_addr.ip.is_loopback() {
+                    interfaces.push((nif.name.clone(), *v4_addr));
+                }
+            }
+        }
+    }
+
+    Ok(interfaces)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V6Interface {
+    pub name: String,
+    pub index: u32,
+    pub addr: network_interface::V6IfAddr,
+}
 
-                    cast_to_networks.push(v4_addr.clone());
+/// One entry per non-loopback interface with a v6 address, `index` is the scope id used for
+/// link-local multicast
+pub fn get_multicast_v6_interfaces() -> Result<Vec<V6Interface>, Box<dyn Error>> {
+    let mut interfaces: Vec<V6Interface> = vec![];
+    for nif in NetworkInterface::show()? {
+        for addr in &nif.addr {
+            if let network_interface::Addr::V6(v6_addr) = addr {
+                if v6_addr.ip.is_loopback()
+                    || interfaces
+                        .iter()
+                        .any(|interface| interface.index == nif.index)
+                {
+                    continue;
                 }
-                _ => {}
+
+                interfaces.push(V6Interface {
+                    name: nif.name.clone(),
+                    index: nif.index,
+                    addr: *v6_addr,
+                });
             }
         }
-        println!("{:?}", nif.addr);
     }
 
-    Ok(cast_to_networks)
+    Ok(interfaces)
+}
+
+pub fn get_local_mac_addresses() -> Result<Vec<MacAddress>, Box<dyn Error>> {
+    let mut mac_addresses = vec![];
+    for nif in NetworkInterface::show()? {
+        let Some(mac_address) = nif.mac_addr.and_then(|mac| mac.parse::<MacAddress>().ok()) else {
+            continue;
+        };
+
+        // Loopback and some virtual interfaces report an all zero address
+        if mac_address.bytes() == [0; 6] || mac_addresses.contains(&mac_address) {
+            continue;
+        }
+
+        mac_addresses.push(mac_address);
+    }
+
+    Ok(mac_addresses)
+}
+
+/// Interfaces with a mac address together with it, loopback and tunnels have none
+pub fn get_ethernet_interfaces() -> Result<Vec<(String, MacAddress)>, Box<dyn Error>> {
+    let mut interfaces = vec![];
+    for nif in NetworkInterface::show()? {
+        let Some(mac_address) = nif
+            .mac_addr
+            .and_then(|mac| mac.parse::<MacAddress>().ok())
+            .filter(|mac| mac.bytes() != [0; 6])
+        else {
+            continue;
+        };
+
+        if !interfaces.iter().any(|(name, _)| *name == nif.name) {
+            interfaces.push((nif.name, mac_address));
+        }
+    }
+
+    Ok(interfaces)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceFlags {
+    pub up: bool,
+    /// Up with a carrier
+    pub running: bool,
+    /// Bridges, veth pairs, tunnels and other interfaces without a physical device
+    pub is_virtual: bool,
+}
+
+const IFF_UP: u32 = 0x1;
+const IFF_RUNNING: u32 = 0x40;
+
+/// Read from sysfs, `None` where that is not available
+pub fn get_interface_flags(name: &str) -> Option<InterfaceFlags> {
+    let flags = std::fs::read_to_string(format!("/sys/class/net/{name}/flags")).ok()?;
+    let flags = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok()?;
+
+    Some(InterfaceFlags {
+        up: flags & IFF_UP != 0,
+        running: flags & IFF_RUNNING != 0,
+        is_virtual: std::path::Path::new(&format!("/sys/devices/virtual/net/{name}")).exists(),
+    })
 }
@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::{IpAddr, SocketAddr, SocketAddrV6},
+    time::Duration,
+};
+
+use futures::{stream, StreamExt};
+use mac_address::MacAddress;
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+
+pub const SERVICE_TYPE: &str = "_wake-runner._tcp.local.";
+
+const VERSION_PROPERTY: &str = "version";
+const MAC_PROPERTY: &str = "mac";
+const TLS_FINGERPRINT_PROPERTY: &str = "tls_fingerprint";
+
+#[derive(Debug, Clone)]
+pub enum MdnsQuery {
+    /// Either the instance name or the host name, with or without the `.local` suffix
+    Name(String),
+    Mac(MacAddress),
+}
+
+impl MdnsQuery {
+    fn matches(&self, info: &ServiceInfo) -> bool {
+        match self {
+            MdnsQuery::Name(name) => {
+                let name = name.trim_end_matches('.').trim_end_matches(".local");
+                let instance_name = info
+                    .get_fullname()
+                    .strip_suffix(SERVICE_TYPE)
+                    .unwrap_or_default()
+                    .trim_end_matches('.');
+                let host_name = info
+                    .get_hostname()
+                    .trim_end_matches('.')
+                    .trim_end_matches(".local");
+
+                instance_name.eq_ignore_ascii_case(name) || host_name.eq_ignore_ascii_case(name)
+            }
+            MdnsQuery::Mac(mac_address) => info
+                .get_property_val_str(MAC_PROPERTY)
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|mac| mac.parse::<MacAddress>().ok())
+                .any(|mac| mac == *mac_address),
+        }
+    }
+}
+
+/// Advertises the runner as `_wake-runner._tcp.local`, the returned daemon has to be kept alive.
+/// `tls_fingerprint` is only published when configured.
+pub fn advertise(
+    port: u16,
+    mac_addresses: &[MacAddress],
+    tls_fingerprint: Option<&str>,
+) -> Result<ServiceDaemon, Box<dyn Error>> {
+    let daemon = ServiceDaemon::new()?;
+    let instance_name = local_hostname();
+    let host_name = format!("{instance_name}.local.");
+
+    let mac_addresses = mac_addresses
+        .iter()
+        .map(|mac| mac.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut properties = HashMap::from([
+        (
+            VERSION_PROPERTY.to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        (MAC_PROPERTY.to_string(), mac_addresses),
+    ]);
+    if let Some(tls_fingerprint) = tls_fingerprint {
+        properties.insert(
+            TLS_FINGERPRINT_PROPERTY.to_string(),
+            tls_fingerprint.to_string(),
+        );
+    }
+
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance_name,
+        &host_name,
+        "",
+        port,
+        properties,
+    )?
+    .enable_addr_auto();
+
+    println!("Advertising {:?} on mDNS", service.get_fullname());
+    daemon.register(service)?;
+    Ok(daemon)
+}
+
+pub async fn resolve(
+    query: &MdnsQuery,
+    timeout_duration: Duration,
+) -> Result<Option<SocketAddr>, Box<dyn Error>> {
+    // One daemon per interface, so link-local answers can be given the scope id of the
+    // interface they arrived on
+    let mut daemons = vec![];
+    let mut events = vec![];
+    for (name, index) in browsable_interfaces()? {
+        let daemon = ServiceDaemon::new()?;
+        daemon.disable_interface(IfKind::All)?;
+        daemon.enable_interface(IfKind::Name(name))?;
+        let receiver = daemon.browse(SERVICE_TYPE)?;
+        events.push(receiver.into_stream().map(move |event| (index, event)));
+        daemons.push(daemon);
+    }
+    let mut events = stream::select_all(events);
+
+    let find_service = async {
+        while let Some((index, event)) = events.next().await {
+            let ServiceEvent::ServiceResolved(info) = event else {
+                continue;
+            };
+
+            if !query.matches(&info) {
+                continue;
+            }
+
+            let mut addresses = info.get_addresses().iter().collect::<Vec<_>>();
+            // Link-local addresses only work through the interface they were seen on, the
+            // others are preferred. Among those v4 first, the same address broadcast discovery
+            // finds, so the address cache does not flip between the two
+            addresses.sort_by_key(|addr| (is_link_local(addr), !addr.is_ipv4()));
+            if let Some(addr) = addresses.first() {
+                return Some(scoped(**addr, info.get_port(), index));
+            }
+        }
+        None
+    };
+
+    let result = tokio::time::timeout(timeout_duration, find_service)
+        .await
+        .unwrap_or_default();
+
+    for daemon in daemons {
+        let _ = daemon.shutdown();
+    }
+    Ok(result)
+}
+
+/// Name and index of every interface with an address, besides loopback
+fn browsable_interfaces() -> Result<Vec<(String, u32)>, Box<dyn Error>> {
+    let mut interfaces: Vec<(String, u32)> = vec![];
+    for nif in NetworkInterface::show()? {
+        let is_loopback = nif.addr.iter().all(|addr| addr.ip().is_loopback());
+        if is_loopback || interfaces.iter().any(|(name, _)| *name == nif.name) {
+            continue;
+        }
+        interfaces.push((nif.name, nif.index));
+    }
+
+    Ok(interfaces)
+}
+
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => v6.is_unicast_link_local(),
+    }
+}
+
+/// Link-local v6 addresses are only usable together with the interface they were seen on
+fn scoped(ip: IpAddr, port: u16, interface_index: u32) -> SocketAddr {
+    match ip {
+        IpAddr::V6(v6) if v6.is_unicast_link_local() => {
+            SocketAddrV6::new(v6, port, 0, interface_index).into()
+        }
+        ip => SocketAddr::new(ip, port),
+    }
+}
+
+fn local_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_string())
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "wake-runner".to_string())
+}
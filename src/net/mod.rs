@@ -1,2 +1,11 @@
+pub mod discovery;
+pub mod dual_stack;
+pub mod interface_filter;
+pub mod interface_watch;
 pub mod interfaces;
+pub mod mdns;
+pub mod network;
+#[cfg(any(test, feature = "simulated"))]
+pub mod simulated;
+pub mod sniffer;
 pub mod wake_on_lan;
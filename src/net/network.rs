@@ -0,0 +1,148 @@
+use std::{
+    error::Error,
+    future::Future,
+    io,
+    net::{Ipv6Addr, SocketAddr},
+};
+
+use mac_address::MacAddress;
+use network_interface::V4IfAddr;
+use tokio::net::UdpSocket;
+
+use super::{
+    dual_stack,
+    interface_watch::{InterfaceWatch, DEFAULT_POLL_INTERVAL},
+    interfaces::{
+        get_ethernet_interfaces, get_interface_flags, get_local_mac_addresses,
+        get_multicast_v6_interfaces, get_named_v4_interfaces, InterfaceFlags, V6Interface,
+    },
+};
+
+/// Where discovery and wake-on-lan find the interfaces of a machine
+pub trait Interfaces {
+    /// Non-loopback v4 addresses together with the name of their interface
+    fn v4_interfaces(&self) -> Result<Vec<(String, V4IfAddr)>, Box<dyn Error>>;
+
+    fn v6_interfaces(&self) -> Result<Vec<V6Interface>, Box<dyn Error>>;
+
+    fn mac_addresses(&self) -> Result<Vec<MacAddress>, Box<dyn Error>>;
+
+    /// Interfaces raw ethernet frames can be sent on, with the mac address they are sent from
+    fn ethernet_interfaces(&self) -> Result<Vec<(String, MacAddress)>, Box<dyn Error>>;
+
+    /// `None` when the flags of the interface can not be read
+    fn flags(&self, _name: &str) -> Option<InterfaceFlags> {
+        None
+    }
+
+    /// Notices when the interfaces returned above may have changed
+    fn watch(&self) -> InterfaceWatch {
+        InterfaceWatch::polling(DEFAULT_POLL_INTERVAL)
+    }
+}
+
+pub trait DatagramSocket: Send + Sync + 'static {
+    fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send;
+
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    fn set_broadcast(&self, on: bool) -> io::Result<()>;
+
+    fn join_multicast_v6(&self, group: &Ipv6Addr, interface: u32) -> io::Result<()>;
+}
+
+/// How discovery and wake-on-lan open udp sockets
+pub trait DatagramTransport {
+    type Socket: DatagramSocket;
+
+    fn bind(&self, addr: SocketAddr) -> impl Future<Output = io::Result<Self::Socket>> + Send;
+
+    /// Binds `port` for both v4 and v6 where the machine supports it
+    fn bind_dual_stack(&self, port: u16) -> io::Result<Self::Socket>;
+
+    /// Same as [`DatagramTransport::bind_dual_stack`] but only receiving from `interface`,
+    /// `Unsupported` or `PermissionDenied` where that is not possible
+    fn bind_on_interface(&self, port: u16, interface: &str) -> io::Result<Self::Socket>;
+}
+
+/// Everything discovery and wake-on-lan need from the machine they run on
+pub trait Network: Interfaces + DatagramTransport + Send + Sync {}
+
+impl<N: Interfaces + DatagramTransport + Send + Sync> Network for N {}
+
+/// The interfaces and sockets of this machine
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemNetwork;
+
+impl Interfaces for SystemNetwork {
+    fn v4_interfaces(&self) -> Result<Vec<(String, V4IfAddr)>, Box<dyn Error>> {
+        get_named_v4_interfaces()
+    }
+
+    fn v6_interfaces(&self) -> Result<Vec<V6Interface>, Box<dyn Error>> {
+        get_multicast_v6_interfaces()
+    }
+
+    fn mac_addresses(&self) -> Result<Vec<MacAddress>, Box<dyn Error>> {
+        get_local_mac_addresses()
+    }
+
+    fn ethernet_interfaces(&self) -> Result<Vec<(String, MacAddress)>, Box<dyn Error>> {
+        get_ethernet_interfaces()
+    }
+
+    fn flags(&self, name: &str) -> Option<InterfaceFlags> {
+        get_interface_flags(name)
+    }
+
+    fn watch(&self) -> InterfaceWatch {
+        InterfaceWatch::system()
+    }
+}
+
+impl DatagramTransport for SystemNetwork {
+    type Socket = UdpSocket;
+
+    async fn bind(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        UdpSocket::bind(addr).await
+    }
+
+    fn bind_dual_stack(&self, port: u16) -> io::Result<UdpSocket> {
+        dual_stack::bind_udp(port)
+    }
+
+    fn bind_on_interface(&self, port: u16, interface: &str) -> io::Result<UdpSocket> {
+        dual_stack::bind_udp_on_interface(port, interface)
+    }
+}
+
+impl DatagramSocket for UdpSocket {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, target).await
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf).await
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn set_broadcast(&self, on: bool) -> io::Result<()> {
+        UdpSocket::set_broadcast(self, on)
+    }
+
+    fn join_multicast_v6(&self, group: &Ipv6Addr, interface: u32) -> io::Result<()> {
+        UdpSocket::join_multicast_v6(self, group, interface)
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    error::Error,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use ipnetwork::Ipv4Network;
+use mac_address::MacAddress;
+use network_interface::V4IfAddr;
+use tokio::{
+    sync::{mpsc, watch, Mutex as AsyncMutex},
+    time::Instant,
+};
+
+use super::{
+    interface_watch::InterfaceWatch,
+    interfaces::{InterfaceFlags, V6Interface},
+    network::{DatagramSocket, DatagramTransport, Interfaces},
+    wake_on_lan::MagicPacket,
+};
+
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+const INTERFACE_NAME: &str = "eth0";
+
+/// A datagram on its way through a [`SimulatedLan`]
+#[derive(Debug)]
+pub struct Datagram<'a> {
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    pub payload: &'a [u8],
+}
+
+type Filter = Box<dyn FnMut(&Datagram) -> bool + Send>;
+
+/// An in-memory v4 subnet for testing discovery and wake-on-lan without real sockets. Time is
+/// taken from tokio, so tests can run it on a paused clock
+#[derive(Clone)]
+pub struct SimulatedLan {
+    state: Arc<Mutex<LanState>>,
+}
+
+struct LanState {
+    network: Ipv4Network,
+    hosts: Vec<HostState>,
+    sockets: Vec<SocketEntry>,
+    next_socket_id: usize,
+    next_port: u16,
+    filter: Option<Filter>,
+}
+
+struct HostState {
+    ip: Ipv4Addr,
+    mac_address: MacAddress,
+    power: Power,
+    changes: watch::Sender<()>,
+}
+
+enum Power {
+    Up,
+    /// Boots after this long once a magic packet for the host arrives
+    Sleeping(Duration),
+    Booting(Instant),
+}
+
+impl HostState {
+    fn is_up(&self) -> bool {
+        match self.power {
+            Power::Up => true,
+            Power::Sleeping(_) => false,
+            Power::Booting(up_at) => Instant::now() >= up_at,
+        }
+    }
+}
+
+struct SocketEntry {
+    id: usize,
+    host: usize,
+    addr: SocketAddrV4,
+    sender: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+}
+
+impl SimulatedLan {
+    pub fn new(network: Ipv4Network) -> SimulatedLan {
+        SimulatedLan {
+            state: Arc::new(Mutex::new(LanState {
+                network,
+                hosts: vec![],
+                sockets: vec![],
+                next_socket_id: 0,
+                next_port: FIRST_EPHEMERAL_PORT,
+                filter: None,
+            })),
+        }
+    }
+
+    /// A host that is up from the start, addresses are handed out in order from the subnet
+    pub fn add_host(&self, mac_address: MacAddress) -> SimulatedHost {
+        self.add(mac_address, Power::Up)
+    }
+
+    /// A host that stays down until a magic packet for it arrives, then takes `boot_time` to come
+    /// up. Datagrams to or from a host that is down are lost
+    pub fn add_sleeping_host(&self, mac_address: MacAddress, boot_time: Duration) -> SimulatedHost {
+        self.add(mac_address, Power::Sleeping(boot_time))
+    }
+
+    fn add(&self, mac_address: MacAddress, power: Power) -> SimulatedHost {
+        let mut state = self.state.lock().unwrap();
+        let index = state.hosts.len();
+        let ip = state
+            .network
+            .nth(index as u32 + 1)
+            .expect("subnet is out of addresses");
+        state.hosts.push(HostState {
+            ip,
+            mac_address,
+            power,
+            changes: watch::channel(()).0,
+        });
+
+        SimulatedHost {
+            lan: self.clone(),
+            index,
+        }
+    }
+
+    /// Only datagrams the filter returns `true` for are delivered, e.g. to simulate packet loss
+    pub fn set_filter(&self, filter: impl FnMut(&Datagram) -> bool + Send + 'static) {
+        self.state.lock().unwrap().filter = Some(Box::new(filter));
+    }
+}
+
+impl LanState {
+    fn deliver(&mut self, host: usize, from: SocketAddrV4, to: SocketAddr, payload: &[u8]) {
+        let SocketAddr::V4(to) = to else {
+            return;
+        };
+        if !self.hosts[host].is_up() {
+            return;
+        }
+
+        let datagram = Datagram {
+            from: from.into(),
+            to: to.into(),
+            payload,
+        };
+        if let Some(filter) = &mut self.filter {
+            if !filter(&datagram) {
+                return;
+            }
+        }
+
+        let is_broadcast = *to.ip() == Ipv4Addr::BROADCAST || *to.ip() == self.network.broadcast();
+        let recipients: Vec<usize> = (0..self.hosts.len())
+            .filter(|index| is_broadcast || self.hosts[*index].ip == *to.ip())
+            .collect();
+
+        // NICs pick magic packets out of any frame, whatever the port
+        if let Ok(magic_packet) = MagicPacket::parse(payload) {
+            for index in &recipients {
+                let host = &mut self.hosts[*index];
+                if let Power::Sleeping(boot_time) = host.power {
+                    if host.mac_address.bytes() == magic_packet.mac_address() {
+                        host.power = Power::Booting(Instant::now() + boot_time);
+                    }
+                }
+            }
+        }
+
+        for socket in &self.sockets {
+            let host = &self.hosts[socket.host];
+            let bound_ip = *socket.addr.ip();
+            let accepts = socket.addr.port() == to.port()
+                && recipients.contains(&socket.host)
+                && host.is_up()
+                // Sockets bound to a specific address do not see broadcasts
+                && (bound_ip.is_unspecified() || (!is_broadcast && bound_ip == host.ip));
+            if accepts {
+                let _ = socket.sender.send((payload.to_vec(), from.into()));
+            }
+        }
+    }
+}
+
+/// One machine on a [`SimulatedLan`], with a single `eth0` interface
+#[derive(Clone)]
+pub struct SimulatedHost {
+    lan: SimulatedLan,
+    index: usize,
+}
+
+impl SimulatedHost {
+    pub fn ip(&self) -> Ipv4Addr {
+        self.lan.state.lock().unwrap().hosts[self.index].ip
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.lan.state.lock().unwrap().hosts[self.index].is_up()
+    }
+
+    /// Moves the host to another address, like a DHCP renewal. Open sockets bound to the old
+    /// address stop receiving
+    pub fn set_ip(&self, ip: Ipv4Addr) {
+        let mut state = self.lan.state.lock().unwrap();
+        let host = &mut state.hosts[self.index];
+        host.ip = ip;
+        host.changes.send_replace(());
+    }
+
+    fn bind_now(&self, addr: SocketAddr) -> io::Result<SimulatedSocket> {
+        let SocketAddr::V4(addr) = addr else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the simulated lan is v4 only",
+            ));
+        };
+
+        let mut state = self.lan.state.lock().unwrap();
+        let ip = state.hosts[self.index].ip;
+        if !addr.ip().is_unspecified() && *addr.ip() != ip {
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        }
+
+        let port = match addr.port() {
+            0 => {
+                state.next_port = state
+                    .next_port
+                    .checked_add(1)
+                    .unwrap_or(FIRST_EPHEMERAL_PORT);
+                state.next_port
+            }
+            port => port,
+        };
+        let in_use = state
+            .sockets
+            .iter()
+            .any(|socket| socket.host == self.index && socket.addr.port() == port);
+        if in_use {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+
+        let id = state.next_socket_id;
+        state.next_socket_id += 1;
+        let addr = SocketAddrV4::new(*addr.ip(), port);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        state.sockets.push(SocketEntry {
+            id,
+            host: self.index,
+            addr,
+            sender,
+        });
+
+        Ok(SimulatedSocket {
+            host: self.clone(),
+            id,
+            addr,
+            broadcast: AtomicBool::new(false),
+            receiver: AsyncMutex::new(receiver),
+        })
+    }
+}
+
+impl Interfaces for SimulatedHost {
+    fn v4_interfaces(&self) -> Result<Vec<(String, V4IfAddr)>, Box<dyn Error>> {
+        let state = self.lan.state.lock().unwrap();
+        let addr = V4IfAddr {
+            ip: state.hosts[self.index].ip,
+            broadcast: Some(state.network.broadcast()),
+            netmask: Some(state.network.mask()),
+        };
+        Ok(vec![(INTERFACE_NAME.to_string(), addr)])
+    }
+
+    fn v6_interfaces(&self) -> Result<Vec<V6Interface>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+
+    fn mac_addresses(&self) -> Result<Vec<MacAddress>, Box<dyn Error>> {
+        Ok(vec![
+            self.lan.state.lock().unwrap().hosts[self.index].mac_address,
+        ])
+    }
+
+    fn ethernet_interfaces(&self) -> Result<Vec<(String, MacAddress)>, Box<dyn Error>> {
+        Ok(vec![(
+            INTERFACE_NAME.to_string(),
+            self.lan.state.lock().unwrap().hosts[self.index].mac_address,
+        )])
+    }
+
+    fn flags(&self, name: &str) -> Option<InterfaceFlags> {
+        let up = self.is_up();
+        (name == INTERFACE_NAME).then_some(InterfaceFlags {
+            up,
+            running: up,
+            is_virtual: false,
+        })
+    }
+
+    fn watch(&self) -> InterfaceWatch {
+        InterfaceWatch::watched(
+            self.lan.state.lock().unwrap().hosts[self.index]
+                .changes
+                .subscribe(),
+        )
+    }
+}
+
+impl DatagramTransport for SimulatedHost {
+    type Socket = SimulatedSocket;
+
+    async fn bind(&self, addr: SocketAddr) -> io::Result<SimulatedSocket> {
+        self.bind_now(addr)
+    }
+
+    fn bind_dual_stack(&self, port: u16) -> io::Result<SimulatedSocket> {
+        self.bind_now(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())
+    }
+
+    fn bind_on_interface(&self, port: u16, interface: &str) -> io::Result<SimulatedSocket> {
+        if interface != INTERFACE_NAME {
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        }
+        self.bind_dual_stack(port)
+    }
+}
+
+pub struct SimulatedSocket {
+    host: SimulatedHost,
+    id: usize,
+    addr: SocketAddrV4,
+    broadcast: AtomicBool,
+    receiver: AsyncMutex<mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>>,
+}
+
+impl DatagramSocket for SimulatedSocket {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let mut state = self.host.lan.state.lock().unwrap();
+        let is_broadcast = match target {
+            SocketAddr::V4(target) => {
+                *target.ip() == Ipv4Addr::BROADCAST || *target.ip() == state.network.broadcast()
+            }
+            SocketAddr::V6(_) => return Err(io::ErrorKind::NetworkUnreachable.into()),
+        };
+        if is_broadcast && !self.broadcast.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let ip = state.hosts[self.host.index].ip;
+        let from = SocketAddrV4::new(ip, self.addr.port());
+        state.deliver(self.host.index, from, target, buf);
+        Ok(buf.len())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (payload, from) = self
+            .receiver
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(io::ErrorKind::BrokenPipe)?;
+
+        // Like udp, whatever does not fit is cut off
+        let len = payload.len().min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+        Ok((len, from))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr.into())
+    }
+
+    fn set_broadcast(&self, on: bool) -> io::Result<()> {
+        self.broadcast.store(on, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn join_multicast_v6(&self, _group: &Ipv6Addr, _interface: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the simulated lan is v4 only",
+        ))
+    }
+}
+
+impl Drop for SimulatedSocket {
+    fn drop(&mut self) {
+        let mut state = self.host.lan.state.lock().unwrap();
+        state.sockets.retain(|socket| socket.id != self.id);
+    }
+}
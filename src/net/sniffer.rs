@@ -0,0 +1,275 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use mac_address::MacAddress;
+use tokio::{net::UdpSocket, task::JoinSet};
+
+use super::wake_on_lan::{InvalidMagicPacket, MagicPacket, ETHER_TYPE};
+
+pub const DEFAULT_PORTS: [u16; 3] = [0, 7, 9];
+
+const ETHERNET_HEADER_SIZE_BYTES: usize = 14;
+const VLAN_TAG_SIZE_BYTES: usize = 4;
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86DD;
+const IPV6_HEADER_SIZE_BYTES: usize = 40;
+const UDP_HEADER_SIZE_BYTES: usize = 8;
+const PROTOCOL_UDP: u8 = 17;
+
+/// Keeps a socket that keeps failing from spinning
+pub const RECEIVE_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketSource {
+    Udp { from: SocketAddr, port: u16 },
+    Ethernet { from: MacAddress },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SniffedPacket {
+    pub source: PacketSource,
+    pub magic_packet: Result<MagicPacket, InvalidMagicPacket>,
+}
+
+impl fmt::Display for SniffedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.magic_packet {
+            Ok(magic_packet) => {
+                write!(f, "{}", MacAddress::new(magic_packet.mac_address()))?;
+                if magic_packet.password().is_some() {
+                    write!(f, " (SecureOn)")?;
+                }
+            }
+            Err(e) => write!(f, "invalid magic packet, {e}")?,
+        }
+
+        match self.source {
+            PacketSource::Udp { from, port } => write!(f, " from {from} to udp port {port}"),
+            PacketSource::Ethernet { from } => write!(f, " from {from} as raw ethernet frame"),
+        }
+    }
+}
+
+/// Picks wake-on-lan traffic out of a captured ethernet frame, `None` for everything else
+pub fn inspect_frame(frame: &[u8], ports: &[u16]) -> Option<SniffedPacket> {
+    let source_mac: [u8; 6] = frame.get(6..12)?.try_into().ok()?;
+    let mut ether_type = read_u16(frame, 12)?;
+    let mut offset = ETHERNET_HEADER_SIZE_BYTES;
+    if ether_type == ETHER_TYPE_VLAN {
+        ether_type = read_u16(frame, offset + 2)?;
+        offset += VLAN_TAG_SIZE_BYTES;
+    }
+
+    let body = frame.get(offset..)?;
+    let (from, udp) = match ether_type {
+        ETHER_TYPE => {
+            return Some(SniffedPacket {
+                source: PacketSource::Ethernet {
+                    from: MacAddress::new(source_mac),
+                },
+                magic_packet: MagicPacket::parse(body),
+            })
+        }
+        ETHER_TYPE_IPV4 => {
+            let header_len = usize::from(body.first()? & 0x0F) * 4;
+            if *body.get(9)? != PROTOCOL_UDP {
+                return None;
+            }
+            let from: [u8; 4] = body.get(12..16)?.try_into().ok()?;
+            (IpAddr::from(Ipv4Addr::from(from)), body.get(header_len..)?)
+        }
+        ETHER_TYPE_IPV6 => {
+            if *body.get(6)? != PROTOCOL_UDP {
+                return None;
+            }
+            let from: [u8; 16] = body.get(8..24)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(from)),
+                body.get(IPV6_HEADER_SIZE_BYTES..)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = read_u16(udp, 0)?;
+    let port = read_u16(udp, 2)?;
+    if !ports.contains(&port) {
+        return None;
+    }
+
+    let udp_len = usize::from(read_u16(udp, 4)?).clamp(UDP_HEADER_SIZE_BYTES, udp.len());
+    Some(SniffedPacket {
+        source: PacketSource::Udp {
+            from: SocketAddr::new(from, source_port),
+            port,
+        },
+        magic_packet: MagicPacket::parse(&udp[UDP_HEADER_SIZE_BYTES..udp_len]),
+    })
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reports every magic packet reaching this machine until the process exits. Needs CAP_NET_RAW
+/// to see udp port 0 and raw ethernet frames, without it only udp sockets on the other ports are
+/// used
+pub async fn sniff(ports: Vec<u16>, report: fn(SniffedPacket)) -> io::Result<()> {
+    match raw::open_capture() {
+        Ok(capture) => tokio::task::spawn_blocking(move || capture.run(&ports, report)).await?,
+        Err(e) => {
+            println!("Could not open raw capture ({e}), falling back to udp sockets");
+            sniff_udp(ports, report).await
+        }
+    }
+}
+
+async fn sniff_udp(ports: Vec<u16>, report: fn(SniffedPacket)) -> io::Result<()> {
+    let mut listeners = JoinSet::new();
+    for port in ports {
+        // Binding port 0 would pick a random port instead
+        if port == 0 {
+            println!("Udp port 0 can only be observed with a raw capture");
+            continue;
+        }
+
+        // Ports below 1024 need privileges as well, the others are still worth watching
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("Not sniffing udp port {port}, could not bind it: {e}");
+                continue;
+            }
+        };
+        println!("Sniffing on {:?}", socket.local_addr()?);
+        listeners.spawn(async move {
+            let mut buf = Vec::with_capacity(1024);
+            loop {
+                buf.clear();
+                let (n_bytes, from) = match socket.recv_buf_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        println!("Receiving on udp port {port} failed: {e}");
+                        tokio::time::sleep(RECEIVE_ERROR_BACKOFF).await;
+                        continue;
+                    }
+                };
+
+                report(SniffedPacket {
+                    source: PacketSource::Udp { from, port },
+                    magic_packet: MagicPacket::parse(&buf[..n_bytes]),
+                });
+            }
+        });
+    }
+
+    if listeners.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "none of the ports could be bound, try running as root",
+        ));
+    }
+
+    while listeners.join_next().await.is_some() {}
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod raw {
+    use std::{io, mem};
+
+    use super::{inspect_frame, SniffedPacket};
+
+    const MAX_FRAME_SIZE_BYTES: usize = 65536;
+    /// From linux/if_packet.h, missing in libc
+    const PACKET_OUTGOING: u8 = 4;
+
+    pub struct Capture {
+        fd: libc::c_int,
+    }
+
+    pub fn open_capture() -> io::Result<Capture> {
+        // SAFETY: plain socket creation, the descriptor is owned by `Capture` from here on
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_RAW,
+                (libc::ETH_P_ALL as u16).to_be() as libc::c_int,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Capture { fd })
+    }
+
+    impl Capture {
+        pub fn run(&self, ports: &[u16], report: fn(SniffedPacket)) -> io::Result<()> {
+            println!("Sniffing raw frames for udp ports {ports:?} and EtherType 0x0842");
+            let mut frame = vec![0u8; MAX_FRAME_SIZE_BYTES];
+            loop {
+                // SAFETY: sockaddr_ll is plain data, all zeroes is a valid value
+                let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+                let mut address_len = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+                // SAFETY: `frame` and `address` outlive the call and their lengths are passed along
+                let len = unsafe {
+                    libc::recvfrom(
+                        self.fd,
+                        frame.as_mut_ptr() as *mut libc::c_void,
+                        frame.len(),
+                        0,
+                        &mut address as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                        &mut address_len,
+                    )
+                };
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                // Only report what arrived, loopback would otherwise show every packet twice
+                if address.sll_pkttype == PACKET_OUTGOING {
+                    continue;
+                }
+
+                if let Some(packet) = inspect_frame(&frame[..len as usize], ports) {
+                    report(packet);
+                }
+            }
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            // SAFETY: the descriptor is only closed once, here
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod raw {
+    use std::io;
+
+    use super::SniffedPacket;
+
+    pub struct Capture;
+
+    pub fn open_capture() -> io::Result<Capture> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "raw capture is only supported on linux",
+        ))
+    }
+
+    impl Capture {
+        pub fn run(&self, _ports: &[u16], _report: fn(SniffedPacket)) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
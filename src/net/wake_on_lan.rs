@@ -1,24 +1,445 @@
 use std::{
     error::Error,
-    io,
-    net::{Ipv4Addr, SocketAddrV4},
+    fmt, io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    str::FromStr,
 };
 
-use tokio::net::UdpSocket;
+use ipnetwork::Ipv4Network;
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
 
-use super::interfaces::get_broadcastable_v4_interfaces;
+use crate::os::arp;
+
+use super::network::{DatagramSocket, DatagramTransport, Network, SystemNetwork};
 
 const MAC_ADDRESS_SIZE: usize = 6;
-const MAGIC_PACKET_TOTAL_SIZE_BYTES: usize = 102;
+const MAGIC_PACKET_SIZE_BYTES: usize = 102;
 const HEADER_SIZE_BYTES: usize = 6;
 const TARGET_MAC_ADDRESS_REPETITIONS: usize = 16;
+const MAX_PASSWORD_SIZE_BYTES: usize = 6;
+const ETHERNET_HEADER_SIZE_BYTES: usize = 14;
 
-pub async fn send(mac_address: [u8; MAC_ADDRESS_SIZE]) -> Result<(), Box<dyn Error>> {
-    let interfaces = get_broadcastable_v4_interfaces()?;
-    for interface in interfaces {
-        send_from_to(mac_address, interface.ip, interface.broadcast.unwrap()).await?;
+/// Port most NICs listen on, 0 and 7 are also common
+pub const DEFAULT_PORT: u16 = 9;
+pub const ETHER_TYPE: u16 = 0x0842;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum SecureOnPassword {
+    Short([u8; 4]),
+    Long([u8; 6]),
+}
+
+impl SecureOnPassword {
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            SecureOnPassword::Short(bytes) => bytes,
+            SecureOnPassword::Long(bytes) => bytes,
+        }
     }
-    Ok(())
+}
+
+impl TryFrom<&[u8]> for SecureOnPassword {
+    type Error = InvalidSecureOnPassword;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if let Ok(short) = bytes.try_into() {
+            return Ok(SecureOnPassword::Short(short));
+        }
+
+        bytes
+            .try_into()
+            .map(SecureOnPassword::Long)
+            .map_err(|_| InvalidSecureOnPassword)
+    }
+}
+
+/// Accepts the two notations BIOSes use, `aa:bb:cc:dd:ee:ff` for 6 bytes and `192.168.1.1` for 4
+impl FromStr for SecureOnPassword {
+    type Err = InvalidSecureOnPassword;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse::<Ipv4Addr>() {
+            return Ok(SecureOnPassword::Short(ip.octets()));
+        }
+
+        let bytes = s
+            .split([':', '-'])
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| InvalidSecureOnPassword)?;
+
+        SecureOnPassword::try_from(bytes.as_slice())
+    }
+}
+
+impl TryFrom<String> for SecureOnPassword {
+    type Error = InvalidSecureOnPassword;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for SecureOnPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecureOnPassword::Short(bytes) => write!(f, "{}", Ipv4Addr::from(*bytes)),
+            SecureOnPassword::Long(bytes) => write!(f, "{}", MacAddress::new(*bytes)),
+        }
+    }
+}
+
+impl From<SecureOnPassword> for String {
+    fn from(password: SecureOnPassword) -> String {
+        password.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidSecureOnPassword;
+
+impl fmt::Display for InvalidSecureOnPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecureOn password has to be 4 or 6 bytes")
+    }
+}
+
+impl Error for InvalidSecureOnPassword {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidMagicPacket {
+    TooShort(usize),
+    InvalidHeader,
+    /// Index of the first of the 16 mac address repetitions that differs from the first one
+    MismatchedRepetition(usize),
+    InvalidPasswordLength(usize),
+}
+
+impl fmt::Display for InvalidMagicPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidMagicPacket::TooShort(len) => write!(f, "packet too short ({len} bytes)"),
+            InvalidMagicPacket::InvalidHeader => write!(f, "header is not 6 bytes of 0xFF"),
+            InvalidMagicPacket::MismatchedRepetition(repetition) => {
+                write!(f, "mac address repetition {repetition} differs")
+            }
+            InvalidMagicPacket::InvalidPasswordLength(len) => {
+                write!(
+                    f,
+                    "trailing {len} bytes are not a 4 or 6 byte SecureOn password"
+                )
+            }
+        }
+    }
+}
+
+impl Error for InvalidMagicPacket {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp {
+        port: u16,
+    },
+    /// Raw frame with EtherType 0x0842, for NICs that ignore udp
+    Ethernet,
+}
+
+/// Where a magic packet is sent, hosts on another subnet need one of the routed strategies
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum SendStrategy {
+    /// Limited broadcast on every local interface
+    #[default]
+    LocalBroadcast,
+    /// Limited broadcast on a single interface
+    Interface { name: String },
+    /// Subnet-directed broadcast, the routers in between have to forward it
+    DirectedBroadcast { network: Ipv4Network },
+    /// Unicast to the last known address of the host
+    Unicast {
+        ip: Ipv4Addr,
+        /// Add a static ARP entry first, only possible when `ip` is on a local subnet
+        #[serde(default)]
+        seed_arp: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MagicPacket {
+    mac_address: [u8; MAC_ADDRESS_SIZE],
+    password: Option<SecureOnPassword>,
+    transport: Transport,
+}
+
+impl MagicPacket {
+    pub fn new(mac_address: [u8; MAC_ADDRESS_SIZE]) -> MagicPacket {
+        Self {
+            mac_address,
+            password: None,
+            transport: Transport::Udp { port: DEFAULT_PORT },
+        }
+    }
+
+    pub fn with_password(mut self, password: SecureOnPassword) -> MagicPacket {
+        self.password = Some(password);
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> MagicPacket {
+        self.transport = Transport::Udp { port };
+        self
+    }
+
+    pub fn over_ethernet(mut self) -> MagicPacket {
+        self.transport = Transport::Ethernet;
+        self
+    }
+
+    /// Validates a udp payload (or ethernet frame body) as a magic packet, optionally followed
+    /// by a SecureOn password
+    pub fn parse(payload: &[u8]) -> Result<MagicPacket, InvalidMagicPacket> {
+        let (header, rest) = payload
+            .split_at_checked(HEADER_SIZE_BYTES)
+            .ok_or(InvalidMagicPacket::TooShort(payload.len()))?;
+        if header.iter().any(|byte| *byte != u8::MAX) {
+            return Err(InvalidMagicPacket::InvalidHeader);
+        }
+
+        let (repetitions, password) = rest
+            .split_at_checked(TARGET_MAC_ADDRESS_REPETITIONS * MAC_ADDRESS_SIZE)
+            .ok_or(InvalidMagicPacket::TooShort(payload.len()))?;
+        let mac_address: [u8; MAC_ADDRESS_SIZE] =
+            repetitions[..MAC_ADDRESS_SIZE].try_into().unwrap();
+
+        let mismatch = repetitions
+            .chunks(MAC_ADDRESS_SIZE)
+            .position(|repetition| repetition != mac_address);
+        if let Some(repetition) = mismatch {
+            return Err(InvalidMagicPacket::MismatchedRepetition(repetition));
+        }
+
+        let mut magic_packet = MagicPacket::new(mac_address);
+        if !password.is_empty() {
+            let password = SecureOnPassword::try_from(password)
+                .map_err(|_| InvalidMagicPacket::InvalidPasswordLength(password.len()))?;
+            magic_packet = magic_packet.with_password(password);
+        }
+
+        Ok(magic_packet)
+    }
+
+    pub fn password(&self) -> Option<SecureOnPassword> {
+        self.password
+    }
+
+    pub fn mac_address(&self) -> [u8; MAC_ADDRESS_SIZE] {
+        self.mac_address
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut magic_packet_content =
+            Vec::with_capacity(MAGIC_PACKET_SIZE_BYTES + MAX_PASSWORD_SIZE_BYTES);
+        // Fill 6 bytes of 0xFF
+        magic_packet_content.extend_from_slice(&[u8::MAX; HEADER_SIZE_BYTES]);
+
+        // Add 16 repetitions of the wakee:s mac address
+        for _ in 0..TARGET_MAC_ADDRESS_REPETITIONS {
+            magic_packet_content.extend_from_slice(&self.mac_address);
+        }
+
+        if let Some(password) = &self.password {
+            magic_packet_content.extend_from_slice(password.bytes());
+        }
+
+        magic_packet_content
+    }
+
+    /// Broadcast ethernet frame carrying the packet, `source` is the mac of the sending interface
+    pub fn to_ethernet_frame(&self, source: [u8; MAC_ADDRESS_SIZE]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(
+            ETHERNET_HEADER_SIZE_BYTES + MAGIC_PACKET_SIZE_BYTES + MAX_PASSWORD_SIZE_BYTES,
+        );
+        frame.extend_from_slice(&[u8::MAX; MAC_ADDRESS_SIZE]);
+        frame.extend_from_slice(&source);
+        frame.extend_from_slice(&ETHER_TYPE.to_be_bytes());
+        frame.extend_from_slice(&self.to_bytes());
+        frame
+    }
+
+    /// Sends the packet on every broadcastable interface
+    pub async fn send(&self) -> Result<(), Box<dyn Error>> {
+        self.send_on(&SystemNetwork).await
+    }
+
+    /// Broadcasts on every interface of `network`, fails only if no interface could send
+    pub async fn send_on<N: Network>(&self, network: &N) -> Result<(), Box<dyn Error>> {
+        let mut sent = SentOnInterfaces::default();
+        match self.transport {
+            Transport::Udp { .. } => {
+                let interfaces = network.v4_interfaces()?;
+                for (name, interface) in interfaces {
+                    let Some(broadcast) = interface.broadcast else {
+                        continue;
+                    };
+                    let result = self.send_from_to_on(network, interface.ip, broadcast).await;
+                    sent.record(&name, result);
+                }
+            }
+            Transport::Ethernet => {
+                for (name, source) in network.ethernet_interfaces()? {
+                    sent.record(&name, raw::send_on_interface(self, &name, source.bytes()));
+                }
+            }
+        }
+        sent.result()
+    }
+
+    pub async fn send_with_strategy(&self, strategy: &SendStrategy) -> Result<(), Box<dyn Error>> {
+        self.send_with_strategy_on(&SystemNetwork, strategy).await
+    }
+
+    /// Only the interfaces of `network` are used, raw frames and arp entries are still this machine:s
+    pub async fn send_with_strategy_on<N: Network>(
+        &self,
+        network: &N,
+        strategy: &SendStrategy,
+    ) -> Result<(), Box<dyn Error>> {
+        match (strategy, self.transport) {
+            (SendStrategy::LocalBroadcast, _) => self.send_on(network).await?,
+            (SendStrategy::Interface { name }, Transport::Udp { .. }) => {
+                let interfaces = network.v4_interfaces()?;
+                let broadcasts = interfaces.into_iter().filter(|(interface_name, addr)| {
+                    interface_name == name && addr.broadcast.is_some()
+                });
+
+                let mut sent = SentOnInterfaces::default();
+                for (_, addr) in broadcasts {
+                    let result = self
+                        .send_from_to_on(network, addr.ip, addr.broadcast.unwrap())
+                        .await;
+                    sent.record(name, result);
+                }
+                sent.result()?
+            }
+            (SendStrategy::Interface { name }, Transport::Ethernet) => {
+                let source = network
+                    .ethernet_interfaces()?
+                    .into_iter()
+                    .find(|(interface_name, _)| interface_name == name)
+                    .map(|(_, source)| source)
+                    .ok_or_else(|| format!("No allowed interface {name:?} with a mac address"))?;
+                raw::send_on_interface(self, name, source.bytes())?
+            }
+            (SendStrategy::DirectedBroadcast { network: subnet }, Transport::Udp { .. }) => {
+                self.send_from_to_on(network, Ipv4Addr::UNSPECIFIED, subnet.broadcast())
+                    .await?
+            }
+            (SendStrategy::Unicast { ip, seed_arp }, Transport::Udp { .. }) => {
+                if *seed_arp {
+                    self.seed_arp_entry(network, *ip).await;
+                }
+                self.send_from_to_on(network, Ipv4Addr::UNSPECIFIED, *ip)
+                    .await?
+            }
+            (_, Transport::Ethernet) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "raw ethernet frames can not be routed, use udp",
+                )
+                .into())
+            }
+        }
+        Ok(())
+    }
+
+    async fn seed_arp_entry<N: Network>(&self, network: &N, ip: Ipv4Addr) {
+        let interface = network.v4_interfaces().ok().and_then(|interfaces| {
+            interfaces.into_iter().find(|(_, addr)| {
+                addr.netmask
+                    .and_then(|netmask| Ipv4Network::with_netmask(addr.ip, netmask).ok())
+                    .is_some_and(|network| network.contains(ip))
+            })
+        });
+
+        let Some((interface_name, _)) = interface else {
+            println!("{ip:?} is not on a local subnet, not seeding ARP");
+            return;
+        };
+
+        let mac_address = MacAddress::new(self.mac_address);
+        if let Err(e) = arp::seed_static_entry(ip, mac_address, &interface_name).await {
+            println!("Could not seed ARP entry for {ip:?}: {e:?}");
+        }
+    }
+
+    pub async fn send_from_to(&self, from: Ipv4Addr, to: Ipv4Addr) -> io::Result<()> {
+        self.send_from_to_on(&SystemNetwork, from, to).await
+    }
+
+    pub async fn send_from_to_on<T: DatagramTransport>(
+        &self,
+        transport: &T,
+        from: Ipv4Addr,
+        to: Ipv4Addr,
+    ) -> io::Result<()> {
+        let Transport::Udp { port } = self.transport else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "raw ethernet magic packets are broadcast on an interface, not sent over udp",
+            ));
+        };
+
+        let sock = transport
+            .bind(SocketAddr::V4(SocketAddrV4::new(from, 0)))
+            .await?;
+        sock.set_broadcast(true)?;
+
+        let payload = self.to_bytes();
+
+        let sent_bytes = sock
+            .send_to(&payload, SocketAddr::V4(SocketAddrV4::new(to, port)))
+            .await?;
+        println!("Sent {:?} bytes from {:?}, to: {:?}", sent_bytes, from, to);
+
+        Ok(())
+    }
+}
+
+/// Sending continues past failing interfaces, the last error is kept for when none succeeded
+#[derive(Default)]
+struct SentOnInterfaces {
+    sent: usize,
+    last_error: Option<io::Error>,
+}
+
+impl SentOnInterfaces {
+    fn record(&mut self, interface_name: &str, result: io::Result<()>) {
+        match result {
+            Ok(()) => self.sent += 1,
+            Err(e) => {
+                println!("Could not send magic packet on {interface_name:?}: {e:?}");
+                self.last_error = Some(e);
+            }
+        }
+    }
+
+    fn result(self) -> Result<(), Box<dyn Error>> {
+        match (self.sent, self.last_error) {
+            (0, Some(e)) => Err(e.into()),
+            (0, None) => Err("No interface to send the magic packet on".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub async fn send(mac_address: [u8; MAC_ADDRESS_SIZE]) -> Result<(), Box<dyn Error>> {
+    MagicPacket::new(mac_address).send().await
 }
 
 pub async fn send_from_to(
@@ -26,31 +447,83 @@ pub async fn send_from_to(
     from: Ipv4Addr,
     to: Ipv4Addr,
 ) -> io::Result<()> {
-    let sock = UdpSocket::bind(SocketAddrV4::new(from, 0)).await?;
-    sock.set_broadcast(true)?;
+    MagicPacket::new(mac_address).send_from_to(from, to).await
+}
 
-    let payload = create_magic_packet(mac_address);
+#[cfg(target_os = "linux")]
+mod raw {
+    use std::{ffi::CString, io, mem};
 
-    let sent_bytes = sock.send_to(&payload, SocketAddrV4::new(to, 9)).await?;
-    println!("Sent {:?} bytes from {:?}, to: {:?}", sent_bytes, from, to);
+    use super::{MagicPacket, ETHER_TYPE, MAC_ADDRESS_SIZE};
 
-    Ok(())
-}
+    /// Needs CAP_NET_RAW
+    pub fn send_on_interface(
+        packet: &MagicPacket,
+        interface_name: &str,
+        source: [u8; MAC_ADDRESS_SIZE],
+    ) -> io::Result<()> {
+        let name = CString::new(interface_name)?;
+        let frame = packet.to_ethernet_frame(source);
 
-fn create_magic_packet(mac_address: [u8; MAC_ADDRESS_SIZE]) -> [u8; MAGIC_PACKET_TOTAL_SIZE_BYTES] {
-    let mut magic_packet_content = [0; MAGIC_PACKET_TOTAL_SIZE_BYTES];
-    // Fill 6 bytes of 0xFF
-    for i in 0..HEADER_SIZE_BYTES {
-        magic_packet_content[i] = u8::MAX;
-    }
+        // SAFETY: plain libc calls, the descriptor is closed before returning and the address
+        // struct is fully initialised before it is handed to sendto
+        unsafe {
+            let index = libc::if_nametoindex(name.as_ptr());
+            if index == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let fd = libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_RAW,
+                ETHER_TYPE.to_be() as libc::c_int,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut address: libc::sockaddr_ll = mem::zeroed();
+            address.sll_family = libc::AF_PACKET as u16;
+            address.sll_protocol = ETHER_TYPE.to_be();
+            address.sll_ifindex = index as i32;
+            address.sll_halen = MAC_ADDRESS_SIZE as u8;
+            address.sll_addr[..MAC_ADDRESS_SIZE].fill(u8::MAX);
+
+            let sent = libc::sendto(
+                fd,
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+                0,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            );
+            let result = if sent < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                println!("Sent {:?} byte frame on {:?}", sent, interface_name);
+                Ok(())
+            };
 
-    // Add 16 repetitions of the wakee:s mac address
-    for i in 0..TARGET_MAC_ADDRESS_REPETITIONS {
-        let offset = HEADER_SIZE_BYTES + i * mac_address.len();
-        for (byte_idx, byte) in mac_address.iter().enumerate() {
-            magic_packet_content[offset + byte_idx] = *byte;
+            libc::close(fd);
+            result
         }
     }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod raw {
+    use std::io;
 
-    magic_packet_content
+    use super::{MagicPacket, MAC_ADDRESS_SIZE};
+
+    pub fn send_on_interface(
+        _packet: &MagicPacket,
+        _interface_name: &str,
+        _source: [u8; MAC_ADDRESS_SIZE],
+    ) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "raw ethernet magic packets are only supported on linux",
+        ))
+    }
 }
@@ -0,0 +1,28 @@
+use std::{io, net::Ipv4Addr};
+
+use mac_address::MacAddress;
+use tokio::process::Command;
+
+/// Adds a permanent neighbour entry so unicast packets reach a host that has stopped answering
+/// ARP while asleep, needs CAP_NET_ADMIN
+pub async fn seed_static_entry(
+    ip: Ipv4Addr,
+    mac_address: MacAddress,
+    interface: &str,
+) -> io::Result<()> {
+    let output = Command::new("ip")
+        .args(["neigh", "replace", &ip.to_string()])
+        .args(["lladdr", &mac_address.to_string()])
+        .args(["dev", interface, "nud", "permanent"])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,267 @@
+use std::{io, path::Path, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, select, sync::watch, time::Instant};
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct BootConfig {
+    /// A discovery request this soon after boot means a client woke the machine
+    pub discovery_window_secs: u64,
+
+    /// Idle time before shutting down when a client or the rtc alarm booted the machine
+    pub wake_idle_secs: u64,
+
+    /// Idle time before shutting down when someone powered the machine on by hand
+    pub human_idle_secs: u64,
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            discovery_window_secs: 120,
+            wake_idle_secs: 60,
+            human_idle_secs: 30 * 60,
+        }
+    }
+}
+
+impl BootConfig {
+    /// How long the machine may sit idle before shutting down, a boot that is not attributed
+    /// yet gets the benefit of the doubt
+    pub fn idle_timeout(&self, wake_source: WakeSource) -> Duration {
+        match wake_source {
+            WakeSource::WakeOnLan | WakeSource::RtcAlarm => {
+                Duration::from_secs(self.wake_idle_secs)
+            }
+            WakeSource::Human | WakeSource::Pending => Duration::from_secs(self.human_idle_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WakeSource {
+    /// Still inside the discovery window, nothing has pointed at a source yet
+    Pending,
+    RtcAlarm,
+    WakeOnLan,
+    Human,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WakeupIrq {
+    pub irq: u32,
+    /// Actions registered on the irq in /proc/interrupts
+    pub actions: Vec<String>,
+    pub is_network: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AcpiWakeupDevice {
+    pub name: String,
+    pub sysfs_node: Option<String>,
+    pub is_network: bool,
+}
+
+/// What is known about how the machine came to be running
+#[derive(Debug, Clone, Serialize)]
+pub struct BootContext {
+    /// Seconds since the unix epoch
+    pub boot_time: i64,
+    pub wake_source: WakeSource,
+    pub rtc_alarm: bool,
+    /// The irq that last resumed the machine from suspend, from /sys/power/pm_wakeup_irq
+    pub wakeup_irq: Option<WakeupIrq>,
+    /// Devices enabled to wake the machine in /proc/acpi/wakeup
+    pub acpi_wakeup_devices: Vec<AcpiWakeupDevice>,
+    /// Seconds between boot and the first discovery request that was answered
+    pub first_discovery_secs: Option<i64>,
+}
+
+impl BootContext {
+    pub async fn detect(boot_time: i64, rtc_alarm: bool) -> BootContext {
+        let wakeup_irq = read_wakeup_irq().await;
+        let acpi_wakeup_devices = read_acpi_wakeup_devices().await;
+
+        BootContext {
+            boot_time,
+            wake_source: attribute_wake(rtc_alarm, wakeup_irq.as_ref(), &acpi_wakeup_devices),
+            rtc_alarm,
+            wakeup_irq,
+            acpi_wakeup_devices,
+            first_discovery_secs: None,
+        }
+    }
+
+    fn discovery_answered(&mut self, since_boot: Duration) {
+        if self.first_discovery_secs.is_none() {
+            self.first_discovery_secs = Some(since_boot.as_secs() as i64);
+        }
+        if self.wake_source == WakeSource::Pending {
+            self.wake_source = WakeSource::WakeOnLan;
+        }
+    }
+
+    fn discovery_window_passed(&mut self) {
+        if self.wake_source == WakeSource::Pending {
+            self.wake_source = WakeSource::Human;
+        }
+    }
+}
+
+/// What booted the machine as far as can be told at boot, `Pending` leaves it to discovery.
+/// Network cards waking through ACPI show up as the `acpi` irq, that only counts when network
+/// cards are all that is enabled to wake the machine
+pub fn attribute_wake(
+    rtc_alarm: bool,
+    wakeup_irq: Option<&WakeupIrq>,
+    acpi_wakeup_devices: &[AcpiWakeupDevice],
+) -> WakeSource {
+    let by_acpi = wakeup_irq.is_some_and(|irq| irq.actions.iter().any(|action| action == "acpi"));
+    let only_network_wakes_acpi = !acpi_wakeup_devices.is_empty()
+        && acpi_wakeup_devices.iter().all(|device| device.is_network);
+
+    if rtc_alarm {
+        WakeSource::RtcAlarm
+    } else if wakeup_irq.is_some_and(|irq| irq.is_network) || by_acpi && only_network_wakes_acpi {
+        WakeSource::WakeOnLan
+    } else {
+        WakeSource::Pending
+    }
+}
+
+/// Attributes the boot in `context` to a client when `answered_requests` changes within
+/// `window` of boot, and to a human once the window has passed without any. `uptime` is how
+/// long ago the machine booted when tracking starts
+pub async fn track_boot_context(
+    context: watch::Sender<BootContext>,
+    mut answered_requests: watch::Receiver<usize>,
+    window: Duration,
+    uptime: Duration,
+) {
+    let started = Instant::now();
+    let sleep = tokio::time::sleep_until(started + window.saturating_sub(uptime));
+    tokio::pin!(sleep);
+    loop {
+        select! {
+            _ = &mut sleep => break,
+            result = answered_requests.changed() => {
+                if result.is_err() {
+                    break;
+                }
+                let since_boot = uptime + started.elapsed();
+                context.send_modify(|context| context.discovery_answered(since_boot));
+            }
+        }
+    }
+
+    context.send_modify(|context| context.discovery_window_passed());
+    println!("Boot attributed to {:?}", context.borrow().wake_source);
+
+    // The shutdown condition would see a closed channel as a context changing over and over
+    context.closed().await;
+}
+
+/// Time since boot from /proc/uptime, unlike the wall clock it does not jump
+pub async fn read_uptime() -> io::Result<Duration> {
+    let uptime = fs::read_to_string("/proc/uptime").await?;
+    uptime
+        .split_whitespace()
+        .next()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed /proc/uptime"))
+}
+
+impl WakeupIrq {
+    /// The line of `irq` in /proc/interrupts, `is_interface` tells network interfaces apart
+    pub fn parse(irq: u32, interrupts: &str, is_interface: impl Fn(&str) -> bool) -> WakeupIrq {
+        let actions: Vec<String> = interrupts
+            .lines()
+            .find(|line| line.trim_start().strip_prefix(&format!("{irq}:")).is_some())
+            .map(|line| {
+                // `28:  0  PCI-MSIX-0000:00:01.0  0-edge  enp3s0`, counts and chip before the trigger
+                line.split_whitespace()
+                    .skip_while(|field| {
+                        !["edge", "level", "fasteoi"]
+                            .iter()
+                            .any(|t| field.ends_with(t))
+                    })
+                    .skip(1)
+                    .map(|action| action.trim_end_matches(',').to_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Network drivers name their vectors after the interface, `enp3s0` or `eth0-rx-0`
+        let is_network = actions.iter().any(|action| {
+            let interface = action.split('-').next().unwrap_or(action);
+            is_interface(interface)
+        });
+
+        WakeupIrq {
+            irq,
+            actions,
+            is_network,
+        }
+    }
+}
+
+impl AcpiWakeupDevice {
+    /// The enabled devices in /proc/acpi/wakeup, `is_network_device` is asked about the pci
+    /// address of each
+    pub fn parse_enabled(
+        wakeup: &str,
+        is_network_device: impl Fn(&str) -> bool,
+    ) -> Vec<AcpiWakeupDevice> {
+        // Device  S-state  Status  Sysfs node, `GLAN  S4  *enabled  pci:0000:00:1f.6`
+        wakeup
+            .lines()
+            .skip(1)
+            .filter(|line| line.contains("*enabled"))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let name = fields.next()?.to_owned();
+                let sysfs_node = fields.nth(2).map(str::to_owned);
+                let is_network = sysfs_node
+                    .as_deref()
+                    .and_then(|node| node.strip_prefix("pci:"))
+                    .is_some_and(&is_network_device);
+
+                Some(AcpiWakeupDevice {
+                    name,
+                    sysfs_node,
+                    is_network,
+                })
+            })
+            .collect()
+    }
+}
+
+async fn read_wakeup_irq() -> Option<WakeupIrq> {
+    let irq: u32 = fs::read_to_string("/sys/power/pm_wakeup_irq")
+        .await
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+
+    let interrupts = fs::read_to_string("/proc/interrupts").await.ok()?;
+    Some(WakeupIrq::parse(irq, &interrupts, |interface| {
+        Path::new("/sys/class/net").join(interface).exists()
+    }))
+}
+
+async fn read_acpi_wakeup_devices() -> Vec<AcpiWakeupDevice> {
+    let Ok(wakeup) = fs::read_to_string("/proc/acpi/wakeup").await else {
+        return vec![];
+    };
+
+    AcpiWakeupDevice::parse_enabled(&wakeup, |address| {
+        Path::new("/sys/bus/pci/devices")
+            .join(address)
+            .join("net")
+            .exists()
+    })
+}
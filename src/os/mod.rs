@@ -1 +1,5 @@
+pub mod arp;
+pub mod boot;
+pub mod pty;
+pub mod rtc;
 pub mod users;
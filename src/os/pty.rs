@@ -0,0 +1,213 @@
+use std::{
+    ffi::CStr,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    pin::Pin,
+    process::{Command, Stdio},
+    task::{ready, Context, Poll},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WindowSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Default for WindowSize {
+    fn default() -> Self {
+        Self { cols: 80, rows: 24 }
+    }
+}
+
+impl WindowSize {
+    fn winsize(self) -> libc::winsize {
+        libc::winsize {
+            ws_row: self.rows,
+            ws_col: self.cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+}
+
+/// Opens a pseudo terminal of `size`, returning its master and slave ends
+pub fn open_pty(size: WindowSize) -> io::Result<(PtyStream, OwnedFd)> {
+    // SAFETY: plain descriptor creation, it is owned by `OwnedFd` right after the check
+    let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if master < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `master` was just created and is not owned by anything else
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+
+    // SAFETY: `master` is an open pty master for the duration of both calls
+    if unsafe { libc::grantpt(master.as_raw_fd()) } != 0
+        || unsafe { libc::unlockpt(master.as_raw_fd()) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut name = [0 as libc::c_char; 128];
+    // SAFETY: `name` outlives the call and its length is passed along
+    let named = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+    if named != 0 {
+        return Err(io::Error::from_raw_os_error(named));
+    }
+
+    // SAFETY: ptsname_r nul terminated `name`, which is not touched while the slave is opened
+    let slave = unsafe {
+        let name = CStr::from_ptr(name.as_ptr());
+        libc::open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    };
+    if slave < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `slave` was just opened and is not owned by anything else
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+
+    let master = PtyStream::new(master)?;
+    master.resize(size)?;
+    Ok((master, slave))
+}
+
+/// Makes the pty's slave end the command's stdin, stdout, stderr and controlling terminal
+///
+/// The process becomes the leader of a new session, and with it of its own process group, so
+/// it must not also be given one with `process_group`
+pub fn attach_to_pty(command: &mut Command, slave: &OwnedFd) -> io::Result<()> {
+    command.stdin(Stdio::from(slave.try_clone()?));
+    command.stdout(Stdio::from(slave.try_clone()?));
+    command.stderr(Stdio::from(slave.try_clone()?));
+
+    // SAFETY: setsid and ioctl are async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+/// The master end of a pty, reading what the process writes and writing what it reads
+#[derive(Debug)]
+pub struct PtyStream {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl PtyStream {
+    fn new(fd: OwnedFd) -> io::Result<PtyStream> {
+        // SAFETY: `fd` is open for the duration of both calls
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0
+            || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(PtyStream {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// A second handle on the same pty, so reading and writing can happen in separate places
+    pub fn try_clone(&self) -> io::Result<PtyStream> {
+        PtyStream::new(self.fd.get_ref().try_clone()?)
+    }
+
+    /// Tells the process its terminal changed size, it receives SIGWINCH
+    pub fn resize(&self, size: WindowSize) -> io::Result<()> {
+        let winsize = size.winsize();
+        // SAFETY: `winsize` outlives the call
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), libc::TIOCSWINSZ, &winsize) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for PtyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            // SAFETY: `unfilled` outlives the call and its length is passed along
+            let read = guard.try_io(|fd| {
+                match unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        unfilled.as_mut_ptr() as *mut libc::c_void,
+                        unfilled.len(),
+                    )
+                } {
+                    len if len >= 0 => Ok(len as usize),
+                    _ => Err(io::Error::last_os_error()),
+                }
+            });
+
+            match read {
+                Ok(Ok(len)) => {
+                    buf.advance(len);
+                    return Poll::Ready(Ok(()));
+                }
+                // Every slave descriptor was closed, the process and its children are gone
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Poll::Ready(Ok(())),
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for PtyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_write_ready(cx))?;
+            // SAFETY: `buf` outlives the call and its length is passed along
+            let written = guard.try_io(|fd| {
+                match unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void,
+                        buf.len(),
+                    )
+                } {
+                    len if len >= 0 => Ok(len as usize),
+                    _ => Err(io::Error::last_os_error()),
+                }
+            });
+
+            if let Ok(written) = written {
+                return Poll::Ready(written);
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
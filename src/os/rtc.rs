@@ -0,0 +1,140 @@
+use std::{
+    io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// An alarm that went off this long before the machine booted is taken to have woken it
+const BOOT_WINDOW_SECS: i64 = 10 * 60;
+
+/// Firmware clocks drift, boots slightly before the alarm time still count
+const EARLY_BOOT_SLACK_SECS: i64 = 60;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RtcConfig {
+    pub enabled: bool,
+
+    /// Where the alarm is written, `/sys/class/rtc/rtcN/wakealarm`
+    pub wakealarm_path: PathBuf,
+
+    /// Boot this many seconds ahead of a scheduled run
+    pub lead_secs: u64,
+}
+
+impl Default for RtcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            wakealarm_path: PathBuf::from("/sys/class/rtc/rtc0/wakealarm"),
+            lead_secs: 120,
+        }
+    }
+}
+
+/// The rtc wake alarm, along with a record of what was armed since the kernel empties
+/// `wakealarm` once the alarm has fired. Only an alarm matching the record is ours to change
+#[derive(Debug, Clone)]
+pub struct RtcAlarm {
+    pub wakealarm_path: PathBuf,
+    pub record_path: PathBuf,
+}
+
+impl RtcAlarm {
+    pub fn new(config: &RtcConfig) -> Self {
+        let mut record_path = ProjectDirs::from("com", "ngodag", "wake_runner")
+            .unwrap()
+            .data_dir()
+            .to_owned();
+        record_path.push("rtc_alarm");
+
+        Self {
+            wakealarm_path: config.wakealarm_path.clone(),
+            record_path,
+        }
+    }
+
+    /// Makes the machine power on at `at`, seconds since the unix epoch. Fails when another
+    /// tool has an alarm set
+    pub async fn arm(&self, at: i64) -> io::Result<()> {
+        match self.armed().await? {
+            Some(armed) if Some(armed) != self.recorded().await => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("rtc alarm for {armed} was set by another tool, not replacing it"),
+                ))
+            }
+            // The kernel refuses a new alarm while one is set
+            Some(_) => fs::write(&self.wakealarm_path, "0").await?,
+            None => {}
+        }
+        fs::write(&self.wakealarm_path, at.to_string()).await?;
+
+        if let Some(parent) = self.record_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&self.record_path, at.to_string()).await
+    }
+
+    /// Clears the alarm if it is ours, forgets about it either way
+    pub async fn disarm(&self) -> io::Result<()> {
+        let armed = self.armed().await?;
+        if armed.is_some() && armed == self.recorded().await {
+            fs::write(&self.wakealarm_path, "0").await?;
+        }
+        match fs::remove_file(&self.record_path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// The alarm time currently set, `None` when there is none
+    pub async fn armed(&self) -> io::Result<Option<i64>> {
+        Ok(parse_alarm(
+            &fs::read_to_string(&self.wakealarm_path).await?,
+        ))
+    }
+
+    async fn recorded(&self) -> Option<i64> {
+        parse_alarm(&fs::read_to_string(&self.record_path).await.ok()?)
+    }
+
+    /// Whether the boot at `boot_time` was caused by our alarm, clears our alarm when it has
+    /// already passed so it cannot power the machine on again
+    pub async fn check_boot(&self, boot_time: i64) -> io::Result<bool> {
+        let Some(alarm) = self.recorded().await else {
+            return Ok(false);
+        };
+
+        if alarm <= now() {
+            println!("Clearing stale rtc alarm set for {alarm}");
+            self.disarm().await?;
+        }
+
+        Ok(alarm - EARLY_BOOT_SLACK_SECS <= boot_time && boot_time <= alarm + BOOT_WINDOW_SECS)
+    }
+}
+
+fn parse_alarm(alarm_str: &str) -> Option<i64> {
+    alarm_str.trim().parse().ok().filter(|alarm| *alarm > 0)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// When the machine booted, seconds since the unix epoch, from `btime` in /proc/stat
+pub async fn boot_time() -> io::Result<i64> {
+    let stat = fs::read_to_string("/proc/stat").await?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no btime in /proc/stat"))
+}
@@ -22,7 +22,7 @@ pub async fn watch_active_user_count(
 
     tokio::spawn(user_count_update_loop(stop, tx));
     println!("Select loop quit");
-    return user_count_rx;
+    user_count_rx
 }
 
 async fn user_count_update_loop(stop: CancellationToken, user_count: watch::Sender<usize>) {
@@ -35,9 +35,8 @@ async fn user_count_update_loop(stop: CancellationToken, user_count: watch::Send
         select! {
             _ = query_user_count_signal.notified() => {
                 let new_user_count = get_active_user_count().await.unwrap();
-                match user_count.send(new_user_count) {
-                    Err(_) => break,
-                    _ => {},
+                if user_count.send(new_user_count).is_err() {
+                    break;
                 }
             },
             _ = stop.cancelled() =>  {
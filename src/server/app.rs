@@ -3,39 +3,56 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use axum::{response::IntoResponse, routing::get, Json, Router};
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+use serde_json::json;
 use tokio::sync::watch;
 
+use crate::os::boot::BootContext;
+
 use super::{
+    relay,
     server_state::ServerState,
     wake::{router::create_router, WakeProcess},
 };
 
-pub fn create_app(
+pub fn create_state(
     wake_process_count_setter: watch::Sender<usize>,
     active_wake_process_count: watch::Receiver<usize>,
+    boot_context: watch::Receiver<BootContext>,
     config: super::config::Config,
-) -> Router<()> {
+) -> Arc<ServerState> {
     let wake_processes: HashMap<String, WakeProcess> = HashMap::new();
 
-    let app_state = Arc::new(ServerState {
+    Arc::new(ServerState {
         config,
         wake_processes: wake_processes.into(),
+        wake_run_results: HashMap::new().into(),
         active_wake_process_count,
+        boot_context,
         active_wake_process_count_setter: Mutex::new(wake_process_count_setter),
-    });
+    })
+}
 
-    let app = Router::new()
+pub fn create_app(app_state: Arc<ServerState>) -> Router<()> {
+    Router::new()
         .route("/ping", get(ping))
+        .route("/status", get(status))
         .nest("/wake", create_router(app_state.clone()))
+        .nest("/relay", relay::router::create_router(app_state.clone()))
         // .route("/wakes", get(get_wakes))
         // .route("/ping", get(ping))
         // .route("/wake_run", post(wake_run))
-        .with_state(app_state);
-
-    app
+        .with_state(app_state)
 }
 
 async fn ping() -> impl IntoResponse {
     Json("pong")
 }
+
+async fn status(state: State<Arc<ServerState>>) -> impl IntoResponse {
+    let boot_context = state.boot_context.borrow().clone();
+    Json(json!({
+        "boot": boot_context,
+        "active_wakes": *state.active_wake_process_count.borrow(),
+    }))
+}
@@ -1,6 +1,13 @@
-use std::{fmt, path::PathBuf};
+use std::path::PathBuf;
 
-use crate::server::wake;
+use crate::{
+    net::interface_filter::InterfaceFilter,
+    os::{boot::BootConfig, rtc::RtcConfig},
+    server::{
+        relay::RelayConfig,
+        wake::{self, hooks::Hooks, logs::LogConfig},
+    },
+};
 use directories::ProjectDirs;
 use futures::StreamExt;
 use once_cell::sync::OnceCell;
@@ -10,11 +17,47 @@ use tokio::fs;
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub wakes: Vec<wake::Wake>,
+
+    /// Wake-on-LAN relaying for neighbouring machines, disabled when missing
+    pub relay: Option<RelayConfig>,
+
+    /// Interfaces discovery is answered on
+    #[serde(default)]
+    pub interfaces: InterfaceFilter,
+
+    /// Powering the machine back on for the next scheduled run
+    #[serde(default)]
+    pub rtc: RtcConfig,
+
+    /// Telling wake-on-lan boots from ones by hand
+    #[serde(default)]
+    pub boot: BootConfig,
+
+    /// Commands run around every run of every wake
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    /// Where the output of runs is kept, and for how long
+    #[serde(default)]
+    pub logs: LogConfig,
+
+    /// Certificate fingerprint of a TLS proxy in front of the runner, published over mDNS.
+    /// The runner itself only serves plain http.
+    pub tls_fingerprint: Option<String>,
 }
 
 impl Config {
     fn default() -> Config {
-        Self { wakes: vec![] }
+        Self {
+            wakes: vec![],
+            relay: None,
+            interfaces: InterfaceFilter::default(),
+            rtc: RtcConfig::default(),
+            boot: BootConfig::default(),
+            hooks: Hooks::default(),
+            logs: LogConfig::default(),
+            tls_fingerprint: None,
+        }
     }
 }
 
@@ -0,0 +1,341 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    time::Duration,
+};
+
+use futures::future;
+use mac_address::MacAddress;
+use network_interface::V4IfAddr;
+use tokio::{select, sync::watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::net::{
+    discovery::{
+        Announcement, DiscoveryRequest, IPV6_DISCOVERY_MULTICAST_GROUP, UDP_DISCOVERY_PORT,
+    },
+    interface_watch::InterfaceSnapshot,
+    interfaces::V6Interface,
+    network::{DatagramSocket, Network},
+};
+
+/// Wait before binding again after the socket failed
+const REBIND_DELAY: Duration = Duration::from_secs(1);
+
+/// Answers discovery requests for the mac addresses of `network` with the http port of the
+/// server, until `shutdown_token` is cancelled. Requests are only answered when they arrive
+/// from the link of one of the interfaces of `network`.
+///
+/// A socket is bound on each interface of `network` so filtered out interfaces never see the
+/// requests, on machines that can't bind to an interface one socket listens on all of them.
+/// When the interfaces change (DHCP renewal, VPN, resume) the sockets are bound again, the v6
+/// group joined on the new interfaces and the new addresses announced
+pub async fn discovery_server<N: Network>(
+    network: &N,
+    shutdown_token: &CancellationToken,
+    server_listen_port: u16,
+) {
+    let (answered_requests, _) = watch::channel(0);
+    discovery_server_reporting(
+        network,
+        shutdown_token,
+        server_listen_port,
+        &answered_requests,
+    )
+    .await
+}
+
+/// Same as [`discovery_server`], counting the requests answered in `answered_requests`
+pub async fn discovery_server_reporting<N: Network>(
+    network: &N,
+    shutdown_token: &CancellationToken,
+    server_listen_port: u16,
+    answered_requests: &watch::Sender<usize>,
+) {
+    println!("Starting discovery server on {}", UDP_DISCOVERY_PORT);
+    let mut watch = network.watch();
+    let mut interfaces = InterfaceSnapshot::of(network);
+    let mut local_mac_addresses = read_mac_addresses(network);
+    loop {
+        let udp_sockets = match bind_discovery_sockets(network, &interfaces) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                println!("Could not bind discovery socket, retrying on the next change: {e:?}");
+                vec![]
+            }
+        };
+
+        let answering = async {
+            if udp_sockets.is_empty() {
+                return std::future::pending().await;
+            }
+            let answering_on_each = udp_sockets.iter().map(|socket| {
+                Box::pin(answer_requests(
+                    socket,
+                    &interfaces,
+                    &local_mac_addresses,
+                    server_listen_port,
+                    answered_requests,
+                ))
+            });
+            future::select_all(answering_on_each).await;
+        };
+        let changed = async {
+            loop {
+                watch.changed().await;
+                let current = InterfaceSnapshot::of(network);
+                if current != interfaces {
+                    return current;
+                }
+            }
+        };
+
+        let changed_interfaces = select! {
+            _ = shutdown_token.cancelled() => break,
+            _ = answering => {
+                tokio::time::sleep(REBIND_DELAY).await;
+                continue;
+            }
+            changed_interfaces = changed => changed_interfaces,
+        };
+
+        println!("Interfaces changed, rebinding discovery");
+        drop(udp_sockets);
+        interfaces = changed_interfaces;
+        // Adapters come and go with the interfaces, a new one should be answered for right away
+        local_mac_addresses = read_mac_addresses(network);
+        announce(
+            network,
+            &interfaces,
+            &Announcement {
+                port: server_listen_port,
+                mac_addresses: local_mac_addresses.clone(),
+            },
+        )
+        .await;
+    }
+}
+
+/// Without them only `any` requests are answered
+fn read_mac_addresses<N: Network>(network: &N) -> Vec<MacAddress> {
+    let mac_addresses = match network.mac_addresses() {
+        Ok(mac_addresses) => mac_addresses,
+        Err(e) => {
+            println!("Could not read the local mac addresses: {e:?}");
+            vec![]
+        }
+    };
+    println!("Answering discovery for: {:?}", mac_addresses);
+    mac_addresses
+}
+
+/// One socket per interface, a single one bound to all interfaces where that is not possible
+fn bind_discovery_sockets<N: Network>(
+    network: &N,
+    interfaces: &InterfaceSnapshot,
+) -> io::Result<Vec<N::Socket>> {
+    let mut names: Vec<&str> = interfaces
+        .v4
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    names.extend(
+        interfaces
+            .v6
+            .iter()
+            .map(|interface| interface.name.as_str()),
+    );
+    names.sort_unstable();
+    names.dedup();
+
+    let mut udp_sockets = vec![];
+    let mut last_error = None;
+    for name in names {
+        let udp_socket = match network.bind_on_interface(UDP_DISCOVERY_PORT, name) {
+            Ok(udp_socket) => udp_socket,
+            Err(e) if is_binding_to_interfaces_unsupported(&e) => {
+                println!("Could not bind discovery to {name}, listening on all interfaces: {e:?}");
+                let udp_socket = network.bind_dual_stack(UDP_DISCOVERY_PORT)?;
+                join_discovery_group(&udp_socket, interfaces.v6.iter())?;
+                return Ok(vec![udp_socket]);
+            }
+            Err(e) => {
+                println!("Could not bind discovery to {name}: {e:?}");
+                last_error = Some(e);
+                continue;
+            }
+        };
+
+        let on_interface = interfaces
+            .v6
+            .iter()
+            .filter(|interface| interface.name == name);
+        join_discovery_group(&udp_socket, on_interface)?;
+        udp_sockets.push(udp_socket);
+    }
+
+    match (udp_sockets.is_empty(), last_error) {
+        (true, Some(e)) => Err(e),
+        (true, None) => Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no allowed interface to answer discovery on",
+        )),
+        (false, _) => Ok(udp_sockets),
+    }
+}
+
+fn is_binding_to_interfaces_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+    )
+}
+
+fn join_discovery_group<'a, S: DatagramSocket>(
+    udp_socket: &S,
+    interfaces: impl Iterator<Item = &'a V6Interface>,
+) -> io::Result<()> {
+    if !udp_socket.local_addr()?.is_ipv6() {
+        return Ok(());
+    }
+
+    for interface in interfaces {
+        let joined = udp_socket.join_multicast_v6(&IPV6_DISCOVERY_MULTICAST_GROUP, interface.index);
+        if let Err(e) = joined {
+            println!(
+                "Could not join discovery group on {}: {e:?}",
+                interface.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Only returns when the socket fails
+async fn answer_requests<S: DatagramSocket>(
+    udp_socket: &S,
+    interfaces: &InterfaceSnapshot,
+    local_mac_addresses: &[MacAddress],
+    server_listen_port: u16,
+    answered_requests: &watch::Sender<usize>,
+) {
+    let response_bytes = server_listen_port.to_le_bytes();
+    println!("Response bytes: {:?}", response_bytes);
+    match udp_socket.local_addr() {
+        Ok(local_addr) => println!("Listening on discovery on {local_addr:?}"),
+        Err(e) => println!("Listening on discovery, on an unknown address: {e:?}"),
+    }
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let (n_bytes, from) = match udp_socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                println!("Discovery socket failed: {e:?}");
+                return;
+            }
+        };
+
+        println!("Got {}, from {:?}", n_bytes, from);
+        if !is_on_link(from, &interfaces.v4, &interfaces.v6) {
+            println!("Ignoring discovery from outside the allowed interfaces");
+            continue;
+        }
+
+        if let Some(announcement) = Announcement::parse(&buf[..n_bytes]) {
+            // Broadcasts loop back to the sender
+            if announcement.mac_addresses != local_mac_addresses {
+                println!("Runner {:?} moved to {from:?}", announcement.mac_addresses);
+            }
+            continue;
+        }
+
+        match DiscoveryRequest::parse(&buf[..n_bytes]) {
+            Some(request) if request.matches(local_mac_addresses) => {
+                match udp_socket.send_to(&response_bytes, from).await {
+                    Ok(_) => answered_requests.send_modify(|count| *count += 1),
+                    Err(e) => println!("Could not answer discovery from {from:?}: {e:?}"),
+                }
+            }
+            Some(request) => println!("Ignoring discovery for {:?}", request),
+            None => println!("Ignoring malformed discovery request"),
+        }
+    }
+}
+
+/// Broadcasts `announcement` on every v4 interface and sends it to the v6 discovery group
+async fn announce<N: Network>(
+    network: &N,
+    interfaces: &InterfaceSnapshot,
+    announcement: &Announcement,
+) {
+    let payload = announcement.to_bytes();
+
+    for (name, addr) in &interfaces.v4 {
+        let Some(broadcast) = addr.broadcast else {
+            continue;
+        };
+        let sent = async {
+            let socket = network.bind(SocketAddrV4::new(addr.ip, 0).into()).await?;
+            socket.set_broadcast(true)?;
+            socket
+                .send_to(
+                    &payload,
+                    SocketAddrV4::new(broadcast, UDP_DISCOVERY_PORT).into(),
+                )
+                .await
+        };
+        if let Err(e) = sent.await {
+            println!("Could not announce on {name}: {e:?}");
+        }
+    }
+
+    for interface in &interfaces.v6 {
+        let group = SocketAddrV6::new(
+            IPV6_DISCOVERY_MULTICAST_GROUP,
+            UDP_DISCOVERY_PORT,
+            0,
+            interface.index,
+        );
+        let sent = async {
+            let socket = network
+                .bind(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0).into())
+                .await?;
+            socket.send_to(&payload, group.into()).await
+        };
+        if let Err(e) = sent.await {
+            println!("Could not announce on {}: {e:?}", interface.name);
+        }
+    }
+}
+
+fn is_on_link(from: SocketAddr, v4: &[(String, V4IfAddr)], v6: &[V6Interface]) -> bool {
+    let ip = match from.ip() {
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(ip)),
+        ip => ip,
+    };
+    if ip.is_loopback() {
+        return true;
+    }
+
+    match (ip, from) {
+        (IpAddr::V4(ip), _) => v4.iter().any(|(_, addr)| {
+            addr.netmask.is_some_and(|netmask| {
+                u32::from(ip) & u32::from(netmask) == u32::from(addr.ip) & u32::from(netmask)
+            })
+        }),
+        // Link-local senders carry the index of the interface the request arrived on
+        (IpAddr::V6(_), SocketAddr::V6(from)) if from.scope_id() != 0 => v6
+            .iter()
+            .any(|interface| interface.index == from.scope_id()),
+        (IpAddr::V6(ip), _) => v6.iter().any(|interface| {
+            interface.addr.netmask.is_some_and(|netmask| {
+                u128::from(ip) & u128::from(netmask)
+                    == u128::from(interface.addr.ip) & u128::from(netmask)
+            })
+        }),
+    }
+}
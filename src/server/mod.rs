@@ -1,4 +1,6 @@
 pub mod app;
 pub mod config;
+pub mod discovery;
+pub mod relay;
 pub mod server_state;
 pub mod wake;
@@ -0,0 +1,119 @@
+pub mod relay_wake_body_dto;
+pub mod router;
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+use tokio::{net::UdpSocket, select, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::net::{
+    interfaces::get_named_v4_interfaces, network::Network, sniffer::RECEIVE_ERROR_BACKOFF,
+    wake_on_lan::MagicPacket,
+};
+
+/// A mac address relayed within this long isn't relayed again. Two relays on the same network
+/// would otherwise keep re-emitting each other's packets when the relay port is a wake-on-lan
+/// port.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RelayConfig {
+    pub allowed_mac_addresses: Vec<MacAddress>,
+
+    /// Also accept magic packets on this udp port and re-emit them on the local networks
+    pub udp_port: Option<u16>,
+}
+
+impl RelayConfig {
+    pub fn allows(&self, mac_address: MacAddress) -> bool {
+        self.allowed_mac_addresses.contains(&mac_address)
+    }
+}
+
+/// Re-emits on the interfaces of `network`, pass a filtered one to keep to the allowed interfaces
+pub async fn udp_relay_listener<N: Network>(
+    relay_config: RelayConfig,
+    port: u16,
+    network: N,
+    stop: CancellationToken,
+) {
+    println!("Starting wake-on-lan relay on {}", port);
+    let udp_socket = match UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)).await {
+        Ok(udp_socket) => udp_socket,
+        Err(e) => {
+            println!("Could not bind the wake-on-lan relay to {port}, not relaying udp: {e:?}");
+            return;
+        }
+    };
+
+    let mut relayed: HashMap<MacAddress, Instant> = HashMap::new();
+    let mut buf = Vec::with_capacity(1024);
+    loop {
+        buf.clear();
+        let received = select! {
+            _ = stop.cancelled() => break,
+            result = udp_socket.recv_buf_from(&mut buf) => result,
+        };
+        let (n_bytes, from) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                println!("Receiving on the wake-on-lan relay port {port} failed: {e}");
+                select! {
+                    _ = stop.cancelled() => break,
+                    _ = tokio::time::sleep(RECEIVE_ERROR_BACKOFF) => continue,
+                }
+            }
+        };
+
+        // Our own re-emitted broadcasts come back when the relay port is a wake-on-lan port
+        if is_local_address(from.ip()) {
+            continue;
+        }
+
+        let magic_packet = match MagicPacket::parse(&buf[..n_bytes]) {
+            Ok(magic_packet) => magic_packet,
+            Err(e) => {
+                println!("Ignoring invalid magic packet from {:?}: {e}", from);
+                continue;
+            }
+        };
+
+        let mac_address = MacAddress::new(magic_packet.mac_address());
+        if !relay_config.allows(mac_address) {
+            println!(
+                "Not relaying to {} from {:?}, not allowed",
+                mac_address, from
+            );
+            continue;
+        }
+
+        let now = Instant::now();
+        relayed.retain(|_, at| now.duration_since(*at) < DUPLICATE_WINDOW);
+        if relayed.contains_key(&mac_address) {
+            println!("Not relaying to {mac_address} from {from:?}, just relayed it");
+            continue;
+        }
+        relayed.insert(mac_address, now);
+
+        println!("Relaying wake-on-lan to {} from {:?}", mac_address, from);
+        if let Err(e) = magic_packet.send_on(&network).await {
+            println!("Could not relay wake-on-lan: {e:?}");
+        }
+    }
+}
+
+fn is_local_address(ip: IpAddr) -> bool {
+    let IpAddr::V4(ip) = ip else {
+        return false;
+    };
+
+    get_named_v4_interfaces()
+        .map(|interfaces| interfaces.iter().any(|(_, addr)| addr.ip == ip))
+        .unwrap_or(false)
+}
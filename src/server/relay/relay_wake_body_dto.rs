@@ -0,0 +1,11 @@
+use mac_address::MacAddress;
+use serde::Deserialize;
+
+use crate::net::wake_on_lan::SecureOnPassword;
+
+#[derive(Debug, Deserialize)]
+pub struct RelayWakeBody {
+    pub mac_address: MacAddress,
+    pub secureon: Option<SecureOnPassword>,
+    pub port: Option<u16>,
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use serde_json::json;
+
+use crate::{
+    net::{interface_filter::FilteredNetwork, network::SystemNetwork, wake_on_lan::MagicPacket},
+    server::server_state::ServerState,
+};
+
+use super::relay_wake_body_dto::RelayWakeBody;
+
+pub fn create_router(state: Arc<ServerState>) -> Router<Arc<ServerState>> {
+    Router::new()
+        .route("/wake", post(relay_wake))
+        .with_state(state)
+}
+
+pub async fn relay_wake(
+    state: State<Arc<ServerState>>,
+    Json(payload): Json<RelayWakeBody>,
+) -> impl IntoResponse {
+    let Some(relay_config) = &state.config.relay else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "Relaying is not enabled on this runner"})),
+        );
+    };
+
+    if !relay_config.allows(payload.mac_address) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "Mac address is not in the relay allow-list"})),
+        );
+    }
+
+    let mut magic_packet = MagicPacket::new(payload.mac_address.bytes());
+    if let Some(port) = payload.port {
+        magic_packet = magic_packet.with_port(port);
+    }
+    if let Some(password) = payload.secureon {
+        magic_packet = magic_packet.with_password(password);
+    }
+
+    let network = FilteredNetwork {
+        network: SystemNetwork,
+        filter: state.config.interfaces.clone(),
+    };
+
+    println!("Relaying wake-on-lan to {}", payload.mac_address);
+    match magic_packet.send_on(&network).await {
+        Ok(()) => (StatusCode::OK, Json(json!({}))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
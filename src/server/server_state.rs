@@ -1,11 +1,18 @@
 use tokio::sync::watch;
 
-use super::{config::Config, wake::WakeProcessMap};
-use std::{collections::HashMap, sync::Mutex};
+use crate::os::boot::BootContext;
+
+use super::{
+    config::Config,
+    wake::{WakeProcessMap, WakeRunResultMap},
+};
+use std::sync::Mutex;
 
 pub struct ServerState {
     pub config: Config,
     pub wake_processes: Mutex<WakeProcessMap>,
+    pub wake_run_results: Mutex<WakeRunResultMap>,
     pub active_wake_process_count_setter: Mutex<watch::Sender<usize>>,
     pub active_wake_process_count: watch::Receiver<usize>,
+    pub boot_context: watch::Receiver<BootContext>,
 }
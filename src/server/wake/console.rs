@@ -0,0 +1,128 @@
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{broadcast, mpsc},
+};
+
+use crate::os::pty::{PtyStream, WindowSize};
+
+/// Output kept for clients attaching after the process wrote it
+const SCROLLBACK_BYTES: usize = 64 * 1024;
+
+/// Chunks of output a slow client may fall behind before it misses some
+const OUTPUT_CHUNKS: usize = 256;
+
+/// Control messages of `/wake/:id/attach`, sent as json text frames next to the binary frames
+/// carrying the terminal's input and output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AttachMessage {
+    /// From the client, its terminal changed size
+    Resize { cols: u16, rows: u16 },
+    /// From the runner, the run finished and the connection is about to close
+    Exited { exit_code: Option<i32> },
+}
+
+#[derive(Debug)]
+pub enum ConsoleInput {
+    Data(Vec<u8>),
+    Resize(WindowSize),
+}
+
+/// The terminal of a pty wake's run, which stays the same across restarts
+#[derive(Clone)]
+pub struct Console {
+    output: broadcast::Sender<Vec<u8>>,
+    input: mpsc::UnboundedSender<ConsoleInput>,
+    scrollback: Arc<Mutex<VecDeque<u8>>>,
+}
+
+/// A client's view of a [`Console`], output stops once the run finished
+pub struct Attachment {
+    pub scrollback: Vec<u8>,
+    pub output: broadcast::Receiver<Vec<u8>>,
+    pub input: mpsc::UnboundedSender<ConsoleInput>,
+}
+
+/// The run's end of a [`Console`], handed from process to process
+pub struct ConsoleHost {
+    pub console: Console,
+    input: mpsc::UnboundedReceiver<ConsoleInput>,
+    size: WindowSize,
+}
+
+impl fmt::Debug for Console {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Console")
+            .field("attached", &self.output.receiver_count())
+            .finish()
+    }
+}
+
+impl Console {
+    pub fn write_output(&self, chunk: &[u8]) {
+        // Sent while holding the lock, so attaching never misses or repeats a chunk
+        let mut scrollback = self.scrollback.lock().unwrap();
+        scrollback.extend(chunk);
+        let overflow = scrollback.len().saturating_sub(SCROLLBACK_BYTES);
+        scrollback.drain(..overflow);
+
+        let _ = self.output.send(chunk.to_vec());
+    }
+
+    pub fn attach(&self) -> Attachment {
+        let scrollback = self.scrollback.lock().unwrap();
+        Attachment {
+            scrollback: scrollback.iter().copied().collect(),
+            output: self.output.subscribe(),
+            input: self.input.clone(),
+        }
+    }
+}
+
+impl ConsoleHost {
+    pub fn open() -> ConsoleHost {
+        let (output, _) = broadcast::channel(OUTPUT_CHUNKS);
+        let (input, input_receiver) = mpsc::unbounded_channel();
+        ConsoleHost {
+            console: Console {
+                output,
+                input,
+                scrollback: Arc::new(Mutex::new(VecDeque::new())),
+            },
+            input: input_receiver,
+            size: WindowSize::default(),
+        }
+    }
+
+    /// The size the last client asked for, new processes start out with it
+    pub fn size(&self) -> WindowSize {
+        self.size
+    }
+
+    /// Writes what attached clients send to `pty`, never returns
+    pub async fn forward_input(&mut self, pty: &mut PtyStream) -> Infallible {
+        while let Some(input) = self.input.recv().await {
+            let written = match input {
+                ConsoleInput::Data(data) => pty.write_all(&data).await,
+                ConsoleInput::Resize(size) => {
+                    self.size = size;
+                    pty.resize(size)
+                }
+            };
+
+            if let Err(e) = written {
+                println!("Could not write to the console: {e:?}");
+            }
+        }
+
+        std::future::pending().await
+    }
+}
@@ -0,0 +1,126 @@
+use std::{path::Path, process::Stdio, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Hooks {
+    /// The run does not start when this fails
+    pub before: Option<Hook>,
+    pub after_success: Option<Hook>,
+    pub after_failure: Option<Hook>,
+    /// After the success or failure hook, whatever the outcome
+    pub always: Option<Hook>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Hook {
+    pub command: String,
+
+    #[serde(default)]
+    pub arguments: Vec<String>,
+}
+
+/// What hooks are told about the run, as `WAKE_*` environment variables
+#[derive(Debug, Clone)]
+pub struct HookRun<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    pub working_directory: Option<&'a str>,
+    pub log_path: &'a Path,
+    /// `None` before the run, `Some(None)` when it was killed by a signal
+    pub exit_code: Option<Option<i32>>,
+    pub duration: Option<Duration>,
+}
+
+impl HookRun<'_> {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("WAKE_RUN_ID", self.id.to_owned()),
+            ("WAKE_NAME", self.name.to_owned()),
+            ("WAKE_LOG_PATH", self.log_path.display().to_string()),
+        ];
+
+        if let Some(exit_code) = self.exit_code {
+            let exit_code = exit_code.map(|code| code.to_string()).unwrap_or_default();
+            env.push(("WAKE_EXIT_CODE", exit_code));
+        }
+        if let Some(duration) = self.duration {
+            env.push((
+                "WAKE_DURATION_SECS",
+                format!("{:.3}", duration.as_secs_f64()),
+            ));
+        }
+
+        env
+    }
+}
+
+/// A `before` hook failed, only the levels outside of it were entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeforeHookFailed {
+    /// How many levels, outermost first, had their `before` hook succeed or had none
+    pub entered: usize,
+    pub exit_code: Option<i32>,
+}
+
+/// Runs the `before` hooks outermost first, stopping at the first failure
+pub async fn run_before(hooks: &[&Hooks], run: &HookRun<'_>) -> Result<(), BeforeHookFailed> {
+    for (entered, hooks) in hooks.iter().enumerate() {
+        if let Some(hook) = &hooks.before {
+            run_hook("before", hook, run)
+                .await
+                .map_err(|exit_code| BeforeHookFailed { entered, exit_code })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the outcome and `always` hooks innermost first, failures are only logged. `hooks` are
+/// the levels that were entered, see [`BeforeHookFailed`]
+pub async fn run_after(hooks: &[&Hooks], run: &HookRun<'_>, succeeded: bool) {
+    for hooks in hooks.iter().rev() {
+        let (kind, outcome) = match succeeded {
+            true => ("after_success", &hooks.after_success),
+            false => ("after_failure", &hooks.after_failure),
+        };
+
+        for (kind, hook) in [(kind, outcome), ("always", &hooks.always)] {
+            if let Some(hook) = hook {
+                let _ = run_hook(kind, hook, run).await;
+            }
+        }
+    }
+}
+
+async fn run_hook(kind: &str, hook: &Hook, run: &HookRun<'_>) -> Result<(), Option<i32>> {
+    println!(
+        "Running {kind} hook {:?} for wake {:?}",
+        hook.command, run.name
+    );
+    let mut command = Command::new(&hook.command);
+    command.args(&hook.arguments);
+    command.envs(run.env());
+    command.stdin(Stdio::null());
+
+    if let Some(dir) = run.working_directory {
+        command.current_dir(dir);
+    }
+
+    let exit_status = match command.status().await {
+        Ok(exit_status) => exit_status,
+        Err(e) => {
+            println!("Could not run {kind} hook {:?}: {e:?}", hook.command);
+            return Err(None);
+        }
+    };
+
+    if !exit_status.success() {
+        println!("{kind} hook {:?} failed: {exit_status}", hook.command);
+        return Err(exit_status.code());
+    }
+
+    Ok(())
+}
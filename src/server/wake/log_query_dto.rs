@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct LogQuery {
+    /// Only the last this many lines
+    pub tail: Option<usize>,
+}
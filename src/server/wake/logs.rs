@@ -0,0 +1,380 @@
+use std::{
+    cmp::Reverse,
+    fs::{self, File},
+    io::{self, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{Local, SecondsFormat};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+};
+
+const MB: u64 = 1024 * 1024;
+
+/// How much of the end of a log is read at first to find the lines of `?tail=`
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Defaults to `logs` in the data directory, unlike /tmp it survives powering off
+    pub directory: Option<PathBuf>,
+
+    /// Logs are removed once they are older than this, or the newer ones add up to more
+    /// than `max_files` or `max_total_mb`
+    pub max_age_days: u64,
+    pub max_files: usize,
+    pub max_total_mb: u64,
+
+    /// A run's log is rotated to `<id>.log.1` when it grows past this, replacing the previous
+    /// one. Both are served as the run's log.
+    pub max_file_mb: u64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            max_age_days: 14,
+            max_files: 200,
+            max_total_mb: 500,
+            max_file_mb: 50,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn directory(&self) -> PathBuf {
+        match &self.directory {
+            Some(directory) => directory.clone(),
+            None => {
+                let mut path = ProjectDirs::from("com", "ngodag", "wake_runner")
+                    .unwrap()
+                    .data_dir()
+                    .to_owned();
+
+                path.push("logs");
+                path
+            }
+        }
+    }
+
+    pub fn log_path(&self, id: &str) -> PathBuf {
+        self.directory().join(format!("{id}.log"))
+    }
+}
+
+/// The log file of a single run, shared by its restarts and pipeline steps. The lines are
+/// written by a task of its own, the run never waits on the disk.
+#[derive(Debug)]
+pub struct RunLog {
+    path: PathBuf,
+    sender: mpsc::UnboundedSender<LogMessage>,
+}
+
+#[derive(Debug)]
+enum LogMessage {
+    Line(String),
+    /// Answered once the lines sent before it are written
+    Flush(oneshot::Sender<()>),
+}
+
+impl RunLog {
+    /// Makes room according to the retention settings, then starts the log of run `id`. The
+    /// logs of runs `in_use` says are still known are left alone.
+    pub async fn create(
+        config: &LogConfig,
+        id: &str,
+        in_use: impl Fn(&str) -> bool + Send + 'static,
+    ) -> RunLog {
+        let path = config.log_path(id);
+        let directory = config.directory();
+        let retention = config.clone();
+        let log_path = path.clone();
+        let file = tokio::task::spawn_blocking(move || {
+            if let Err(e) = enforce_retention(&directory, &retention, SystemTime::now(), in_use) {
+                println!("Could not clean up the logs in {directory:?}: {e:?}");
+            }
+            fs::create_dir_all(&directory).and_then(|_| File::create(&log_path))
+        })
+        .await
+        .unwrap();
+        if let Err(e) = &file {
+            println!("Could not create the log {path:?}: {e:?}");
+        }
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let max_file_bytes = config.max_file_mb * MB;
+        let file = file.ok().map(tokio::fs::File::from_std);
+        tokio::spawn(write_log(path.clone(), max_file_bytes, file, receiver));
+        RunLog { path, sender }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `line` with the current time and where it came from, `label` is the output
+    /// stream, a pipeline step or `runner` for the runner's own notes
+    pub fn write_line(&self, label: &str, line: &str) {
+        let timestamp = Local::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+        let entry = format!("{timestamp} [{label}] {line}\n");
+        let _ = self.sender.send(LogMessage::Line(entry));
+    }
+
+    /// Returns once the lines written so far are in the file
+    pub async fn flush(&self) {
+        let (flushed, receiver) = oneshot::channel();
+        if self.sender.send(LogMessage::Flush(flushed)).is_ok() {
+            let _ = receiver.await;
+        }
+    }
+}
+
+/// Writes the log at `path` until its [`RunLog`] is dropped. `file` is `None` when the log
+/// could not be written, the run goes on without it.
+async fn write_log(
+    path: PathBuf,
+    max_file_bytes: u64,
+    mut file: Option<tokio::fs::File>,
+    mut receiver: mpsc::UnboundedReceiver<LogMessage>,
+) {
+    let mut written = 0;
+    while let Some(message) = receiver.recv().await {
+        let entry = match message {
+            LogMessage::Line(entry) => entry,
+            LogMessage::Flush(flushed) => {
+                let _ = flushed.send(());
+                continue;
+            }
+        };
+
+        if written + entry.len() as u64 > max_file_bytes && written > 0 {
+            file = rotate(&path).await;
+            written = 0;
+        }
+
+        let Some(log) = &mut file else {
+            continue;
+        };
+        let result = match log.write_all(entry.as_bytes()).await {
+            Ok(()) => log.flush().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => written += entry.len() as u64,
+            Err(e) => {
+                println!("Could not write to the log {path:?}: {e:?}");
+                file = None;
+            }
+        }
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.to_owned().into_os_string();
+    rotated.push(".1");
+    rotated.into()
+}
+
+async fn rotate(path: &Path) -> Option<tokio::fs::File> {
+    let file = match tokio::fs::rename(path, rotated_path(path)).await {
+        Ok(()) => tokio::fs::File::create(path).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &file {
+        println!("Could not rotate the log {path:?}: {e:?}");
+    }
+    file.ok()
+}
+
+/// A run's log as it is served, the rotated `<id>.log.1` followed by `<id>.log`
+#[derive(Debug)]
+pub struct LogFiles {
+    /// Each file with its length when it was opened, lines written since are left out
+    files: Vec<(tokio::fs::File, u64)>,
+}
+
+impl LogFiles {
+    /// A `NotFound` error when the run has no log
+    pub async fn open(config: &LogConfig, id: &str) -> io::Result<LogFiles> {
+        let path = config.log_path(id);
+        let current = tokio::fs::File::open(&path).await?;
+        let rotated = match tokio::fs::File::open(rotated_path(&path)).await {
+            Ok(rotated) => Some(rotated),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut files = vec![];
+        for file in rotated.into_iter().chain([current]) {
+            let len = file.metadata().await?.len();
+            files.push((file, len));
+        }
+        Ok(LogFiles { files })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.files.iter().map(|(_, len)| len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads `range` of the files one after the other, only seeking to it
+    pub async fn read(&self, range: Range<u64>) -> io::Result<Box<dyn AsyncRead + Send + Unpin>> {
+        let mut reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(tokio::io::empty());
+        let mut offset = 0;
+        for (file, len) in &self.files {
+            let (start, end) = (offset, offset + len);
+            offset = end;
+            if range.end <= start || end <= range.start {
+                continue;
+            }
+
+            let skip = range.start.saturating_sub(start);
+            let mut file = file.try_clone().await?;
+            file.seek(SeekFrom::Start(skip)).await?;
+            let part = file.take(range.end.min(end) - start - skip);
+            reader = Box::new(reader.chain(part));
+        }
+        Ok(reader)
+    }
+
+    /// The last `lines` lines, read from the end in growing chunks
+    pub async fn tail(&self, lines: usize) -> io::Result<Vec<u8>> {
+        let len = self.len();
+        let mut chunk = TAIL_CHUNK;
+        loop {
+            let start = len.saturating_sub(chunk);
+            let mut content = vec![];
+            self.read(start..len)
+                .await?
+                .read_to_end(&mut content)
+                .await?;
+
+            // The chunk's first line may be cut off, unless it starts the log
+            let tail_len = tail(&content, lines).len();
+            if tail_len < content.len() || start == 0 {
+                content.drain(..content.len() - tail_len);
+                return Ok(content);
+            }
+            chunk *= 2;
+        }
+    }
+}
+
+/// The run a log or rotated log in the logs directory belongs to
+fn log_id(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_suffix(".log.1")
+        .or_else(|| name.strip_suffix(".log"))
+}
+
+/// Removes the logs in `directory` past the configured age, count or total size, the newest
+/// ones are kept. Logs of runs `in_use` returns true for are never removed, they still count
+/// towards the limits.
+pub fn enforce_retention(
+    directory: &Path,
+    config: &LogConfig,
+    now: SystemTime,
+    in_use: impl Fn(&str) -> bool,
+) -> io::Result<()> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let mut logs = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            logs.push((entry.path(), metadata.modified()?, metadata.len()));
+        }
+    }
+    logs.sort_by_key(|(_, modified, _)| Reverse(*modified));
+
+    let max_age = Duration::from_secs(config.max_age_days * 24 * 60 * 60);
+    let (mut kept, mut total) = (0, 0);
+    for (path, modified, len) in logs {
+        let age = now.duration_since(modified).unwrap_or_default();
+        let within_limits =
+            age <= max_age && kept < config.max_files && total + len <= config.max_total_mb * MB;
+        if within_limits || log_id(&path).is_some_and(&in_use) {
+            kept += 1;
+            total += len;
+            continue;
+        }
+
+        println!("Removing log {path:?}");
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// What a `Range` header asks for of a body of `len` bytes
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// Anything but a single byte range, answered with the whole body
+    Ignored,
+    Satisfiable(Range<u64>),
+    /// Starts past the end of the body
+    Unsatisfiable,
+}
+
+pub fn parse_range(header: &str, len: u64) -> RangeRequest {
+    match single_byte_range(header, len) {
+        None => RangeRequest::Ignored,
+        Some(range) if range.start < range.end => RangeRequest::Satisfiable(range),
+        Some(_) => RangeRequest::Unsatisfiable,
+    }
+}
+
+/// The bytes of a `bytes=...` header with a single range, clamped to `len`
+fn single_byte_range(header: &str, len: u64) -> Option<Range<u64>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            Some(len.saturating_sub(suffix)..len)
+        }
+        (start, "") => Some(start.parse().ok()?..len),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            (start <= end).then(|| start..end.saturating_add(1).min(len))
+        }
+    }
+}
+
+/// The last `lines` lines of `content`
+pub fn tail(content: &[u8], lines: usize) -> &[u8] {
+    let trimmed = content.strip_suffix(b"\n").unwrap_or(content);
+    let start = trimmed
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, byte)| **byte == b'\n')
+        .nth(lines.saturating_sub(1))
+        .map(|(index, _)| index + 1)
+        .unwrap_or(0);
+
+    match lines {
+        0 => &[],
+        _ => &content[start..],
+    }
+}
@@ -1,13 +1,35 @@
+pub mod console;
+pub mod hooks;
+pub mod log_query_dto;
+pub mod logs;
+pub mod output;
+pub mod pipeline;
+pub mod restart;
 pub mod router;
+pub mod schedule;
 pub mod start_wake_body_dto;
+pub mod watchdog;
 use std::collections::HashMap;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tokio::process::ChildStdout;
+use tokio_util::sync::CancellationToken;
+
+use self::{
+    console::Console,
+    hooks::Hooks,
+    output::{OutputRule, OutputState},
+    pipeline::{Step, StepRun},
+    restart::{RestartBackoff, RestartPolicy},
+    schedule::Schedule,
+    watchdog::{Termination, TerminationReason},
+};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Wake {
     name: String,
+    /// Unused by pipeline wakes
+    #[serde(default)]
     command: String,
 
     #[serde(default)]
@@ -16,13 +38,95 @@ pub struct Wake {
 
     #[serde(default)]
     arguments: Vec<String>,
+
+    /// Runs started by the runner itself while it is awake
+    #[serde(default)]
+    schedule: Vec<Schedule>,
+
+    /// Makes this a pipeline, the steps run in dependency order instead of `command`
+    #[serde(default, deserialize_with = "pipeline::deserialize_unique_steps")]
+    steps: Vec<Step>,
+
+    /// Commands run around every run of this wake, inside the global hooks
+    #[serde(default)]
+    hooks: Hooks,
+
+    /// Wall clock limit on a run, a pipeline's running steps are terminated when it is reached
+    timeout_secs: Option<u64>,
+
+    /// Terminate a run that has written nothing to stdout or stderr for this long, for a
+    /// pipeline each step on its own
+    idle_output_timeout_secs: Option<u64>,
+
+    /// How runs past their limits are stopped, pipeline steps included
+    #[serde(default)]
+    termination: Termination,
+
+    /// Keeps service-like wakes running until they are stopped through the api
+    #[serde(default)]
+    restart: RestartPolicy,
+
+    #[serde(default)]
+    restart_backoff: RestartBackoff,
+
+    /// Run the command in a pseudo terminal that clients can attach to, its output is then
+    /// all on stdout and it can be written to. Not applied to pipelines
+    #[serde(default)]
+    pty: bool,
+
+    /// Patterns marking the run ready, reporting progress, warning or failing it, not applied
+    /// to pipelines
+    #[serde(default)]
+    output_rules: Vec<OutputRule>,
 }
 
 #[derive(Debug)]
 pub struct WakeProcess {
     pub id: String,
     pub name: String,
-    pub std_out: ChildStdout,
+    pub steps: Vec<StepRun>,
+    pub restarts: u32,
+    /// Cancelled to stop the run without restarting it
+    pub stop: CancellationToken,
+    /// Set for pty wakes
+    pub console: Option<Console>,
+    pub output: OutputState,
 }
 
 pub type WakeProcessMap = HashMap<String, WakeProcess>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WakeRunResult {
+    pub id: String,
+    pub name: String,
+    /// `None` when the process was killed by a signal
+    pub exit_code: Option<i32>,
+    pub steps: Vec<StepRun>,
+    /// Set when the run was stopped by its watchdog
+    pub termination_reason: Option<TerminationReason>,
+    pub restarts: u32,
+    pub output: OutputState,
+    /// Orders the results when old ones are dropped
+    #[serde(skip)]
+    pub finished_at: DateTime<Utc>,
+}
+
+pub type WakeRunResultMap = HashMap<String, WakeRunResult>;
+
+/// Results of older runs are dropped, their logs are kept
+pub const MAX_WAKE_RUN_RESULTS: usize = 200;
+
+/// Adds `result`, dropping the oldest results past [`MAX_WAKE_RUN_RESULTS`]
+pub fn record_wake_run_result(results: &mut WakeRunResultMap, result: WakeRunResult) {
+    results.insert(result.id.clone(), result);
+    while results.len() > MAX_WAKE_RUN_RESULTS {
+        let Some(oldest) = results
+            .values()
+            .min_by_key(|result| result.finished_at)
+            .map(|result| result.id.clone())
+        else {
+            break;
+        };
+        results.remove(&oldest);
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Lines longer than this are split, so output without line breaks is not buffered forever
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// Only the latest warnings are kept in the run state, the log has all of them
+const MAX_WARNINGS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputSource {
+    Stdout,
+    Stderr,
+    /// Both streams of a pty wake, a terminal does not tell them apart
+    Pty,
+}
+
+impl OutputSource {
+    pub fn label(self) -> &'static str {
+        match self {
+            OutputSource::Stdout => "stdout",
+            OutputSource::Stderr => "stderr",
+            OutputSource::Pty => "pty",
+        }
+    }
+}
+
+/// Splits chunks of output into lines, keeping partial lines per source since the chunks of
+/// different sources interleave. Carriage returns end lines too, progress bars redraw with them.
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    partial: HashMap<OutputSource, Vec<u8>>,
+}
+
+impl LineSplitter {
+    pub fn push(&mut self, source: OutputSource, chunk: &[u8], mut on_line: impl FnMut(&str)) {
+        let partial = self.partial.entry(source).or_default();
+        for &byte in chunk {
+            if byte == b'\n' || byte == b'\r' {
+                if !partial.is_empty() {
+                    on_line(&String::from_utf8_lossy(partial));
+                    partial.clear();
+                }
+                continue;
+            }
+
+            partial.push(byte);
+            if partial.len() >= MAX_LINE_BYTES {
+                on_line(&String::from_utf8_lossy(partial));
+                partial.clear();
+            }
+        }
+    }
+
+    /// Hands over what is left of unterminated lines once the output ended
+    pub fn finish(&mut self, mut on_line: impl FnMut(OutputSource, &str)) {
+        for (source, partial) in self.partial.drain() {
+            if !partial.is_empty() {
+                on_line(source, &String::from_utf8_lossy(&partial));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// The run is up, `wake_run start --wait-ready` returns once it is
+    Ready,
+    /// The pattern's first capture group is the progress in percent
+    Progress,
+    Warning,
+    /// Terminates the run as failed, restarted under `on-failure`
+    Fail,
+}
+
+/// A regex matched against every line a run writes
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OutputRule {
+    #[serde(with = "pattern")]
+    pub pattern: Regex,
+    pub action: RuleAction,
+
+    /// Only match lines of this stream, pty wakes match either
+    pub stream: Option<OutputSource>,
+}
+
+impl OutputRule {
+    fn applies_to(&self, source: OutputSource) -> bool {
+        match self.stream {
+            None => true,
+            Some(_) if source == OutputSource::Pty => true,
+            Some(stream) => stream == source,
+        }
+    }
+}
+
+mod pattern {
+    use regex::Regex;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(pattern: &Regex, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(pattern.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map_err(de::Error::custom)
+    }
+}
+
+/// What the output rules made of a run's output so far
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OutputState {
+    /// From the start for wakes without a `ready` rule
+    pub ready: bool,
+    pub progress: Option<f64>,
+    pub warnings: Vec<String>,
+    /// The line that matched a `fail` rule
+    pub failure: Option<String>,
+}
+
+impl OutputState {
+    pub fn new(rules: &[OutputRule]) -> Self {
+        Self {
+            ready: !rules.iter().any(|rule| rule.action == RuleAction::Ready),
+            ..Self::default()
+        }
+    }
+
+    /// A restarted process has to become ready again, warnings are kept
+    pub fn restarted(&mut self, rules: &[OutputRule]) {
+        self.ready = Self::new(rules).ready;
+        self.progress = None;
+    }
+
+    /// Applies the rules matching `line`, returning the actions that changed the state
+    pub fn apply(
+        &mut self,
+        rules: &[OutputRule],
+        source: OutputSource,
+        line: &str,
+    ) -> Vec<RuleAction> {
+        let mut applied = vec![];
+        for rule in rules.iter().filter(|rule| rule.applies_to(source)) {
+            let Some(captures) = rule.pattern.captures(line) else {
+                continue;
+            };
+
+            match rule.action {
+                RuleAction::Ready if self.ready => continue,
+                RuleAction::Ready => self.ready = true,
+                RuleAction::Progress => {
+                    let progress = captures
+                        .get(1)
+                        .and_then(|progress| progress.as_str().parse::<f64>().ok());
+                    let Some(progress) = progress else {
+                        continue;
+                    };
+                    self.progress = Some(progress.clamp(0.0, 100.0));
+                }
+                RuleAction::Warning => {
+                    self.warnings.push(line.to_owned());
+                    let overflow = self.warnings.len().saturating_sub(MAX_WARNINGS);
+                    self.warnings.drain(..overflow);
+                }
+                RuleAction::Fail => self.failure = Some(line.to_owned()),
+            }
+            applied.push(rule.action);
+        }
+
+        applied
+    }
+}
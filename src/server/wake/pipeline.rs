@@ -0,0 +1,418 @@
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use tokio::{process::Command, select, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::server::server_state::ServerState;
+
+use super::{
+    logs::RunLog,
+    output::{LineSplitter, OutputSource},
+    watchdog::{self, TerminationReason, Watchdog},
+    Wake,
+};
+
+/// Only the end of a step's output is kept
+const MAX_STEP_OUTPUT: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Step {
+    name: String,
+    command: String,
+
+    #[serde(default)]
+    arguments: Vec<String>,
+
+    /// The wake's working directory when missing
+    working_directory: Option<String>,
+
+    /// Steps that have to finish before this one starts
+    #[serde(default)]
+    depends_on: Vec<String>,
+
+    /// Terminated and marked as timed out after this long
+    timeout_secs: Option<u64>,
+
+    /// A failure of this step neither fails the pipeline nor skips the steps depending on it
+    #[serde(default)]
+    continue_on_failure: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    /// Ran past its own timeout, the wake's idle output timeout or the wake's timeout
+    TimedOut,
+    /// Terminated because the run was stopped through the api
+    Stopped,
+    /// A dependency failed, is missing or depends on this step in turn
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepRun {
+    pub name: String,
+    pub status: StepStatus,
+    /// `None` when the step did not run to completion or was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Stdout and stderr interleaved by line
+    pub output: String,
+}
+
+impl StepRun {
+    fn new(step: &Step) -> Self {
+        Self {
+            name: step.name.clone(),
+            status: StepStatus::Pending,
+            exit_code: None,
+            output: String::new(),
+        }
+    }
+
+    fn append_output(&mut self, line: &str) {
+        self.output.push_str(line);
+        self.output.push('\n');
+
+        if self.output.len() > MAX_STEP_OUTPUT {
+            let mut cut = self.output.len() - MAX_STEP_OUTPUT;
+            while !self.output.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.output.drain(..cut);
+        }
+    }
+
+    /// Lets the steps depending on this one start
+    fn allows_dependents(&self, step: &Step) -> bool {
+        match self.status {
+            StepStatus::Succeeded => true,
+            StepStatus::Failed | StepStatus::TimedOut => step.continue_on_failure,
+            _ => false,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        !matches!(self.status, StepStatus::Pending | StepStatus::Running)
+    }
+}
+
+/// Steps are told apart by their names, in `depends_on` and in the state of a run. Every
+/// dependency has to name another step and the dependencies can't form a cycle.
+pub fn deserialize_unique_steps<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Step>, D::Error> {
+    let steps = Vec::<Step>::deserialize(deserializer)?;
+    for (index, step) in steps.iter().enumerate() {
+        if steps[..index].iter().any(|other| other.name == step.name) {
+            return Err(D::Error::custom(format!(
+                "duplicate step name {:?}",
+                step.name
+            )));
+        }
+    }
+
+    for step in &steps {
+        if let Some(name) = step
+            .depends_on
+            .iter()
+            .find(|name| !steps.iter().any(|other| &other.name == *name))
+        {
+            return Err(D::Error::custom(format!(
+                "step {:?} depends on unknown step {name:?}",
+                step.name
+            )));
+        }
+    }
+
+    for (index, step) in steps.iter().enumerate() {
+        if depends_on(&steps, index, index) {
+            return Err(D::Error::custom(format!(
+                "step {:?} depends on itself through a cycle",
+                step.name
+            )));
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Whether step `from` has to wait for step `target`, directly or through other steps
+fn depends_on(steps: &[Step], from: usize, target: usize) -> bool {
+    let mut visited = vec![false; steps.len()];
+    let mut stack = vec![from];
+    while let Some(index) = stack.pop() {
+        for name in &steps[index].depends_on {
+            let Some(dependency) = steps.iter().position(|other| &other.name == name) else {
+                continue;
+            };
+            if dependency == target {
+                return true;
+            }
+            if !visited[dependency] {
+                visited[dependency] = true;
+                stack.push(dependency);
+            }
+        }
+    }
+    false
+}
+
+pub fn initial_step_runs(wake: &Wake) -> Vec<StepRun> {
+    wake.steps.iter().map(StepRun::new).collect()
+}
+
+/// How a pipeline run ended
+#[derive(Debug, Clone)]
+pub struct PipelineRun {
+    /// 0 when every step succeeded or was allowed to fail, `None` when the run was terminated
+    pub exit_code: Option<i32>,
+    pub termination_reason: Option<TerminationReason>,
+    pub steps: Vec<StepRun>,
+}
+
+/// Runs the steps of `wake` as soon as their dependencies have finished, keeping their state
+/// in the wake process `id`. Once the wake's timeout is reached or `stop` is cancelled the
+/// running steps are terminated like a command would be and the pending ones skipped.
+pub async fn run_pipeline(
+    wake: &Wake,
+    id: &str,
+    state: &Arc<ServerState>,
+    log: &RunLog,
+    stop: &CancellationToken,
+) -> PipelineRun {
+    // Cancelled by `stop` and by the timeout, the steps can't tell them apart
+    let terminate = stop.child_token();
+    let deadline = wake
+        .timeout_secs
+        .map(|timeout_secs| Instant::now() + Duration::from_secs(timeout_secs));
+    let mut timed_out = false;
+
+    let steps = &wake.steps;
+    let mut runs = initial_step_runs(wake);
+    let mut running = FuturesUnordered::new();
+
+    loop {
+        for (index, step) in steps.iter().enumerate() {
+            if runs[index].status != StepStatus::Pending {
+                continue;
+            }
+
+            if terminate.is_cancelled() {
+                set_status(state, id, &mut runs, index, StepStatus::Skipped);
+                continue;
+            }
+
+            let dependencies: Option<Vec<usize>> = step
+                .depends_on
+                .iter()
+                .map(|name| steps.iter().position(|other| &other.name == name))
+                .collect();
+            // Rejected when the config is loaded, skipped in case a pipeline gets here anyway
+            let Some(dependencies) = dependencies else {
+                println!("Skipping step {:?}, unknown dependency", step.name);
+                set_status(state, id, &mut runs, index, StepStatus::Skipped);
+                continue;
+            };
+
+            let blocked = dependencies.iter().any(|&dependency| {
+                runs[dependency].is_done()
+                    && !runs[dependency].allows_dependents(&steps[dependency])
+            });
+            if blocked {
+                set_status(state, id, &mut runs, index, StepStatus::Skipped);
+                continue;
+            }
+
+            let ready = dependencies
+                .iter()
+                .all(|&dependency| runs[dependency].allows_dependents(&steps[dependency]));
+            if ready {
+                set_status(state, id, &mut runs, index, StepStatus::Running);
+                let terminate = &terminate;
+                running.push(async move {
+                    let step_run = run_step(wake, step, id, index, state, log, terminate).await;
+                    (index, step_run)
+                });
+            }
+        }
+
+        // Nothing running means whatever is still pending waits on a cycle
+        let finished = select! {
+            finished = running.next() => finished,
+            _ = sleep_until(deadline), if !timed_out => {
+                println!("Pipeline {:?} timed out", wake.name);
+                log.write_line("runner", "Pipeline timed out, terminating its steps");
+                timed_out = true;
+                terminate.cancel();
+                continue;
+            }
+        };
+        let Some((index, (exit_status, termination_reason, output))) = finished else {
+            break;
+        };
+
+        let status = match (exit_status, termination_reason) {
+            (_, Some(TerminationReason::Stopped)) if !timed_out => StepStatus::Stopped,
+            (_, Some(_)) => StepStatus::TimedOut,
+            (Ok(exit_status), None) if exit_status.success() => StepStatus::Succeeded,
+            (Ok(exit_status), None) => {
+                runs[index].exit_code = exit_status.code();
+                StepStatus::Failed
+            }
+            (Err(e), None) => {
+                println!("Could not wait for step {:?}: {e:?}", steps[index].name);
+                StepStatus::Failed
+            }
+        };
+        if status == StepStatus::Succeeded {
+            runs[index].exit_code = Some(0);
+        }
+        runs[index].output = output;
+        set_status(state, id, &mut runs, index, status);
+    }
+
+    // Cycles are rejected when the config is loaded as well
+    for index in 0..runs.len() {
+        if runs[index].status == StepStatus::Pending {
+            println!(
+                "Skipping step {:?}, its dependencies form a cycle",
+                steps[index].name
+            );
+            set_status(state, id, &mut runs, index, StepStatus::Skipped);
+        }
+    }
+
+    let termination_reason = match (stop.is_cancelled(), timed_out) {
+        (true, _) => Some(TerminationReason::Stopped),
+        (false, true) => Some(TerminationReason::Timeout),
+        (false, false) => None,
+    };
+    let succeeded = steps
+        .iter()
+        .zip(&runs)
+        .all(|(step, run)| run.allows_dependents(step));
+    let exit_code = match (termination_reason, succeeded) {
+        (Some(_), _) => None,
+        (None, true) => Some(0),
+        (None, false) => Some(1),
+    };
+
+    PipelineRun {
+        exit_code,
+        termination_reason,
+        steps: runs,
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn set_status(
+    state: &ServerState,
+    id: &str,
+    runs: &mut [StepRun],
+    index: usize,
+    status: StepStatus,
+) {
+    runs[index].status = status;
+    let exit_code = runs[index].exit_code;
+    update_step(state, id, index, |run| {
+        run.status = status;
+        run.exit_code = exit_code;
+    });
+}
+
+fn update_step(state: &ServerState, id: &str, index: usize, update: impl FnOnce(&mut StepRun)) {
+    let mut map = state.wake_processes.lock().unwrap();
+    if let Some(run) = map
+        .get_mut(id)
+        .and_then(|process| process.steps.get_mut(index))
+    {
+        update(run);
+    }
+}
+
+/// Supervised like a wake's command, in its own process group so terminating it reaches
+/// everything it started
+async fn run_step(
+    wake: &Wake,
+    step: &Step,
+    id: &str,
+    index: usize,
+    state: &ServerState,
+    log: &RunLog,
+    terminate: &CancellationToken,
+) -> (io::Result<ExitStatus>, Option<TerminationReason>, String) {
+    println!("Starting step {:?} of wake {:?}", step.name, wake.name);
+    let mut command = std::process::Command::new(&step.command);
+    command.args(&step.arguments);
+    command.stdin(Stdio::null());
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    command.process_group(0);
+
+    if let Some(dir) = step
+        .working_directory
+        .as_ref()
+        .or(wake.working_directory.as_ref())
+    {
+        command.current_dir(dir);
+    }
+
+    let mut command = Command::from(command);
+    command.kill_on_drop(true);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            let error = format!("Could not start {:?}: {e}", step.command);
+            log.write_line(&step.name, &error);
+            update_step(state, id, index, |run| run.append_output(&error));
+            return (Err(e), None, error + "\n");
+        }
+    };
+
+    let mut output = StepRun::new(step);
+    let mut handle_line = |_: OutputSource, line: &str| {
+        log.write_line(&step.name, line);
+        update_step(state, id, index, |run| run.append_output(line));
+        output.append_output(line);
+    };
+    let mut lines = LineSplitter::default();
+
+    let watchdog = Watchdog {
+        timeout: step.timeout_secs.map(Duration::from_secs),
+        idle_output_timeout: wake.idle_output_timeout_secs.map(Duration::from_secs),
+    };
+    let child_output = watchdog::piped_output(&mut child);
+    let (exit_status, termination_reason) = watchdog::supervise(
+        &mut child,
+        child_output,
+        |source, chunk| lines.push(source, chunk, |line| handle_line(source, line)),
+        watchdog,
+        &wake.termination,
+        terminate,
+    )
+    .await;
+    lines.finish(&mut handle_line);
+
+    if let Some(reason) = termination_reason {
+        println!("Step {:?} terminated: {reason:?}", step.name);
+    }
+    (exit_status, termination_reason, output.output)
+}
@@ -0,0 +1,132 @@
+use std::{collections::VecDeque, time::Duration};
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use tokio::time::Instant;
+
+use super::watchdog::TerminationReason;
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    No,
+    /// Restart when the run exits with anything but 0
+    OnFailure,
+    /// Restart whatever the exit code
+    Always,
+}
+
+impl RestartPolicy {
+    /// Runs stopped on request stay stopped, any other termination is a failure
+    pub fn should_restart(
+        self,
+        exit_code: Option<i32>,
+        termination_reason: Option<TerminationReason>,
+    ) -> bool {
+        if termination_reason == Some(TerminationReason::Stopped) {
+            return false;
+        }
+
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure => exit_code != Some(0) || termination_reason.is_some(),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RestartBackoff {
+    pub initial_delay_ms: u64,
+    #[serde(deserialize_with = "deserialize_multiplier")]
+    pub multiplier: f64,
+    pub max_delay_ms: u64,
+
+    /// Give up after this many restarts within `window_secs`
+    pub max_restarts: usize,
+    /// A run lasting this long also resets the delay to `initial_delay_ms`
+    pub window_secs: u64,
+}
+
+impl Default for RestartBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1000,
+            multiplier: 2.0,
+            max_delay_ms: 60_000,
+            max_restarts: 5,
+            window_secs: 300,
+        }
+    }
+}
+
+/// The delay must not shrink, nor grow past what a `Duration` holds in a single step
+fn deserialize_multiplier<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let multiplier = f64::deserialize(deserializer)?;
+    if !multiplier.is_finite() || multiplier < 1.0 {
+        return Err(D::Error::custom(format!(
+            "restart multiplier must be a finite number of at least 1, not {multiplier}"
+        )));
+    }
+    Ok(multiplier)
+}
+
+/// Restarts of a single wake run, deciding how long to wait before the next one
+#[derive(Debug)]
+pub struct Restarts {
+    backoff: RestartBackoff,
+    recent: VecDeque<Instant>,
+    delay: Duration,
+    pub count: u32,
+}
+
+impl Restarts {
+    pub fn new(backoff: &RestartBackoff) -> Self {
+        Self {
+            backoff: backoff.clone(),
+            recent: VecDeque::new(),
+            delay: Duration::from_millis(backoff.initial_delay_ms),
+            count: 0,
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.backoff.window_secs)
+    }
+
+    /// The delay before restarting a run that lasted `ran_for`, `None` once the limit of
+    /// restarts within the window is reached
+    pub fn next_delay(&mut self, ran_for: Duration) -> Option<Duration> {
+        let now = Instant::now();
+        let window = self.window();
+        while self
+            .recent
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) >= window)
+        {
+            self.recent.pop_front();
+        }
+
+        if self.recent.len() >= self.backoff.max_restarts {
+            return None;
+        }
+
+        if ran_for >= window {
+            self.delay = Duration::from_millis(self.backoff.initial_delay_ms);
+        }
+
+        let delay = self.delay;
+        let max_delay = Duration::from_millis(self.backoff.max_delay_ms);
+        let next = self.delay.as_secs_f64() * self.backoff.multiplier.max(1.0);
+        self.delay = Duration::try_from_secs_f64(next)
+            .unwrap_or(max_delay)
+            .min(max_delay);
+        Some(delay)
+    }
+
+    pub fn restarted(&mut self) {
+        self.recent.push_back(Instant::now());
+        self.count += 1;
+    }
+}
@@ -1,16 +1,297 @@
-use std::{process::Stdio, sync::Arc};
+use std::{
+    io,
+    os::{fd::OwnedFd, unix::process::CommandExt},
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use axum::{
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::Utc;
 use serde_json::json;
-use tokio::process::{Child, Command};
+use tokio::{
+    process::{Child, Command},
+    select,
+    sync::broadcast::error::RecvError,
+    task::JoinHandle,
+};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
 use uuid::Uuid;
 
-use crate::server::{server_state::ServerState, wake::WakeProcess};
+use crate::{
+    os::pty::{attach_to_pty, open_pty, PtyStream, WindowSize},
+    server::{
+        server_state::ServerState,
+        wake::{record_wake_run_result, WakeProcess, WakeRunResult},
+    },
+};
 
-use super::{start_wake_body_dto::StartWakeBody, Wake};
+use super::{
+    console::{AttachMessage, Attachment, ConsoleHost, ConsoleInput},
+    hooks::{self, HookRun},
+    log_query_dto::LogQuery,
+    logs::{self, LogFiles, RangeRequest, RunLog},
+    output::{LineSplitter, OutputSource, OutputState, RuleAction},
+    pipeline::{initial_step_runs, run_pipeline},
+    restart::Restarts,
+    start_wake_body_dto::StartWakeBody,
+    watchdog::{self, TerminationReason, Watchdog},
+    Wake,
+};
 
 pub fn create_router(state: Arc<ServerState>) -> Router<Arc<ServerState>> {
-    Router::new().route("/", post(wake_run)).with_state(state)
+    Router::new()
+        .route("/", get(list_wakes).post(wake_run))
+        .route("/:id", get(get_wake_run).delete(stop_wake_run))
+        .route("/:id/attach", get(attach_wake_run))
+        .route("/runs/:id/log", get(get_wake_run_log))
+        .with_state(state)
+}
+
+pub async fn get_wake_run(
+    state: State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    if let Some(process) = state.wake_processes.lock().unwrap().get(&id) {
+        return (
+            StatusCode::OK,
+            Json(json!({
+                "id": id,
+                "name": process.name,
+                "status": "running",
+                "steps": process.steps,
+                "restarts": process.restarts,
+                "ready": process.output.ready,
+                "progress": process.output.progress,
+                "warnings": process.output.warnings,
+            })),
+        );
+    }
+
+    match state.wake_run_results.lock().unwrap().get(&id) {
+        Some(result) => (
+            StatusCode::OK,
+            Json(json!({
+                "id": id,
+                "name": result.name,
+                "status": "exited",
+                "exit_code": result.exit_code,
+                "steps": result.steps,
+                "termination_reason": result.termination_reason,
+                "restarts": result.restarts,
+                "ready": result.output.ready,
+                "progress": result.output.progress,
+                "warnings": result.output.warnings,
+                "failure": result.output.failure,
+                "finished_at": result.finished_at.to_rfc3339(),
+            })),
+        ),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "No wake run with that id"})),
+        ),
+    }
+}
+
+/// Stops a running wake for good, it is not restarted whatever its restart policy
+pub async fn stop_wake_run(
+    state: State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.wake_processes.lock().unwrap().get(&id) {
+        Some(process) => {
+            println!("Stopping wake {:?} with id: {:?}", process.name, id);
+            process.stop.cancel();
+            (
+                StatusCode::OK,
+                Json(json!({ "id": id, "status": "stopping" })),
+            )
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "No running wake with that id"})),
+        ),
+    }
+}
+
+/// The log of a run, also of runs from before the last boot, with the part rotated out of
+/// it first. Supports a single byte range and `?tail=<lines>`.
+pub async fn get_wake_run_log(
+    state: State<Arc<ServerState>>,
+    Path(id): Path<String>,
+    Query(query): Query<LogQuery>,
+    headers: HeaderMap,
+) -> Response {
+    // The id ends up in a path
+    if Uuid::parse_str(&id).is_err() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Not a wake run id"})),
+        )
+            .into_response();
+    }
+
+    let files = match LogFiles::open(&state.config.logs, &id).await {
+        Ok(files) => files,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": "No log for a wake run with that id"})),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            println!("Could not open the log of {id:?}: {e:?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let text = [
+        (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+        (header::ACCEPT_RANGES, "bytes"),
+    ];
+    if let Some(lines) = query.tail {
+        return match files.tail(lines).await {
+            Ok(content) => (text, content).into_response(),
+            Err(e) => {
+                println!("Could not read the log of {id:?}: {e:?}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+    }
+
+    let len = files.len();
+    let range = match headers.get(header::RANGE).map(|range| range.to_str()) {
+        Some(Ok(range)) => logs::parse_range(range, len),
+        _ => RangeRequest::Ignored,
+    };
+    let (status, range) = match range {
+        RangeRequest::Ignored => (StatusCode::OK, 0..len),
+        RangeRequest::Satisfiable(range) => (StatusCode::PARTIAL_CONTENT, range),
+        RangeRequest::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+            )
+                .into_response()
+        }
+    };
+
+    let reader = match files.read(range.clone()).await {
+        Ok(reader) => reader,
+        Err(e) => {
+            println!("Could not read the log of {id:?}: {e:?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let body = Body::from_stream(ReaderStream::new(reader));
+    let content_length = [(
+        header::CONTENT_LENGTH,
+        (range.end - range.start).to_string(),
+    )];
+    match status {
+        StatusCode::PARTIAL_CONTENT => {
+            let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+            let content_range = [(header::CONTENT_RANGE, content_range)];
+            (status, text, content_length, content_range, body).into_response()
+        }
+        _ => (status, text, content_length, body).into_response(),
+    }
+}
+
+/// Connects a client to the terminal of a running pty wake, see [`AttachMessage`]
+pub async fn attach_wake_run(
+    state: State<Arc<ServerState>>,
+    Path(id): Path<String>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let attachment = match state.wake_processes.lock().unwrap().get(&id) {
+        Some(WakeProcess {
+            console: Some(console),
+            ..
+        }) => console.attach(),
+        Some(_) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({"error": "The wake does not run in a pty"})),
+            )
+                .into_response()
+        }
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": "No running wake with that id"})),
+            )
+                .into_response()
+        }
+    };
+
+    println!("Attaching to wake run {id:?}");
+    let state = state.0.clone();
+    upgrade.on_upgrade(move |socket| attached(socket, attachment, state, id))
+}
+
+async fn attached(
+    mut socket: WebSocket,
+    attachment: Attachment,
+    state: Arc<ServerState>,
+    id: String,
+) {
+    let Attachment {
+        scrollback,
+        mut output,
+        input,
+    } = attachment;
+    if !scrollback.is_empty() && socket.send(Message::Binary(scrollback)).await.is_err() {
+        return;
+    }
+
+    loop {
+        select! {
+            chunk = output.recv() => match chunk {
+                Ok(chunk) => {
+                    if socket.send(Message::Binary(chunk)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => println!("Attached client of {id:?} missed {skipped} chunks"),
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Binary(data))) => {
+                    let _ = input.send(ConsoleInput::Data(data));
+                }
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(AttachMessage::Resize { cols, rows }) => {
+                        let _ = input.send(ConsoleInput::Resize(WindowSize { cols, rows }));
+                    }
+                    _ => println!("Ignoring attach message {text:?}"),
+                },
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+            },
+        }
+    }
+
+    let exit_code = state
+        .wake_run_results
+        .lock()
+        .unwrap()
+        .get(&id)
+        .and_then(|result| result.exit_code);
+    let exited = serde_json::to_string(&AttachMessage::Exited { exit_code }).unwrap();
+    let _ = socket.send(Message::Text(exited)).await;
+    let _ = socket.close().await;
 }
 
 pub async fn wake_run(
@@ -25,51 +306,372 @@ pub async fn wake_run(
 
     match maybe_wake {
         Some(wake) => {
-            let mut command = construct_wake_command(&wake);
-            let id = Uuid::new_v4().to_string();
+            let (id, _) = start_wake(&state, wake);
+            (StatusCode::OK, Json(json!({ "id": id })))
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "No wake with that id"})),
+        ),
+    }
+}
 
-            println!("Starting wake: {:?} with id: {:?}", wake.name, id);
-            let mut process = command.spawn().unwrap();
+/// Every configured wake with the next time each of its schedules fires
+pub async fn list_wakes(state: State<Arc<ServerState>>) -> impl IntoResponse {
+    let now = Utc::now();
+    let wakes: Vec<_> = state
+        .config
+        .wakes
+        .iter()
+        .map(|wake| {
+            let schedule: Vec<_> = wake
+                .schedule
+                .iter()
+                .map(|schedule| {
+                    json!({
+                        "cron": schedule.cron,
+                        "timezone": schedule.timezone,
+                        "missed": schedule.missed,
+                        "next_fire": schedule.next_fire(now).map(|fire| fire.to_rfc3339()),
+                    })
+                })
+                .collect();
+
+            json!({ "name": wake.name, "schedule": schedule })
+        })
+        .collect();
 
-            {
-                let lock = state.active_wake_process_count_setter.lock().unwrap();
-                lock.send_modify(|val| *val += 1);
+    Json(wakes)
+}
+
+/// Spawns `wake` and tracks it until it exits, the handle finishes when it has
+pub fn start_wake(state: &Arc<ServerState>, wake: &Wake) -> (String, JoinHandle<()>) {
+    let id = Uuid::new_v4().to_string();
+    println!("Starting wake: {:?} with id: {:?}", wake.name, id);
+    let (stop, console) = register_wake_process(state, wake, &id);
+
+    let finished = tokio::spawn(run_wake(
+        wake.clone(),
+        id.clone(),
+        state.clone(),
+        stop,
+        console,
+    ));
+    (id, finished)
+}
+
+fn register_wake_process(
+    state: &ServerState,
+    wake: &Wake,
+    id: &str,
+) -> (CancellationToken, Option<ConsoleHost>) {
+    {
+        let lock = state.active_wake_process_count_setter.lock().unwrap();
+        lock.send_modify(|val| *val += 1);
+    }
+
+    let console = (wake.pty && wake.steps.is_empty()).then(ConsoleHost::open);
+    let wake_process = WakeProcess {
+        name: wake.name.clone(),
+        id: id.to_owned(),
+        steps: initial_step_runs(wake),
+        restarts: 0,
+        stop: CancellationToken::new(),
+        console: console.as_ref().map(|host| host.console.clone()),
+        output: OutputState::new(&wake.output_rules),
+    };
+    let stop = wake_process.stop.clone();
+
+    {
+        let mut map = state.wake_processes.lock().unwrap();
+        map.insert(id.to_owned(), wake_process);
+    }
+
+    (stop, console)
+}
+
+/// The global hooks wrap the wake's own hooks, which wrap the command or pipeline and its restarts
+async fn run_wake(
+    wake: Wake,
+    id: String,
+    state: Arc<ServerState>,
+    stop: CancellationToken,
+    mut console: Option<ConsoleHost>,
+) {
+    let known_ids: Vec<String> = state
+        .wake_processes
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    let log = RunLog::create(&state.config.logs, &id, move |id| {
+        known_ids.iter().any(|known| known == id)
+    })
+    .await;
+    let hooks = [&state.config.hooks, &wake.hooks];
+    let mut hook_run = HookRun {
+        id: &id,
+        name: &wake.name,
+        working_directory: wake.working_directory.as_deref(),
+        log_path: log.path(),
+        exit_code: None,
+        duration: None,
+    };
+
+    let started = Instant::now();
+    let before = hooks::run_before(&hooks, &hook_run).await;
+    let (exit_code, termination_reason, entered) = match before {
+        Err(failed) => {
+            log.write_line(
+                "runner",
+                &format!(
+                    "A before hook failed with {:?}, the run did not start",
+                    failed.exit_code
+                ),
+            );
+            (
+                None,
+                Some(TerminationReason::BeforeHookFailed),
+                failed.entered,
+            )
+        }
+        Ok(()) => {
+            let (exit_code, termination_reason) =
+                run_with_restarts(&wake, &id, &state, &stop, &log, &mut console).await;
+            (exit_code, termination_reason, hooks.len())
+        }
+    };
+
+    hook_run.exit_code = Some(exit_code);
+    hook_run.duration = Some(started.elapsed());
+    // The after hooks get to read the whole log
+    log.flush().await;
+    hooks::run_after(&hooks[..entered], &hook_run, exit_code == Some(0)).await;
+
+    finish_wake_process(exit_code, termination_reason, id.clone(), state);
+}
+
+async fn run_with_restarts(
+    wake: &Wake,
+    id: &str,
+    state: &Arc<ServerState>,
+    stop: &CancellationToken,
+    log: &RunLog,
+    console: &mut Option<ConsoleHost>,
+) -> (Option<i32>, Option<TerminationReason>) {
+    let mut restarts = Restarts::new(&wake.restart_backoff);
+    loop {
+        let started = Instant::now();
+        let (exit_code, termination_reason) = match wake.steps.is_empty() {
+            true => run_command(wake, id, state, console.as_mut(), log, stop).await,
+            false => {
+                let pipeline = run_pipeline(wake, id, state, log, stop).await;
+                (pipeline.exit_code, pipeline.termination_reason)
             }
+        };
 
-            let output = process.stdout.take().unwrap();
+        let outcome = match (exit_code, termination_reason) {
+            (_, Some(reason)) => format!("Terminated: {reason:?}"),
+            (Some(exit_code), None) => format!("Exited with {exit_code}"),
+            (None, None) => "Exited without an exit code".to_owned(),
+        };
+        log.write_line("runner", &outcome);
 
-            let wake_process = WakeProcess {
-                name: wake.name.clone(),
-                id: id.clone(),
-                std_out: output,
-            };
+        if stop.is_cancelled() || !wake.restart.should_restart(exit_code, termination_reason) {
+            return (exit_code, termination_reason);
+        }
+
+        let Some(delay) = restarts.next_delay(started.elapsed()) else {
+            println!(
+                "Wake {:?} restarted too often, giving up after exit code {exit_code:?}",
+                wake.name
+            );
+            log.write_line("runner", "Restarted too often, giving up");
+            return (exit_code, termination_reason);
+        };
+
+        println!(
+            "Wake {:?} exited with {exit_code:?}, restarting in {delay:?}",
+            wake.name
+        );
+        log.write_line("runner", &format!("Restarting in {delay:?}"));
+        select! {
+            _ = stop.cancelled() => return (exit_code, Some(TerminationReason::Stopped)),
+            _ = tokio::time::sleep(delay) => {},
+        }
+
+        restarts.restarted();
+        let mut map = state.wake_processes.lock().unwrap();
+        if let Some(process) = map.get_mut(id) {
+            process.restarts = restarts.count;
+            process.steps = initial_step_runs(wake);
+            process.output.restarted(&wake.output_rules);
+        }
+    }
+}
+
+async fn run_command(
+    wake: &Wake,
+    id: &str,
+    state: &ServerState,
+    console: Option<&mut ConsoleHost>,
+    log: &RunLog,
+    stop: &CancellationToken,
+) -> (Option<i32>, Option<TerminationReason>) {
+    let watchdog = Watchdog {
+        timeout: wake.timeout_secs.map(Duration::from_secs),
+        idle_output_timeout: wake.idle_output_timeout_secs.map(Duration::from_secs),
+    };
 
-            {
-                let mut map = state.wake_processes.lock().unwrap();
-                map.insert(id.clone(), wake_process);
+    // Cancelled by a `fail` output rule, unlike `stop` the run is only over when the
+    // restart policy says so
+    let fail = stop.child_token();
+    let handle_line = |source: OutputSource, line: &str| {
+        log.write_line(source.label(), line);
+        if wake.output_rules.is_empty() {
+            return;
+        }
+
+        let applied = match state.wake_processes.lock().unwrap().get_mut(id) {
+            Some(process) => process.output.apply(&wake.output_rules, source, line),
+            None => return,
+        };
+        for action in applied {
+            match action {
+                RuleAction::Ready => {
+                    println!("Wake {:?} is ready", wake.name);
+                    log.write_line("runner", "Ready");
+                }
+                RuleAction::Fail => {
+                    println!("Wake {:?} failed: {line:?}", wake.name);
+                    log.write_line("runner", "Output matched a fail rule");
+                    fail.cancel();
+                }
+                RuleAction::Progress | RuleAction::Warning => {}
             }
+        }
+    };
+    let mut lines = LineSplitter::default();
+    let mut handle_output = |source: OutputSource, chunk: &[u8]| {
+        lines.push(source, chunk, |line| handle_line(source, line))
+    };
 
-            tokio::spawn(handle_wake_process(process, id.clone(), state.0.clone()));
-            (StatusCode::OK, Json(json!({ "id": id })))
+    let supervised = match console {
+        None => {
+            let Some(mut process) = spawn_wake_command(wake, None, log) else {
+                return (None, None);
+            };
+            let output = watchdog::piped_output(&mut process);
+            watchdog::supervise(
+                &mut process,
+                output,
+                &mut handle_output,
+                watchdog,
+                &wake.termination,
+                &fail,
+            )
+            .await
+        }
+        Some(console) => {
+            let Some((mut process, pty, mut input)) = spawn_in_pty(wake, console, log) else {
+                return (None, None);
+            };
+            let output = console.console.clone();
+            select! {
+                supervised = watchdog::supervise(
+                    &mut process,
+                    vec![(OutputSource::Pty, Box::pin(pty))],
+                    |source, chunk| {
+                        output.write_output(chunk);
+                        handle_output(source, chunk);
+                    },
+                    watchdog,
+                    &wake.termination,
+                    &fail,
+                ) => supervised,
+                never = console.forward_input(&mut input) => match never {},
+            }
+        }
+    };
+    lines.finish(handle_line);
+
+    let (exit_status, mut termination_reason) = supervised;
+    if fail.is_cancelled() && !stop.is_cancelled() {
+        termination_reason = Some(TerminationReason::FailedOutput);
+    }
+    match exit_status {
+        Ok(exit_status) => (exit_status.code(), termination_reason),
+        Err(e) => {
+            println!("Could not wait for wake {:?}: {e:?}", wake.name);
+            log.write_line("runner", &format!("Could not wait for the process: {e}"));
+            (None, termination_reason)
         }
-        None => (
-            StatusCode::NOT_FOUND,
-            Json(json!({"error": "No wake with that id"})),
-        ),
     }
 }
 
-async fn handle_wake_process(
-    mut wake_process: Child,
+/// The process and the pty twice, once for reading its output and once for writing its input
+fn spawn_in_pty(
+    wake: &Wake,
+    console: &ConsoleHost,
+    log: &RunLog,
+) -> Option<(Child, PtyStream, PtyStream)> {
+    let opened =
+        open_pty(console.size()).and_then(|(pty, slave)| Ok((pty.try_clone()?, pty, slave)));
+    let (input, pty, slave) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            println!("Could not open a pty for wake {:?}: {e:?}", wake.name);
+            log.write_line("runner", &format!("Could not open a pty: {e}"));
+            return None;
+        }
+    };
+
+    let process = spawn_wake_command(wake, Some(&slave), log);
+    // Once only the process has the slave open, reading the pty ends when it exits
+    drop(slave);
+    Some((process?, pty, input))
+}
+
+fn spawn_wake_command(wake: &Wake, pty_slave: Option<&OwnedFd>, log: &RunLog) -> Option<Child> {
+    let spawned = construct_wake_command(wake, pty_slave).and_then(|mut command| command.spawn());
+    match spawned {
+        Ok(process) => Some(process),
+        Err(e) => {
+            println!("Could not start wake {:?}: {e:?}", wake.name);
+            log.write_line(
+                "runner",
+                &format!("Could not start {:?}: {e}", wake.command),
+            );
+            None
+        }
+    }
+}
+
+fn finish_wake_process(
+    exit_code: Option<i32>,
+    termination_reason: Option<TerminationReason>,
     wake_process_id: String,
     app_state: Arc<ServerState>,
 ) {
-    wake_process.wait().await.unwrap();
-
     {
         let mut map = app_state.wake_processes.lock().unwrap();
-        map.remove(&wake_process_id);
-        dbg!(&map);
+        let process = map.remove(&wake_process_id);
+
+        if let Some(process) = process {
+            let result = WakeRunResult {
+                id: wake_process_id.clone(),
+                name: process.name,
+                exit_code,
+                steps: process.steps,
+                termination_reason,
+                restarts: process.restarts,
+                output: process.output,
+                finished_at: Utc::now(),
+            };
+            let mut results = app_state.wake_run_results.lock().unwrap();
+            record_wake_run_result(&mut results, result);
+        }
     }
 
     {
@@ -80,18 +682,25 @@ async fn handle_wake_process(
     println!("Wake {:?} exited", wake_process_id)
 }
 
-fn construct_wake_command(wake: &Wake) -> Command {
+fn construct_wake_command(wake: &Wake, pty_slave: Option<&OwnedFd>) -> io::Result<Command> {
     println!("Constructing command: {wake:?}");
-    let mut command = Command::new(&wake.command);
-    command.stdin(Stdio::null());
-    command.stdout(Stdio::piped());
-    command.stderr(Stdio::piped());
-
+    let mut command = std::process::Command::new(&wake.command);
     command.args(&wake.arguments);
 
     if let Some(dir) = &wake.working_directory {
         command.current_dir(dir);
     }
 
-    command
+    match pty_slave {
+        Some(slave) => attach_to_pty(&mut command, slave)?,
+        None => {
+            command.stdin(Stdio::null());
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+            // Its own process group, so the watchdog can stop everything it started
+            command.process_group(0);
+        }
+    }
+
+    Ok(Command::from(command))
 }
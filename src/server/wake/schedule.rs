@@ -0,0 +1,444 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local, Utc};
+use chrono_tz::Tz;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs, select,
+    sync::{mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::server::server_state::ServerState;
+
+use super::{router::start_wake, Wake};
+
+/// A scheduled run closer than this keeps the machine from shutting down
+pub const IMMINENT_RUN_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// The scheduler re-reads the clock at least this often, so suspends and clock changes are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Upper bound on runs started for a single schedule by [`MissedRunPolicy::RunAll`]
+pub const MAX_CATCH_UP_RUNS: usize = 100;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Schedule {
+    /// Cron expression, either the usual 5 fields or 6-7 fields starting with seconds
+    pub cron: String,
+
+    /// IANA name such as `Europe/Stockholm`, the local timezone when missing
+    pub timezone: Option<String>,
+
+    /// What to do about runs that were due while the machine was off
+    #[serde(default)]
+    pub missed: MissedRunPolicy,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedRunPolicy {
+    #[default]
+    Skip,
+    /// A single run on the next boot, however many were missed
+    RunOnce,
+    /// Every missed run, one after another
+    RunAll,
+}
+
+impl Schedule {
+    fn parse(&self) -> Result<ParsedSchedule, String> {
+        // The cron crate wants a seconds field
+        let expression = match self.cron.split_whitespace().count() {
+            5 => format!("0 {}", self.cron),
+            _ => self.cron.clone(),
+        };
+        let cron = cron::Schedule::from_str(&expression)
+            .map_err(|e| format!("invalid cron expression {:?}: {e}", self.cron))?;
+
+        let timezone = match &self.timezone {
+            Some(name) => Some(
+                name.parse::<Tz>()
+                    .map_err(|e| format!("invalid timezone {name:?}: {e}"))?,
+            ),
+            None => None,
+        };
+
+        Ok(ParsedSchedule { cron, timezone })
+    }
+
+    /// The first time this schedule fires after `after`, `None` when it is invalid or never fires again
+    pub fn next_fire(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.parse().ok()?.next_fire(after)
+    }
+
+    /// How far this schedule of `wake` is handled once each of its catch-up runs finished, see
+    /// [`ParsedSchedule::catch_up_progress`]
+    pub fn catch_up_progress(
+        &self,
+        wake: &str,
+        state: &ScheduleState,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>, String> {
+        let handled_until = state.handled_until(wake, &self.cron);
+        Ok(self
+            .parse()?
+            .catch_up_progress(self.missed, handled_until, now))
+    }
+
+    /// The runs this schedule of `wake` missed between what `state` last handled and `now`
+    pub fn missed_runs(
+        &self,
+        wake: &str,
+        state: &ScheduleState,
+        now: DateTime<Utc>,
+    ) -> Result<MissedRuns, String> {
+        let handled_until = state.handled_until(wake, &self.cron);
+        Ok(self.parse()?.missed_runs(self.missed, handled_until, now))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MissedRuns {
+    pub missed: usize,
+    /// How many of the missed runs the policy makes up for
+    pub runs: usize,
+}
+
+struct ParsedSchedule {
+    cron: cron::Schedule,
+    timezone: Option<Tz>,
+}
+
+impl ParsedSchedule {
+    fn next_fire(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.fires_after(after).next()
+    }
+
+    fn fires_after(&self, after: DateTime<Utc>) -> Box<dyn Iterator<Item = DateTime<Utc>> + '_> {
+        match self.timezone {
+            Some(timezone) => Box::new(
+                self.cron
+                    .after(&after.with_timezone(&timezone))
+                    .map(|fire| fire.with_timezone(&Utc)),
+            ),
+            None => Box::new(
+                self.cron
+                    .after(&after.with_timezone(&Local))
+                    .map(|fire| fire.with_timezone(&Utc)),
+            ),
+        }
+    }
+
+    /// Fire times in `(since, until]`
+    fn fires_between(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        self.fires_after(since)
+            .take_while(|fire| *fire <= until)
+            .take(MAX_CATCH_UP_RUNS)
+            .collect()
+    }
+
+    fn missed_runs(
+        &self,
+        policy: MissedRunPolicy,
+        handled_until: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> MissedRuns {
+        // A schedule seen for the first time has not missed anything
+        let Some(handled_until) = handled_until else {
+            return MissedRuns::default();
+        };
+
+        let missed = self.fires_between(handled_until, now).len();
+        let runs = match policy {
+            MissedRunPolicy::Skip => 0,
+            MissedRunPolicy::RunOnce => missed.min(1),
+            MissedRunPolicy::RunAll => missed,
+        };
+        MissedRuns { missed, runs }
+    }
+
+    /// For each catch-up run, how far the schedule is handled once it finished: the missed
+    /// fire it made up for, and for the last one everything up to `now`. The missed runs left
+    /// when the machine goes off in between are caught up on the next boot.
+    fn catch_up_progress(
+        &self,
+        policy: MissedRunPolicy,
+        handled_until: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Vec<DateTime<Utc>> {
+        let MissedRuns { runs, .. } = self.missed_runs(policy, handled_until, now);
+        let Some(handled_until) = handled_until.filter(|_| runs > 0) else {
+            return vec![];
+        };
+
+        let mut progress: Vec<_> = self
+            .fires_between(handled_until, now)
+            .into_iter()
+            .take(runs - 1)
+            .collect();
+        progress.push(now);
+        progress
+    }
+}
+
+/// How far each schedule has been handled, kept across power cycles to find missed runs
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ScheduleState {
+    #[serde(default)]
+    entries: Vec<ScheduleRecord>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ScheduleRecord {
+    wake: String,
+    cron: String,
+    /// Seconds since the unix epoch, every fire up to here has been run or skipped
+    handled_until: i64,
+}
+
+impl ScheduleState {
+    pub fn handled_until(&self, wake: &str, cron: &str) -> Option<DateTime<Utc>> {
+        let record = self
+            .entries
+            .iter()
+            .find(|record| record.wake == wake && record.cron == cron)?;
+        DateTime::from_timestamp(record.handled_until, 0)
+    }
+
+    pub fn set_handled_until(&mut self, wake: &str, cron: &str, until: DateTime<Utc>) {
+        self.entries
+            .retain(|record| record.wake != wake || record.cron != cron);
+        self.entries.push(ScheduleRecord {
+            wake: wake.to_owned(),
+            cron: cron.to_owned(),
+            handled_until: until.timestamp(),
+        });
+    }
+}
+
+pub fn schedule_state_path() -> PathBuf {
+    let mut path = ProjectDirs::from("com", "ngodag", "wake_runner")
+        .unwrap()
+        .data_dir()
+        .to_owned();
+
+    path.push("schedule_state.toml");
+    path
+}
+
+async fn load_schedule_state() -> ScheduleState {
+    let Ok(state_str) = fs::read_to_string(schedule_state_path()).await else {
+        return ScheduleState::default();
+    };
+
+    match toml::from_str(&state_str) {
+        Ok(state) => state,
+        Err(e) => {
+            println!("Ignoring broken schedule state: {e}");
+            ScheduleState::default()
+        }
+    }
+}
+
+async fn save_schedule_state(state: &ScheduleState) {
+    let path = schedule_state_path();
+    let result = async {
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        fs::write(&path, toml::to_string(state).unwrap()).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        println!("Could not save schedule state to {path:?}: {e:?}");
+    }
+}
+
+/// The first time any schedule of `wakes` fires after `after`
+pub fn next_scheduled_fire(wakes: &[Wake], after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    wakes
+        .iter()
+        .flat_map(|wake| &wake.schedule)
+        .filter_map(|schedule| schedule.next_fire(after))
+        .min()
+}
+
+struct ScheduledWake<'a> {
+    wake: &'a Wake,
+    schedule: &'a Schedule,
+    parsed: ParsedSchedule,
+}
+
+/// Starts the wakes of every valid configured schedule when they are due, catching up on runs
+/// missed while the machine was off. `run_imminent` is kept true while a run is close.
+pub async fn run_scheduler(
+    state: Arc<ServerState>,
+    run_imminent: watch::Sender<bool>,
+    stop: CancellationToken,
+) {
+    let config_state = state.clone();
+    let scheduled: Vec<ScheduledWake> = config_state
+        .config
+        .wakes
+        .iter()
+        .flat_map(|wake| wake.schedule.iter().map(move |schedule| (wake, schedule)))
+        .filter_map(|(wake, schedule)| match schedule.parse() {
+            Ok(parsed) => Some(ScheduledWake {
+                wake,
+                schedule,
+                parsed,
+            }),
+            Err(e) => {
+                println!("Not scheduling wake {:?}: {e}", wake.name);
+                None
+            }
+        })
+        .collect();
+
+    if scheduled.is_empty() {
+        // Nothing will ever be imminent, but dropping the sender would read as constant change
+        select! {
+            _ = stop.cancelled() => {},
+            _ = run_imminent.closed() => {},
+        }
+        return;
+    }
+
+    let mut schedule_state = load_schedule_state().await;
+    let (caught_up_sender, mut caught_up) = mpsc::unbounded_channel();
+    // Fires handled while a schedule is still catching up, recorded once it is done
+    let mut catching_up: HashMap<usize, DateTime<Utc>> = HashMap::new();
+    let now = Utc::now();
+    for (index, entry) in scheduled.iter().enumerate() {
+        match catch_up(
+            &state,
+            index,
+            entry,
+            &schedule_state,
+            now,
+            &caught_up_sender,
+        ) {
+            true => {
+                catching_up.insert(index, now);
+            }
+            false => schedule_state.set_handled_until(&entry.wake.name, &entry.schedule.cron, now),
+        }
+    }
+    save_schedule_state(&schedule_state).await;
+
+    let mut handled_until = now;
+    loop {
+        let now = Utc::now();
+        let mut fired = false;
+        for (index, entry) in scheduled.iter().enumerate() {
+            if entry.parsed.fires_between(handled_until, now).is_empty() {
+                continue;
+            }
+
+            println!(
+                "Scheduled run of wake {:?} ({})",
+                entry.wake.name, entry.schedule.cron
+            );
+            start_wake(&state, entry.wake);
+            match catching_up.get_mut(&index) {
+                Some(deferred) => *deferred = now,
+                None => {
+                    schedule_state.set_handled_until(&entry.wake.name, &entry.schedule.cron, now)
+                }
+            }
+            fired = true;
+        }
+        handled_until = now;
+
+        if fired {
+            save_schedule_state(&schedule_state).await;
+        }
+
+        let until_next = scheduled
+            .iter()
+            .filter_map(|entry| entry.parsed.next_fire(now))
+            .min()
+            .and_then(|next| (next - now).to_std().ok());
+
+        let imminent = until_next.is_some_and(|until_next| until_next <= IMMINENT_RUN_WINDOW);
+        run_imminent.send_if_modified(|current| std::mem::replace(current, imminent) != imminent);
+
+        // Wake up when the next run enters the imminent window, and again when it is due
+        let sleep = match until_next {
+            Some(until_next) if until_next > IMMINENT_RUN_WINDOW => {
+                until_next - IMMINENT_RUN_WINDOW
+            }
+            Some(until_next) => until_next,
+            None => MAX_SLEEP,
+        }
+        .min(MAX_SLEEP);
+
+        select! {
+            _ = stop.cancelled() => break,
+            _ = tokio::time::sleep(sleep) => {},
+            Some(progress) = caught_up.recv() => {
+                let entry = &scheduled[progress.entry];
+                let mut until = progress.handled_until;
+                if progress.done {
+                    if let Some(deferred) = catching_up.remove(&progress.entry) {
+                        until = until.max(deferred);
+                    }
+                }
+                schedule_state.set_handled_until(&entry.wake.name, &entry.schedule.cron, until);
+                save_schedule_state(&schedule_state).await;
+            }
+        }
+    }
+}
+
+/// How far a finished catch-up run got the schedule `entry` handled
+struct CaughtUp {
+    entry: usize,
+    handled_until: DateTime<Utc>,
+    /// The schedule's last catch-up run
+    done: bool,
+}
+
+/// Starts the runs `entry` missed since it was last handled one after another as its policy
+/// asks, reporting each finished one to `caught_up`. False when there is nothing to catch up on.
+fn catch_up(
+    state: &Arc<ServerState>,
+    index: usize,
+    entry: &ScheduledWake,
+    schedule_state: &ScheduleState,
+    now: DateTime<Utc>,
+    caught_up: &mpsc::UnboundedSender<CaughtUp>,
+) -> bool {
+    let handled_until = schedule_state.handled_until(&entry.wake.name, &entry.schedule.cron);
+    let policy = entry.schedule.missed;
+    let MissedRuns { missed, runs } = entry.parsed.missed_runs(policy, handled_until, now);
+    let progress = entry.parsed.catch_up_progress(policy, handled_until, now);
+
+    if missed > 0 {
+        println!(
+            "Wake {:?} missed {missed} scheduled runs ({}), running {runs}",
+            entry.wake.name, entry.schedule.cron
+        );
+    }
+    if progress.is_empty() {
+        return false;
+    }
+
+    let state = state.clone();
+    let wake = entry.wake.clone();
+    let caught_up = caught_up.clone();
+    tokio::spawn(async move {
+        let runs = progress.len();
+        for (run, handled_until) in progress.into_iter().enumerate() {
+            let (_, finished) = start_wake(&state, &wake);
+            let _ = finished.await;
+            let _ = caught_up.send(CaughtUp {
+                entry: index,
+                handled_until,
+                done: run + 1 == runs,
+            });
+        }
+    });
+    true
+}
@@ -0,0 +1,188 @@
+use std::{io, pin::Pin, process::ExitStatus, time::Duration};
+
+use futures::{stream::select_all, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncRead, process::Child, select, time::Instant};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+
+use super::output::OutputSource;
+
+/// Processes the wake started in the background can keep its output open after it exited
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Termination {
+    /// Sent to the process group in order, SIGKILL follows when the last one is not enough
+    pub signals: Vec<String>,
+
+    /// Time given to exit after each signal
+    pub grace_secs: u64,
+}
+
+impl Default for Termination {
+    fn default() -> Self {
+        Self {
+            signals: vec!["SIGTERM".to_owned()],
+            grace_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TerminationReason {
+    /// Ran for longer than `timeout_secs`
+    Timeout,
+    /// Wrote nothing for `idle_output_timeout_secs`
+    IdleOutput,
+    /// Stopped through the api
+    Stopped,
+    /// Wrote a line matching a `fail` output rule
+    FailedOutput,
+    /// A `before` hook failed, the run never started
+    BeforeHookFailed,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Watchdog {
+    pub timeout: Option<Duration>,
+    pub idle_output_timeout: Option<Duration>,
+}
+
+/// Something a supervised process writes to
+pub type OutputStream = (OutputSource, Pin<Box<dyn AsyncRead + Send>>);
+
+/// The piped stdout and stderr of `child`
+pub fn piped_output(child: &mut Child) -> Vec<OutputStream> {
+    let mut output: Vec<OutputStream> = vec![];
+    if let Some(stdout) = child.stdout.take() {
+        output.push((OutputSource::Stdout, Box::pin(stdout)));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        output.push((OutputSource::Stderr, Box::pin(stderr)));
+    }
+    output
+}
+
+/// Waits for `child` to exit while handing everything it writes to `on_output`, terminating its
+/// process group when it runs past the watchdog's limits or `stop` is cancelled
+pub async fn supervise(
+    child: &mut Child,
+    output: Vec<OutputStream>,
+    mut on_output: impl FnMut(OutputSource, &[u8]),
+    watchdog: Watchdog,
+    termination: &Termination,
+    stop: &CancellationToken,
+) -> (io::Result<ExitStatus>, Option<TerminationReason>) {
+    let mut output = select_all(
+        output
+            .into_iter()
+            .map(|(source, reader)| ReaderStream::new(reader).map(move |chunk| (source, chunk))),
+    );
+    let mut output_open = !output.is_empty();
+
+    let started = Instant::now();
+    let mut last_output = started;
+
+    let reason = loop {
+        let deadline = watchdog.timeout.map(|timeout| started + timeout);
+        let idle_deadline = watchdog
+            .idle_output_timeout
+            .map(|timeout| last_output + timeout);
+
+        select! {
+            exit_status = child.wait() => {
+                if output_open {
+                    drain(&mut output, &mut on_output).await;
+                }
+                return (exit_status, None);
+            }
+            chunk = output.next(), if output_open => {
+                match chunk {
+                    Some((source, Ok(chunk))) => on_output(source, &chunk),
+                    Some((_, Err(_))) => {}
+                    None => output_open = false,
+                }
+                last_output = Instant::now();
+            }
+            _ = sleep_until(deadline) => break TerminationReason::Timeout,
+            _ = sleep_until(idle_deadline) => break TerminationReason::IdleOutput,
+            _ = stop.cancelled() => break TerminationReason::Stopped,
+        }
+    };
+
+    println!("Terminating process {:?}: {reason:?}", child.id());
+    (terminate(child, termination).await, Some(reason))
+}
+
+/// Hands over what is left to read after the process exited, giving up after [`DRAIN_TIMEOUT`]
+async fn drain<B: AsRef<[u8]>>(
+    output: &mut (impl Stream<Item = (OutputSource, io::Result<B>)> + Unpin),
+    on_output: &mut impl FnMut(OutputSource, &[u8]),
+) {
+    let drained = async {
+        while let Some((source, chunk)) = output.next().await {
+            if let Ok(chunk) = chunk {
+                on_output(source, chunk.as_ref());
+            }
+        }
+    };
+    let _ = tokio::time::timeout(DRAIN_TIMEOUT, drained).await;
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Escalates through the configured signals, then SIGKILL, until the process exits
+async fn terminate(child: &mut Child, termination: &Termination) -> io::Result<ExitStatus> {
+    let grace = Duration::from_secs(termination.grace_secs);
+    for name in &termination.signals {
+        let Some(signal) = parse_signal(name) else {
+            println!("Ignoring unknown signal {name:?}");
+            continue;
+        };
+
+        signal_group(child, signal);
+        if let Ok(exit_status) = tokio::time::timeout(grace, child.wait()).await {
+            return exit_status;
+        }
+    }
+
+    signal_group(child, libc::SIGKILL);
+    child.wait().await
+}
+
+fn parse_signal(name: &str) -> Option<libc::c_int> {
+    let signal = match name.trim_start_matches("SIG") {
+        "TERM" => libc::SIGTERM,
+        "INT" => libc::SIGINT,
+        "HUP" => libc::SIGHUP,
+        "QUIT" => libc::SIGQUIT,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "KILL" => libc::SIGKILL,
+        _ => return None,
+    };
+
+    Some(signal)
+}
+
+/// Wakes are started as the leaders of their own process group, so signalling the group also
+/// reaches whatever they started
+fn signal_group(child: &Child, signal: libc::c_int) {
+    let Some(pid) = child.id() else {
+        return;
+    };
+
+    if unsafe { libc::kill(-(pid as libc::pid_t), signal) } != 0 {
+        println!(
+            "Could not signal process group {pid}: {:?}",
+            io::Error::last_os_error()
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use tokio::{sync::watch, time::Instant};
+use wake_runner::os::boot::{
+    attribute_wake, track_boot_context, AcpiWakeupDevice, BootConfig, BootContext, WakeSource,
+    WakeupIrq,
+};
+
+const INTERRUPTS: &str = "\
+           CPU0       CPU1
+  9:          0          0   IO-APIC   9-fasteoi   acpi
+ 28:       1520          0  PCI-MSIX-0000:03:00.0   0-edge      enp3s0
+ 29:        311          0  PCI-MSIX-0000:04:00.0   0-edge      eth1-rx-0, eth1-tx-0
+ 30:         12          0  PCI-MSI-0000:00:17.0   0-edge      ahci[0000:00:17.0]
+";
+
+const ACPI_WAKEUP: &str = "\
+Device\tS-state\t  Status   Sysfs node
+PWRB\t  S5\t*enabled   platform:PNP0C0C:00
+GLAN\t  S4\t*enabled   pci:0000:00:1f.6
+XHC\t  S3\t*disabled  pci:0000:00:14.0
+";
+
+const WINDOW: Duration = Duration::from_secs(120);
+
+fn is_interface(name: &str) -> bool {
+    ["enp3s0", "eth1"].contains(&name)
+}
+
+fn wakeup_irq(irq: u32) -> WakeupIrq {
+    WakeupIrq::parse(irq, INTERRUPTS, is_interface)
+}
+
+fn acpi_device(name: &str, is_network: bool) -> AcpiWakeupDevice {
+    AcpiWakeupDevice {
+        name: name.to_string(),
+        sysfs_node: None,
+        is_network,
+    }
+}
+
+fn pending_context() -> BootContext {
+    BootContext {
+        boot_time: 0,
+        wake_source: WakeSource::Pending,
+        rtc_alarm: false,
+        wakeup_irq: None,
+        acpi_wakeup_devices: vec![],
+        first_discovery_secs: None,
+    }
+}
+
+#[test]
+fn interrupts_naming_an_interface_are_network() {
+    let irq = wakeup_irq(28);
+
+    assert_eq!(irq.actions, vec!["enp3s0"]);
+    assert!(irq.is_network);
+}
+
+#[test]
+fn per_queue_vectors_are_network() {
+    let irq = wakeup_irq(29);
+
+    assert_eq!(irq.actions, vec!["eth1-rx-0", "eth1-tx-0"]);
+    assert!(irq.is_network);
+}
+
+#[test]
+fn other_interrupts_are_not_network() {
+    assert!(!wakeup_irq(30).is_network);
+    assert!(!wakeup_irq(9).is_network);
+    assert!(wakeup_irq(99).actions.is_empty());
+}
+
+#[test]
+fn only_enabled_acpi_wakeup_devices_are_listed() {
+    let devices = AcpiWakeupDevice::parse_enabled(ACPI_WAKEUP, |address| address == "0000:00:1f.6");
+
+    let names: Vec<_> = devices.iter().map(|device| device.name.as_str()).collect();
+    assert_eq!(names, vec!["PWRB", "GLAN"]);
+    assert_eq!(devices[1].sysfs_node.as_deref(), Some("pci:0000:00:1f.6"));
+    assert!(!devices[0].is_network);
+    assert!(devices[1].is_network);
+}
+
+#[test]
+fn rtc_alarm_wins_attribution() {
+    let source = attribute_wake(true, Some(&wakeup_irq(28)), &[]);
+
+    assert_eq!(source, WakeSource::RtcAlarm);
+}
+
+#[test]
+fn network_wakeup_irq_is_wake_on_lan() {
+    let source = attribute_wake(false, Some(&wakeup_irq(28)), &[]);
+
+    assert_eq!(source, WakeSource::WakeOnLan);
+}
+
+#[test]
+fn acpi_wakeup_counts_when_only_network_devices_can_wake() {
+    let only_network = [acpi_device("GLAN", true)];
+    let also_power_button = [acpi_device("PWRB", false), acpi_device("GLAN", true)];
+
+    assert_eq!(
+        attribute_wake(false, Some(&wakeup_irq(9)), &only_network),
+        WakeSource::WakeOnLan
+    );
+    assert_eq!(
+        attribute_wake(false, Some(&wakeup_irq(9)), &also_power_button),
+        WakeSource::Pending
+    );
+    assert_eq!(
+        attribute_wake(false, Some(&wakeup_irq(30)), &only_network),
+        WakeSource::Pending
+    );
+    assert_eq!(
+        attribute_wake(false, None, &only_network),
+        WakeSource::Pending
+    );
+}
+
+#[test]
+fn woken_machines_idle_shorter() {
+    let config = BootConfig::default();
+
+    assert_eq!(
+        config.idle_timeout(WakeSource::WakeOnLan),
+        Duration::from_secs(config.wake_idle_secs)
+    );
+    assert_eq!(
+        config.idle_timeout(WakeSource::RtcAlarm),
+        Duration::from_secs(config.wake_idle_secs)
+    );
+    assert_eq!(
+        config.idle_timeout(WakeSource::Pending),
+        Duration::from_secs(config.human_idle_secs)
+    );
+    assert_eq!(
+        config.idle_timeout(WakeSource::Human),
+        Duration::from_secs(config.human_idle_secs)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn discovery_within_the_window_is_wake_on_lan() {
+    let (context_setter, context) = watch::channel(pending_context());
+    let (answered, answered_receiver) = watch::channel(0);
+    tokio::spawn(track_boot_context(
+        context_setter,
+        answered_receiver,
+        WINDOW,
+        Duration::from_secs(30),
+    ));
+    tokio::task::yield_now().await;
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    answered.send_modify(|count| *count += 1);
+    tokio::task::yield_now().await;
+
+    assert_eq!(context.borrow().wake_source, WakeSource::WakeOnLan);
+    assert_eq!(context.borrow().first_discovery_secs, Some(40));
+}
+
+#[tokio::test(start_paused = true)]
+async fn no_discovery_within_the_window_is_human() {
+    let (context_setter, mut context) = watch::channel(pending_context());
+    let (_answered, answered_receiver) = watch::channel(0);
+    let started = Instant::now();
+    tokio::spawn(track_boot_context(
+        context_setter,
+        answered_receiver,
+        WINDOW,
+        Duration::from_secs(30),
+    ));
+
+    context.changed().await.unwrap();
+
+    assert_eq!(context.borrow().wake_source, WakeSource::Human);
+    assert_eq!(started.elapsed(), Duration::from_secs(90));
+}
+
+#[tokio::test(start_paused = true)]
+async fn late_start_attributes_right_away() {
+    let (context_setter, mut context) = watch::channel(pending_context());
+    let (_answered, answered_receiver) = watch::channel(0);
+    let started = Instant::now();
+    tokio::spawn(track_boot_context(
+        context_setter,
+        answered_receiver,
+        WINDOW,
+        Duration::from_secs(600),
+    ));
+
+    context.changed().await.unwrap();
+
+    assert_eq!(context.borrow().wake_source, WakeSource::Human);
+    assert_eq!(started.elapsed(), Duration::ZERO);
+}
+
+#[tokio::test(start_paused = true)]
+async fn known_sources_are_kept() {
+    let (context_setter, mut context) = watch::channel(BootContext {
+        wake_source: WakeSource::RtcAlarm,
+        ..pending_context()
+    });
+    let (answered, answered_receiver) = watch::channel(0);
+    tokio::spawn(track_boot_context(
+        context_setter,
+        answered_receiver,
+        WINDOW,
+        Duration::ZERO,
+    ));
+    tokio::task::yield_now().await;
+
+    answered.send_modify(|count| *count += 1);
+    tokio::time::sleep(WINDOW).await;
+    context.changed().await.unwrap();
+
+    assert_eq!(context.borrow().wake_source, WakeSource::RtcAlarm);
+    assert_eq!(context.borrow().first_discovery_secs, Some(0));
+}
@@ -0,0 +1,197 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use wake_runner::server::wake::hooks::{
+    run_after, run_before, BeforeHookFailed, Hook, HookRun, Hooks,
+};
+
+/// A fresh temp directory the hooks run in
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wake_runner_hooks_{}_{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn sh(script: &str) -> Option<Hook> {
+    Some(Hook {
+        command: "sh".to_string(),
+        arguments: vec!["-c".to_string(), script.to_string()],
+    })
+}
+
+/// Hooks of one level appending their kind and `level` to `order`
+fn recording(level: &str) -> Hooks {
+    Hooks {
+        before: sh(&format!("echo before {level} >> order")),
+        after_success: sh(&format!("echo after_success {level} >> order")),
+        after_failure: sh(&format!("echo after_failure {level} >> order")),
+        always: sh(&format!("echo always {level} >> order")),
+    }
+}
+
+fn hook_run<'a>(dir: &'a str, log_path: &'a Path) -> HookRun<'a> {
+    HookRun {
+        id: "run-1",
+        name: "backup",
+        working_directory: Some(dir),
+        log_path,
+        exit_code: None,
+        duration: None,
+    }
+}
+
+fn recorded(dir: &Path, file: &str) -> Vec<String> {
+    std::fs::read_to_string(dir.join(file))
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_owned)
+        .collect()
+}
+
+#[tokio::test]
+async fn hooks_nest_around_the_run() {
+    let dir = temp_dir("nest");
+    let log_path = dir.join("run.log");
+    let (global, wake) = (recording("global"), recording("wake"));
+    let hooks = [&global, &wake];
+    let run = hook_run(dir.to_str().unwrap(), &log_path);
+
+    assert_eq!(run_before(&hooks, &run).await, Ok(()));
+    run_after(&hooks, &run, true).await;
+
+    assert_eq!(
+        recorded(&dir, "order"),
+        vec![
+            "before global",
+            "before wake",
+            "after_success wake",
+            "always wake",
+            "after_success global",
+            "always global",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failed_runs_get_the_failure_hooks() {
+    let dir = temp_dir("failure");
+    let log_path = dir.join("run.log");
+    let wake = recording("wake");
+    let run = hook_run(dir.to_str().unwrap(), &log_path);
+
+    run_after(&[&wake], &run, false).await;
+
+    assert_eq!(
+        recorded(&dir, "order"),
+        vec!["after_failure wake", "always wake"]
+    );
+}
+
+#[tokio::test]
+async fn failing_before_hook_stops_at_its_level() {
+    let dir = temp_dir("before_failure");
+    let log_path = dir.join("run.log");
+    let global = recording("global");
+    let wake = Hooks {
+        before: sh("echo before wake >> order; exit 3"),
+        ..recording("wake")
+    };
+    let hooks = [&global, &wake];
+    let run = hook_run(dir.to_str().unwrap(), &log_path);
+
+    let failed = run_before(&hooks, &run).await.unwrap_err();
+    assert_eq!(
+        failed,
+        BeforeHookFailed {
+            entered: 1,
+            exit_code: Some(3)
+        }
+    );
+    run_after(&hooks[..failed.entered], &run, false).await;
+
+    assert_eq!(
+        recorded(&dir, "order"),
+        vec![
+            "before global",
+            "before wake",
+            "after_failure global",
+            "always global",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn levels_without_a_before_hook_are_entered() {
+    let dir = temp_dir("no_before");
+    let log_path = dir.join("run.log");
+    let global = Hooks {
+        before: None,
+        ..recording("global")
+    };
+    let wake = Hooks {
+        before: sh("exit 1"),
+        ..recording("wake")
+    };
+    let run = hook_run(dir.to_str().unwrap(), &log_path);
+
+    let failed = run_before(&[&global, &wake], &run).await.unwrap_err();
+
+    assert_eq!(failed.entered, 1);
+}
+
+#[tokio::test]
+async fn hooks_that_can_not_start_fail_without_an_exit_code() {
+    let dir = temp_dir("missing");
+    let log_path = dir.join("run.log");
+    let hooks = Hooks {
+        before: Some(Hook {
+            command: "/nonexistent/hook".to_string(),
+            arguments: vec![],
+        }),
+        ..Hooks::default()
+    };
+    let run = hook_run(dir.to_str().unwrap(), &log_path);
+
+    let failed = run_before(&[&hooks], &run).await.unwrap_err();
+
+    assert_eq!(
+        failed,
+        BeforeHookFailed {
+            entered: 0,
+            exit_code: None
+        }
+    );
+}
+
+#[tokio::test]
+async fn hooks_are_told_about_the_run() {
+    let dir = temp_dir("env");
+    let log_path = dir.join("run.log");
+    let script = "echo \"$WAKE_RUN_ID|$WAKE_NAME|$WAKE_LOG_PATH|${WAKE_EXIT_CODE-unset}|${WAKE_DURATION_SECS-unset}\" >> env";
+    let hooks = Hooks {
+        before: sh(script),
+        always: sh(script),
+        ..Hooks::default()
+    };
+    let mut run = hook_run(dir.to_str().unwrap(), &log_path);
+
+    run_before(&[&hooks], &run).await.unwrap();
+    run.exit_code = Some(Some(2));
+    run.duration = Some(Duration::from_millis(1500));
+    run_after(&[&hooks], &run, false).await;
+    run.exit_code = Some(None);
+    run_after(&[&hooks], &run, false).await;
+
+    let log_path = log_path.display();
+    assert_eq!(
+        recorded(&dir, "env"),
+        vec![
+            format!("run-1|backup|{log_path}|unset|unset"),
+            format!("run-1|backup|{log_path}|2|1.500"),
+            format!("run-1|backup|{log_path}||1.500"),
+        ]
+    );
+}
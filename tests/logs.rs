@@ -0,0 +1,221 @@
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+use wake_runner::server::wake::{
+    logs::{
+        enforce_retention, parse_range, tail, LogConfig, LogFiles,
+        RangeRequest::{Ignored, Satisfiable, Unsatisfiable},
+        RunLog,
+    },
+    output::{LineSplitter, OutputSource},
+};
+
+fn log_config() -> LogConfig {
+    let directory = std::env::temp_dir().join(format!("wake_runner_logs_{}", Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    LogConfig {
+        directory: Some(directory),
+        ..LogConfig::default()
+    }
+}
+
+/// Writes a log of `len` bytes that was last modified `age` ago
+fn old_log(config: &LogConfig, name: &str, len: usize, age: Duration) -> PathBuf {
+    let path = config.directory().join(name);
+    fs::write(&path, vec![b'x'; len]).unwrap();
+    let file = File::options().write(true).open(&path).unwrap();
+    file.set_modified(SystemTime::now() - age).unwrap();
+    path
+}
+
+#[test]
+fn lines_are_split_per_source() {
+    let mut splitter = LineSplitter::default();
+    let mut lines = vec![];
+
+    for (source, chunk) in [
+        (OutputSource::Stdout, "first ha"),
+        (OutputSource::Stderr, "an error\n"),
+        (
+            OutputSource::Stdout,
+            "lf\r\nprogress 10%\rprogress 20%\rdone",
+        ),
+    ] {
+        splitter.push(source, chunk.as_bytes(), |line| {
+            lines.push((source, line.to_owned()))
+        });
+    }
+    splitter.finish(|source, line| lines.push((source, line.to_owned())));
+
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|(source, line)| (*source, line.as_str()))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (OutputSource::Stderr, "an error"),
+            (OutputSource::Stdout, "first half"),
+            (OutputSource::Stdout, "progress 10%"),
+            (OutputSource::Stdout, "progress 20%"),
+            (OutputSource::Stdout, "done"),
+        ]
+    );
+}
+
+#[test]
+fn ranges_are_clamped_to_the_content() {
+    assert_eq!(parse_range("bytes=0-9", 100), Satisfiable(0..10));
+    assert_eq!(parse_range("bytes=90-", 100), Satisfiable(90..100));
+    assert_eq!(parse_range("bytes=-10", 100), Satisfiable(90..100));
+    assert_eq!(parse_range("bytes=50-500", 100), Satisfiable(50..100));
+    assert_eq!(
+        parse_range("bytes=0-18446744073709551615", 100),
+        Satisfiable(0..100)
+    );
+    assert_eq!(parse_range("bytes=100-", 100), Unsatisfiable);
+}
+
+#[test]
+fn other_ranges_are_ignored() {
+    assert_eq!(parse_range("bytes=0-1,5-6", 100), Ignored);
+    assert_eq!(parse_range("bytes=9-0", 100), Ignored);
+    assert_eq!(parse_range("bytes=a-", 100), Ignored);
+    assert_eq!(parse_range("lines=0-1", 100), Ignored);
+}
+
+#[test]
+fn tail_keeps_the_last_lines() {
+    let content = b"one\ntwo\nthree\n";
+    assert_eq!(tail(content, 2), b"two\nthree\n");
+    assert_eq!(tail(content, 5), content);
+    assert_eq!(tail(content, 0), b"");
+    assert_eq!(tail(b"one\ntwo", 1), b"two");
+}
+
+#[test]
+fn retention_removes_old_excess_and_oversized_logs() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    let mut config = log_config();
+    config.max_age_days = 7;
+    config.max_files = 3;
+    config.max_total_mb = 1;
+
+    let expired = old_log(&config, "expired.log", 10, 8 * day);
+    let newest = old_log(&config, "newest.log", 10, Duration::ZERO);
+    let large = old_log(&config, "large.log", 1024 * 1024, day);
+    let kept = old_log(&config, "kept.log", 10, Duration::from_secs(60));
+    let also_kept = old_log(&config, "also_kept.log", 10, 2 * day);
+    let beyond_count = old_log(&config, "beyond_count.log", 10, 3 * day);
+
+    enforce_retention(&config.directory(), &config, SystemTime::now(), |_| false).unwrap();
+
+    assert!(newest.exists());
+    assert!(kept.exists());
+    // Would push the total past 1MB, the smaller logs after it still fit
+    assert!(!large.exists());
+    assert!(also_kept.exists());
+    assert!(!beyond_count.exists());
+    assert!(!expired.exists());
+    fs::remove_dir_all(config.directory()).unwrap();
+}
+
+#[test]
+fn retention_keeps_the_logs_of_known_runs() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    let mut config = log_config();
+    config.max_age_days = 7;
+
+    let running = old_log(&config, "running.log", 10, 8 * day);
+    let rotated = old_log(&config, "running.log.1", 10, 9 * day);
+    let finished = old_log(&config, "finished.log", 10, 8 * day);
+
+    enforce_retention(&config.directory(), &config, SystemTime::now(), |id| {
+        id == "running"
+    })
+    .unwrap();
+
+    assert!(running.exists());
+    assert!(rotated.exists());
+    assert!(!finished.exists());
+    fs::remove_dir_all(config.directory()).unwrap();
+}
+
+#[tokio::test]
+async fn run_log_has_timestamped_lines_and_rotates() {
+    let mut config = log_config();
+    config.max_file_mb = 1;
+    let id = Uuid::new_v4().to_string();
+
+    let log = RunLog::create(&config, &id, |_| false).await;
+    log.write_line("stdout", "hello");
+    log.flush().await;
+    let content = fs::read_to_string(log.path()).unwrap();
+    let (timestamp, line) = content.trim_end().split_once(' ').unwrap();
+    assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+    assert_eq!(line, "[stdout] hello");
+
+    let long_line = "x".repeat(1000);
+    for _ in 0..1100 {
+        log.write_line("stdout", &long_line);
+    }
+    log.flush().await;
+    let rotated = config.directory().join(format!("{id}.log.1"));
+    assert!(rotated.exists());
+    assert!(fs::metadata(log.path()).unwrap().len() < 1024 * 1024);
+    fs::remove_dir_all(config.directory()).unwrap();
+}
+
+async fn read(files: &LogFiles, range: std::ops::Range<u64>) -> String {
+    let mut content = String::new();
+    let mut reader = files.read(range).await.unwrap();
+    reader.read_to_string(&mut content).await.unwrap();
+    content
+}
+
+#[tokio::test]
+async fn rotated_logs_are_served_first() {
+    let config = log_config();
+    let id = Uuid::new_v4().to_string();
+    let path = config.log_path(&id);
+    fs::write(format!("{}.1", path.display()), "one\ntwo\n").unwrap();
+    fs::write(&path, "three\nfour\n").unwrap();
+
+    let files = LogFiles::open(&config, &id).await.unwrap();
+
+    assert_eq!(files.len(), 19);
+    assert_eq!(read(&files, 0..19).await, "one\ntwo\nthree\nfour\n");
+    assert_eq!(read(&files, 4..14).await, "two\nthree\n");
+    assert_eq!(read(&files, 14..19).await, "four\n");
+    assert_eq!(files.tail(3).await.unwrap(), b"two\nthree\nfour\n");
+    fs::remove_dir_all(config.directory()).unwrap();
+}
+
+#[tokio::test]
+async fn long_logs_are_tailed_from_the_end() {
+    let config = log_config();
+    let id = Uuid::new_v4().to_string();
+    let line = format!("{}\n", "x".repeat(999));
+    fs::write(config.log_path(&id), line.repeat(200)).unwrap();
+
+    let files = LogFiles::open(&config, &id).await.unwrap();
+
+    assert_eq!(files.tail(100).await.unwrap(), line.repeat(100).as_bytes());
+    assert_eq!(files.tail(500).await.unwrap(), line.repeat(200).as_bytes());
+    fs::remove_dir_all(config.directory()).unwrap();
+}
+
+#[tokio::test]
+async fn missing_logs_are_not_found() {
+    let config = log_config();
+
+    let error = LogFiles::open(&config, "missing").await.unwrap_err();
+
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    fs::remove_dir_all(config.directory()).unwrap();
+}
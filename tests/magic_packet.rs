@@ -0,0 +1,127 @@
+use std::{io, net::SocketAddr};
+
+use mac_address::MacAddress;
+use wake_runner::net::{
+    network::{DatagramSocket, DatagramTransport},
+    simulated::SimulatedLan,
+    wake_on_lan::{MagicPacket, SecureOnPassword, ETHER_TYPE},
+};
+
+const MAC: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+/// 6 bytes of 0xFF followed by 16 repetitions of `MAC`
+fn expected_packet() -> Vec<u8> {
+    let mut expected = vec![0xFF; 6];
+    for _ in 0..16 {
+        expected.extend_from_slice(&MAC);
+    }
+    expected
+}
+
+#[test]
+fn plain_packet_is_102_bytes() {
+    let bytes = MagicPacket::new(MAC).to_bytes();
+
+    assert_eq!(bytes.len(), 102);
+    assert_eq!(bytes, expected_packet());
+}
+
+#[test]
+fn secureon_passwords_are_appended() {
+    let short = SecureOnPassword::Short([192, 168, 1, 1]);
+    let long = SecureOnPassword::Long([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+    let mut expected = expected_packet();
+    expected.extend_from_slice(&[192, 168, 1, 1]);
+    let bytes = MagicPacket::new(MAC).with_password(short).to_bytes();
+    assert_eq!(bytes.len(), 106);
+    assert_eq!(bytes, expected);
+
+    let mut expected = expected_packet();
+    expected.extend_from_slice(&[0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+    let bytes = MagicPacket::new(MAC).with_password(long).to_bytes();
+    assert_eq!(bytes.len(), 108);
+    assert_eq!(bytes, expected);
+}
+
+#[test]
+fn secureon_passwords_parse_both_notations() {
+    assert_eq!(
+        "192.168.1.1".parse(),
+        Ok(SecureOnPassword::Short([192, 168, 1, 1]))
+    );
+    assert_eq!(
+        "aa:bb:cc:dd:ee:ff".parse(),
+        Ok(SecureOnPassword::Long([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]))
+    );
+    assert_eq!(
+        "AA-BB-CC-DD".parse(),
+        Ok(SecureOnPassword::Short([0xAA, 0xBB, 0xCC, 0xDD]))
+    );
+    assert!("aa:bb:cc".parse::<SecureOnPassword>().is_err());
+    assert!("aa:bb:cc:dd:ee:ff:00".parse::<SecureOnPassword>().is_err());
+    assert!("password".parse::<SecureOnPassword>().is_err());
+
+    let long = SecureOnPassword::Long([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+    assert_eq!(long.to_string().parse(), Ok(long));
+}
+
+#[test]
+fn ethernet_frame_is_a_broadcast_with_the_wake_on_lan_ether_type() {
+    let source = [0x02, 0, 0, 0, 0, 0x01];
+    let frame = MagicPacket::new(MAC)
+        .over_ethernet()
+        .to_ethernet_frame(source);
+
+    assert_eq!(frame.len(), 14 + 102);
+    assert_eq!(frame[..6], [0xFF; 6]);
+    assert_eq!(frame[6..12], source);
+    assert_eq!(frame[12..14], [0x08, 0x42]);
+    assert_eq!(u16::from_be_bytes([frame[12], frame[13]]), ETHER_TYPE);
+    assert_eq!(frame[14..], expected_packet());
+}
+
+#[tokio::test]
+async fn udp_packets_go_to_the_selected_port() {
+    let lan = SimulatedLan::new("10.0.0.0/24".parse().unwrap());
+    let sender = lan.add_host(MacAddress::new([0x02, 0, 0, 0, 0, 1]));
+    let receiver = lan.add_host(MacAddress::new(MAC));
+    let broadcast = "10.0.0.255".parse().unwrap();
+    let mut buf = [0u8; 1024];
+
+    let default_port = receiver
+        .bind(SocketAddr::from(([0, 0, 0, 0], 9)))
+        .await
+        .unwrap();
+    MagicPacket::new(MAC)
+        .send_from_to_on(&sender, sender.ip(), broadcast)
+        .await
+        .unwrap();
+    let (len, _) = default_port.recv_from(&mut buf).await.unwrap();
+    assert_eq!(buf[..len], expected_packet());
+
+    let port_7 = receiver
+        .bind(SocketAddr::from(([0, 0, 0, 0], 7)))
+        .await
+        .unwrap();
+    MagicPacket::new(MAC)
+        .with_port(7)
+        .send_from_to_on(&sender, sender.ip(), broadcast)
+        .await
+        .unwrap();
+    let (len, _) = port_7.recv_from(&mut buf).await.unwrap();
+    assert_eq!(buf[..len], expected_packet());
+}
+
+#[tokio::test]
+async fn ethernet_packets_are_not_sent_over_udp() {
+    let lan = SimulatedLan::new("10.0.0.0/24".parse().unwrap());
+    let sender = lan.add_host(MacAddress::new([0x02, 0, 0, 0, 0, 1]));
+
+    let sent = MagicPacket::new(MAC)
+        .over_ethernet()
+        .send_from_to_on(&sender, sender.ip(), "10.0.0.255".parse().unwrap())
+        .await;
+
+    assert_eq!(sent.unwrap_err().kind(), io::ErrorKind::Unsupported);
+}
@@ -0,0 +1,98 @@
+use serde::Deserialize;
+use wake_runner::server::wake::output::{OutputRule, OutputSource, OutputState, RuleAction};
+
+#[derive(Deserialize)]
+struct Rules {
+    output_rules: Vec<OutputRule>,
+}
+
+fn rules(toml: &str) -> Vec<OutputRule> {
+    toml::from_str::<Rules>(toml).unwrap().output_rules
+}
+
+fn minecraft_rules() -> Vec<OutputRule> {
+    rules(
+        r#"
+        output_rules = [
+            { pattern = 'Done \(.*\)! For help', action = "ready" },
+            { pattern = 'Preparing spawn area: (\d+)%', action = "progress" },
+            { pattern = 'WARN', action = "warning", stream = "stderr" },
+            { pattern = 'FAILED TO BIND', action = "fail" },
+        ]
+        "#,
+    )
+}
+
+#[test]
+fn ready_is_set_once_the_pattern_matches() {
+    let rules = minecraft_rules();
+    let mut state = OutputState::new(&rules);
+    assert!(!state.ready);
+
+    let applied = state.apply(&rules, OutputSource::Stdout, "Loading libraries");
+    assert!(applied.is_empty());
+    assert!(!state.ready);
+
+    let line = "[Server thread/INFO]: Done (3.2s)! For help, type \"help\"";
+    assert_eq!(
+        state.apply(&rules, OutputSource::Stdout, line),
+        [RuleAction::Ready]
+    );
+    assert!(state.ready);
+    // Already ready, nothing changes
+    assert!(state.apply(&rules, OutputSource::Stdout, line).is_empty());
+
+    state.restarted(&rules);
+    assert!(!state.ready);
+}
+
+#[test]
+fn wakes_without_a_ready_rule_are_ready_from_the_start() {
+    let rules = rules(r#"output_rules = [{ pattern = "x", action = "warning" }]"#);
+    assert!(OutputState::new(&rules).ready);
+    assert!(OutputState::new(&[]).ready);
+}
+
+#[test]
+fn progress_comes_from_the_first_capture_group() {
+    let rules = minecraft_rules();
+    let mut state = OutputState::new(&rules);
+
+    state.apply(&rules, OutputSource::Stdout, "Preparing spawn area: 42%");
+    assert_eq!(state.progress, Some(42.0));
+    state.apply(&rules, OutputSource::Pty, "Preparing spawn area: 97%");
+    assert_eq!(state.progress, Some(97.0));
+}
+
+#[test]
+fn rules_only_match_their_stream() {
+    let rules = minecraft_rules();
+    let mut state = OutputState::new(&rules);
+
+    state.apply(&rules, OutputSource::Stdout, "WARN not on stderr");
+    assert!(state.warnings.is_empty());
+    state.apply(&rules, OutputSource::Stderr, "WARN low memory");
+    // A terminal does not tell the streams apart
+    state.apply(&rules, OutputSource::Pty, "WARN can't keep up");
+    assert_eq!(state.warnings, ["WARN low memory", "WARN can't keep up"]);
+}
+
+#[test]
+fn fail_rules_record_the_line() {
+    let rules = minecraft_rules();
+    let mut state = OutputState::new(&rules);
+
+    let applied = state.apply(&rules, OutputSource::Stderr, "**** FAILED TO BIND TO PORT!");
+    assert_eq!(applied, [RuleAction::Fail]);
+    assert_eq!(
+        state.failure.as_deref(),
+        Some("**** FAILED TO BIND TO PORT!")
+    );
+}
+
+#[test]
+fn invalid_patterns_are_rejected_with_the_config() {
+    let invalid =
+        toml::from_str::<Rules>(r#"output_rules = [{ pattern = "(", action = "ready" }]"#);
+    assert!(invalid.is_err());
+}
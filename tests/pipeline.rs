@@ -0,0 +1,385 @@
+use std::{path::PathBuf, sync::Arc};
+
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use wake_runner::{
+    os::boot::{BootContext, WakeSource},
+    server::{
+        app::create_state,
+        config::Config,
+        server_state::ServerState,
+        wake::{
+            logs::RunLog,
+            pipeline::{run_pipeline, PipelineRun, StepRun, StepStatus},
+            watchdog::TerminationReason,
+        },
+    },
+};
+
+/// A fresh temp directory for the logs and whatever the steps write
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "wake_runner_pipeline_{}_{name}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `steps` are `[[wakes.steps]]` tables of a wake named `pipeline`
+fn config(dir: &PathBuf, steps: &str) -> Result<Config, toml::de::Error> {
+    toml::from_str(&format!(
+        "[[wakes]]\nname = \"pipeline\"\nworking_directory = {dir:?}\n{steps}\n[logs]\ndirectory = {:?}\n",
+        dir.join("logs"),
+    ))
+}
+
+fn state(config: Config) -> Arc<ServerState> {
+    let (count_setter, count) = watch::channel(0);
+    let (_, boot_context) = watch::channel(BootContext {
+        boot_time: 0,
+        wake_source: WakeSource::Human,
+        rtc_alarm: false,
+        wakeup_irq: None,
+        acpi_wakeup_devices: vec![],
+        first_discovery_secs: None,
+    });
+    create_state(count_setter, count, boot_context, config)
+}
+
+/// `wake` are extra keys of the wake, put before its steps
+async fn run_wake(name: &str, wake: &str, steps: &str) -> PipelineRun {
+    let dir = temp_dir(name);
+    let state = state(config(&dir, &format!("{wake}\n{steps}")).unwrap());
+    let log = RunLog::create(&state.config.logs, name, |_| false).await;
+    let wake = &state.config.wakes[0];
+    run_pipeline(wake, name, &state, &log, &CancellationToken::new()).await
+}
+
+async fn run(name: &str, steps: &str) -> PipelineRun {
+    run_wake(name, "", steps).await
+}
+
+fn statuses(runs: &[StepRun]) -> Vec<(&str, StepStatus)> {
+    runs.iter()
+        .map(|run| (run.name.as_str(), run.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn steps_wait_for_their_dependencies() {
+    let run = run(
+        "order",
+        r#"
+        [[wakes.steps]]
+        name = "last"
+        command = "sh"
+        arguments = ["-c", "cat first; echo last"]
+        depends_on = ["first"]
+
+        [[wakes.steps]]
+        name = "first"
+        command = "sh"
+        arguments = ["-c", "sleep 0.2; echo first > first"]
+        "#,
+    )
+    .await;
+
+    assert_eq!(run.exit_code, Some(0));
+    assert_eq!(
+        statuses(&run.steps),
+        vec![
+            ("last", StepStatus::Succeeded),
+            ("first", StepStatus::Succeeded)
+        ]
+    );
+    assert_eq!(run.steps[0].output, "first\nlast\n");
+    assert_eq!(run.steps[0].exit_code, Some(0));
+}
+
+#[tokio::test]
+async fn failures_skip_everything_depending_on_them() {
+    let run = run(
+        "failure",
+        r#"
+        [[wakes.steps]]
+        name = "test"
+        command = "sh"
+        arguments = ["-c", "exit 3"]
+
+        [[wakes.steps]]
+        name = "package"
+        command = "true"
+        depends_on = ["test"]
+
+        [[wakes.steps]]
+        name = "upload"
+        command = "true"
+        depends_on = ["package"]
+        "#,
+    )
+    .await;
+
+    assert_eq!(run.exit_code, Some(1));
+    assert_eq!(
+        statuses(&run.steps),
+        vec![
+            ("test", StepStatus::Failed),
+            ("package", StepStatus::Skipped),
+            ("upload", StepStatus::Skipped)
+        ]
+    );
+    assert_eq!(run.steps[0].exit_code, Some(3));
+}
+
+#[tokio::test]
+async fn allowed_failures_let_dependents_run() {
+    let run = run(
+        "allowed",
+        r#"
+        [[wakes.steps]]
+        name = "lint"
+        command = "sh"
+        arguments = ["-c", "exit 1"]
+        continue_on_failure = true
+
+        [[wakes.steps]]
+        name = "build"
+        command = "true"
+        depends_on = ["lint"]
+        "#,
+    )
+    .await;
+
+    assert_eq!(run.exit_code, Some(0));
+    assert_eq!(
+        statuses(&run.steps),
+        vec![
+            ("lint", StepStatus::Failed),
+            ("build", StepStatus::Succeeded)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn slow_steps_time_out() {
+    let run = run(
+        "timeout",
+        r#"
+        [[wakes.steps]]
+        name = "hang"
+        command = "sleep"
+        arguments = ["30"]
+        timeout_secs = 1
+
+        [[wakes.steps]]
+        name = "after"
+        command = "true"
+        depends_on = ["hang"]
+        "#,
+    )
+    .await;
+
+    assert_eq!(run.exit_code, Some(1));
+    assert_eq!(
+        statuses(&run.steps),
+        vec![
+            ("hang", StepStatus::TimedOut),
+            ("after", StepStatus::Skipped)
+        ]
+    );
+    assert_eq!(run.steps[0].exit_code, None);
+}
+
+#[tokio::test]
+async fn wake_timeout_terminates_running_steps() {
+    let run = run_wake(
+        "wake_timeout",
+        "timeout_secs = 1",
+        r#"
+        [[wakes.steps]]
+        name = "hang"
+        command = "sleep"
+        arguments = ["30"]
+
+        [[wakes.steps]]
+        name = "after"
+        command = "true"
+        depends_on = ["hang"]
+        "#,
+    )
+    .await;
+
+    assert_eq!(run.exit_code, None);
+    assert_eq!(run.termination_reason, Some(TerminationReason::Timeout));
+    assert_eq!(
+        statuses(&run.steps),
+        vec![
+            ("hang", StepStatus::TimedOut),
+            ("after", StepStatus::Skipped)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn quiet_steps_are_terminated() {
+    let run = run_wake(
+        "idle_output",
+        "idle_output_timeout_secs = 1",
+        r#"
+        [[wakes.steps]]
+        name = "quiet"
+        command = "sh"
+        arguments = ["-c", "echo started; sleep 30"]
+
+        [[wakes.steps]]
+        name = "chatty"
+        command = "sh"
+        arguments = ["-c", "for i in 1 2 3; do echo $i; sleep 0.5; done"]
+        "#,
+    )
+    .await;
+
+    assert_eq!(run.exit_code, Some(1));
+    assert_eq!(run.termination_reason, None);
+    assert_eq!(
+        statuses(&run.steps),
+        vec![
+            ("quiet", StepStatus::TimedOut),
+            ("chatty", StepStatus::Succeeded)
+        ]
+    );
+    assert_eq!(run.steps[0].output, "started\n");
+}
+
+#[tokio::test]
+async fn stopped_pipelines_skip_the_pending_steps() {
+    let dir = temp_dir("stop");
+    let state = state(
+        config(
+            &dir,
+            r#"
+            [[wakes.steps]]
+            name = "hang"
+            command = "sleep"
+            arguments = ["30"]
+
+            [[wakes.steps]]
+            name = "after"
+            command = "true"
+            depends_on = ["hang"]
+            "#,
+        )
+        .unwrap(),
+    );
+    let log = RunLog::create(&state.config.logs, "stop", |_| false).await;
+    let stop = CancellationToken::new();
+    let stopper = stop.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        stopper.cancel();
+    });
+
+    let run = run_pipeline(&state.config.wakes[0], "stop", &state, &log, &stop).await;
+
+    assert_eq!(run.exit_code, None);
+    assert_eq!(run.termination_reason, Some(TerminationReason::Stopped));
+    assert_eq!(
+        statuses(&run.steps),
+        vec![
+            ("hang", StepStatus::Stopped),
+            ("after", StepStatus::Skipped)
+        ]
+    );
+}
+
+#[test]
+fn duplicate_step_names_are_rejected() {
+    let dir = temp_dir("duplicate");
+
+    let error = config(
+        &dir,
+        r#"
+        [[wakes.steps]]
+        name = "build"
+        command = "true"
+
+        [[wakes.steps]]
+        name = "build"
+        command = "false"
+        "#,
+    )
+    .unwrap_err();
+
+    assert!(error.to_string().contains("duplicate step name \"build\""));
+}
+
+#[test]
+fn unknown_dependencies_are_rejected() {
+    let dir = temp_dir("unknown");
+
+    let error = config(
+        &dir,
+        r#"
+        [[wakes.steps]]
+        name = "build"
+        command = "true"
+        depends_on = ["fetch"]
+        "#,
+    )
+    .unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("step \"build\" depends on unknown step \"fetch\""));
+}
+
+#[test]
+fn cycles_are_rejected() {
+    let dir = temp_dir("cycle");
+
+    let error = config(
+        &dir,
+        r#"
+        [[wakes.steps]]
+        name = "independent"
+        command = "true"
+
+        [[wakes.steps]]
+        name = "a"
+        command = "true"
+        depends_on = ["independent", "b"]
+
+        [[wakes.steps]]
+        name = "b"
+        command = "true"
+        depends_on = ["a"]
+        "#,
+    )
+    .unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("step \"a\" depends on itself through a cycle"));
+}
+
+#[test]
+fn steps_depending_on_themselves_are_rejected() {
+    let dir = temp_dir("self");
+
+    let error = config(
+        &dir,
+        r#"
+        [[wakes.steps]]
+        name = "build"
+        command = "true"
+        depends_on = ["build"]
+        "#,
+    )
+    .unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("step \"build\" depends on itself through a cycle"));
+}
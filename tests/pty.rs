@@ -0,0 +1,55 @@
+use std::process::Command;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Child,
+};
+use wake_runner::os::pty::{attach_to_pty, open_pty, PtyStream, WindowSize};
+
+/// `sh -c script` on the slave end of a new pty
+fn spawn(script: &str, size: WindowSize) -> (Child, PtyStream) {
+    let (pty, slave) = open_pty(size).unwrap();
+    let mut command = Command::new("sh");
+    command.args(["-c", script]);
+    attach_to_pty(&mut command, &slave).unwrap();
+
+    let child = tokio::process::Command::from(command).spawn().unwrap();
+    (child, pty)
+}
+
+#[tokio::test]
+async fn process_reads_and_writes_a_terminal() {
+    let (mut child, mut pty) = spawn(
+        "test -t 0 && test -t 1 && stty size && read line && echo got $line",
+        WindowSize {
+            cols: 100,
+            rows: 40,
+        },
+    );
+
+    pty.write_all(b"hello\n").await.unwrap();
+    let mut output = String::new();
+    // Reading ends once the process exited and closed the slave
+    pty.read_to_string(&mut output).await.unwrap();
+
+    assert!(output.contains("40 100"), "{output:?}");
+    assert!(output.contains("got hello"), "{output:?}");
+    assert!(child.wait().await.unwrap().success());
+}
+
+#[tokio::test]
+async fn resizing_is_seen_by_the_process() {
+    let (mut child, mut pty) = spawn("read line; stty size", WindowSize::default());
+
+    pty.resize(WindowSize {
+        cols: 132,
+        rows: 50,
+    })
+    .unwrap();
+    pty.write_all(b"\n").await.unwrap();
+    let mut output = String::new();
+    pty.read_to_string(&mut output).await.unwrap();
+
+    assert!(output.contains("50 132"), "{output:?}");
+    child.wait().await.unwrap();
+}
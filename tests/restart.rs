@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use wake_runner::server::wake::{
+    restart::{RestartBackoff, RestartPolicy, Restarts},
+    watchdog::TerminationReason,
+};
+
+fn backoff() -> RestartBackoff {
+    RestartBackoff {
+        initial_delay_ms: 1000,
+        multiplier: 2.0,
+        max_delay_ms: 5000,
+        max_restarts: 3,
+        window_secs: 60,
+    }
+}
+
+#[test]
+fn policies_decide_on_the_exit_code() {
+    assert!(!RestartPolicy::No.should_restart(Some(1), None));
+    assert!(RestartPolicy::OnFailure.should_restart(Some(1), None));
+    assert!(RestartPolicy::OnFailure.should_restart(None, None));
+    assert!(!RestartPolicy::OnFailure.should_restart(Some(0), None));
+    assert!(RestartPolicy::Always.should_restart(Some(0), None));
+}
+
+#[test]
+fn stopped_runs_are_not_restarted() {
+    for policy in [RestartPolicy::OnFailure, RestartPolicy::Always] {
+        assert!(!policy.should_restart(None, Some(TerminationReason::Stopped)));
+    }
+}
+
+#[test]
+fn watchdog_terminations_are_failures() {
+    for reason in [TerminationReason::Timeout, TerminationReason::IdleOutput] {
+        assert!(!RestartPolicy::No.should_restart(None, Some(reason)));
+        assert!(RestartPolicy::OnFailure.should_restart(None, Some(reason)));
+        // Exiting 0 on SIGTERM doesn't make the run a success
+        assert!(RestartPolicy::OnFailure.should_restart(Some(0), Some(reason)));
+        assert!(RestartPolicy::Always.should_restart(None, Some(reason)));
+    }
+}
+
+#[test]
+fn failed_output_is_a_failure() {
+    let failed = Some(TerminationReason::FailedOutput);
+
+    assert!(!RestartPolicy::No.should_restart(None, failed));
+    assert!(RestartPolicy::OnFailure.should_restart(None, failed));
+    // The process may exit cleanly once terminated, the fail rule still matched
+    assert!(RestartPolicy::OnFailure.should_restart(Some(0), failed));
+    assert!(RestartPolicy::Always.should_restart(Some(0), failed));
+}
+
+#[tokio::test(start_paused = true)]
+async fn delays_grow_until_the_limit_is_reached() {
+    let mut restarts = Restarts::new(&backoff());
+    let crashed_quickly = Duration::from_secs(1);
+
+    let mut delays = vec![];
+    while let Some(delay) = restarts.next_delay(crashed_quickly) {
+        delays.push(delay.as_millis());
+        tokio::time::advance(delay).await;
+        restarts.restarted();
+    }
+
+    assert_eq!(delays, [1000, 2000, 4000]);
+    assert_eq!(restarts.count, 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn restarts_outside_the_window_do_not_count() {
+    let mut restarts = Restarts::new(&backoff());
+    let crashed_quickly = Duration::from_secs(1);
+
+    for _ in 0..3 {
+        let delay = restarts.next_delay(crashed_quickly).unwrap();
+        tokio::time::advance(delay).await;
+        restarts.restarted();
+    }
+    assert_eq!(restarts.next_delay(crashed_quickly), None);
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    // A run that lasted the whole window starts the backoff over
+    assert_eq!(
+        restarts.next_delay(Duration::from_secs(60)),
+        Some(Duration::from_secs(1))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn huge_delays_stop_at_the_maximum() {
+    let mut restarts = Restarts::new(&RestartBackoff {
+        initial_delay_ms: u64::MAX,
+        multiplier: 1e300,
+        max_delay_ms: u64::MAX,
+        max_restarts: 3,
+        window_secs: 60,
+    });
+    let crashed_quickly = Duration::from_secs(1);
+
+    for _ in 0..3 {
+        restarts.next_delay(crashed_quickly).unwrap();
+        restarts.restarted();
+    }
+
+    assert_eq!(restarts.next_delay(crashed_quickly), None);
+}
+
+#[test]
+fn multipliers_are_validated_at_load() {
+    let backoff = |multiplier: &str| {
+        toml::from_str::<RestartBackoff>(&format!("multiplier = {multiplier}"))
+            .map(|backoff| backoff.multiplier)
+    };
+
+    assert_eq!(backoff("1.5").unwrap(), 1.5);
+    assert!(backoff("0.5").is_err());
+    assert!(backoff("inf").is_err());
+    assert!(backoff("nan").is_err());
+    assert_eq!(
+        toml::from_str::<RestartBackoff>("max_restarts = 1")
+            .unwrap()
+            .multiplier,
+        2.0
+    );
+}
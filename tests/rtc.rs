@@ -0,0 +1,97 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use wake_runner::os::rtc::RtcAlarm;
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// An alarm backed by files in a fresh temp directory instead of sysfs
+fn temp_alarm(name: &str) -> RtcAlarm {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("wake_runner_rtc_{}_{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("wakealarm"), "").unwrap();
+
+    RtcAlarm {
+        wakealarm_path: dir.join("wakealarm"),
+        record_path: dir.join("state").join("rtc_alarm"),
+    }
+}
+
+#[tokio::test]
+async fn arming_writes_the_alarm_time() {
+    let alarm = temp_alarm("arm");
+    let at = now() + 3600;
+
+    alarm.arm(at).await.unwrap();
+    assert_eq!(alarm.armed().await.unwrap(), Some(at));
+
+    alarm.disarm().await.unwrap();
+    assert_eq!(alarm.armed().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn boot_shortly_after_the_alarm_was_caused_by_it() {
+    let alarm = temp_alarm("caused");
+    let at = now() - 30;
+    alarm.arm(at).await.unwrap();
+    // The kernel empties wakealarm once it has fired
+    std::fs::write(&alarm.wakealarm_path, "").unwrap();
+
+    assert!(alarm.check_boot(at + 5).await.unwrap());
+}
+
+#[tokio::test]
+async fn unrelated_boot_is_not_attributed_to_the_alarm() {
+    let alarm = temp_alarm("unrelated");
+    let at = now() + 3600;
+    alarm.arm(at).await.unwrap();
+
+    assert!(!alarm.check_boot(now() - 10).await.unwrap());
+    // A future alarm is still wanted
+    assert_eq!(alarm.armed().await.unwrap(), Some(at));
+}
+
+#[tokio::test]
+async fn stale_alarm_is_cleared_at_boot() {
+    let alarm = temp_alarm("stale");
+    let at = now() - 24 * 3600;
+    alarm.arm(at).await.unwrap();
+
+    assert!(!alarm.check_boot(now() - 10).await.unwrap());
+    assert_eq!(alarm.armed().await.unwrap(), None);
+    assert!(!alarm.record_path.exists());
+}
+
+#[tokio::test]
+async fn alarms_of_other_tools_are_not_replaced() {
+    let alarm = temp_alarm("foreign_arm");
+    let theirs = now() + 7200;
+    std::fs::write(&alarm.wakealarm_path, theirs.to_string()).unwrap();
+
+    assert!(alarm.arm(now() + 3600).await.is_err());
+    assert_eq!(alarm.armed().await.unwrap(), Some(theirs));
+}
+
+#[tokio::test]
+async fn alarms_of_other_tools_are_not_cleared() {
+    let alarm = temp_alarm("foreign_clear");
+    let ours = now() - 24 * 3600;
+    alarm.arm(ours).await.unwrap();
+    let theirs = now() - 3600;
+    std::fs::write(&alarm.wakealarm_path, theirs.to_string()).unwrap();
+
+    assert!(!alarm.check_boot(now() - 10).await.unwrap());
+    assert_eq!(alarm.armed().await.unwrap(), Some(theirs));
+
+    alarm.disarm().await.unwrap();
+    assert_eq!(alarm.armed().await.unwrap(), Some(theirs));
+}
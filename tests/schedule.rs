@@ -0,0 +1,202 @@
+use chrono::{DateTime, Utc};
+use wake_runner::server::wake::schedule::{
+    MissedRunPolicy, MissedRuns, Schedule, ScheduleState, MAX_CATCH_UP_RUNS,
+};
+
+const WAKE: &str = "backup";
+
+fn at(time: &str) -> DateTime<Utc> {
+    time.parse().unwrap()
+}
+
+fn schedule(cron: &str, timezone: &str, missed: MissedRunPolicy) -> Schedule {
+    Schedule {
+        cron: cron.to_string(),
+        timezone: Some(timezone.to_string()),
+        missed,
+    }
+}
+
+fn handled_until(schedule: &Schedule, until: &str) -> ScheduleState {
+    let mut state = ScheduleState::default();
+    state.set_handled_until(WAKE, &schedule.cron, at(until));
+    state
+}
+
+/// Daily at 03:00 UTC, off from the start of the 1st until the 4th
+fn missed_after_three_days(missed: MissedRunPolicy) -> MissedRuns {
+    let schedule = schedule("0 3 * * *", "UTC", missed);
+    let state = handled_until(&schedule, "2024-01-01T00:00:00Z");
+    schedule
+        .missed_runs(WAKE, &state, at("2024-01-04T00:00:00Z"))
+        .unwrap()
+}
+
+#[test]
+fn skip_runs_none_of_the_missed_runs() {
+    let missed = missed_after_three_days(MissedRunPolicy::Skip);
+
+    assert_eq!(missed, MissedRuns { missed: 3, runs: 0 });
+}
+
+#[test]
+fn run_once_runs_a_single_missed_run() {
+    let missed = missed_after_three_days(MissedRunPolicy::RunOnce);
+
+    assert_eq!(missed, MissedRuns { missed: 3, runs: 1 });
+}
+
+#[test]
+fn run_all_runs_every_missed_run() {
+    let missed = missed_after_three_days(MissedRunPolicy::RunAll);
+
+    assert_eq!(missed, MissedRuns { missed: 3, runs: 3 });
+}
+
+#[test]
+fn run_all_is_capped() {
+    let schedule = schedule("* * * * *", "UTC", MissedRunPolicy::RunAll);
+    let state = handled_until(&schedule, "2024-01-01T00:00:00Z");
+
+    let missed = schedule
+        .missed_runs(WAKE, &state, at("2024-01-02T00:00:00Z"))
+        .unwrap();
+
+    assert_eq!(missed.runs, MAX_CATCH_UP_RUNS);
+}
+
+fn progress_after_three_days(missed: MissedRunPolicy) -> Vec<DateTime<Utc>> {
+    let schedule = schedule("0 3 * * *", "UTC", missed);
+    let state = handled_until(&schedule, "2024-01-01T00:00:00Z");
+    schedule
+        .catch_up_progress(WAKE, &state, at("2024-01-04T00:00:00Z"))
+        .unwrap()
+}
+
+#[test]
+fn each_catch_up_run_handles_its_missed_run() {
+    let progress = progress_after_three_days(MissedRunPolicy::RunAll);
+
+    assert_eq!(
+        progress,
+        vec![
+            at("2024-01-01T03:00:00Z"),
+            at("2024-01-02T03:00:00Z"),
+            // The last run handles everything up to the boot
+            at("2024-01-04T00:00:00Z"),
+        ]
+    );
+}
+
+#[test]
+fn a_single_catch_up_run_handles_everything() {
+    assert_eq!(
+        progress_after_three_days(MissedRunPolicy::RunOnce),
+        vec![at("2024-01-04T00:00:00Z")]
+    );
+    assert!(progress_after_three_days(MissedRunPolicy::Skip).is_empty());
+}
+
+#[test]
+fn capped_catch_ups_handle_everything_with_the_last_run() {
+    let schedule = schedule("* * * * *", "UTC", MissedRunPolicy::RunAll);
+    let state = handled_until(&schedule, "2024-01-01T00:00:00Z");
+    let now = at("2024-01-02T00:00:00Z");
+
+    let progress = schedule.catch_up_progress(WAKE, &state, now).unwrap();
+
+    assert_eq!(progress.len(), MAX_CATCH_UP_RUNS);
+    assert_eq!(progress[0], at("2024-01-01T00:01:00Z"));
+    assert_eq!(progress.last(), Some(&now));
+}
+
+#[test]
+fn schedules_seen_for_the_first_time_missed_nothing() {
+    let schedule = schedule("0 3 * * *", "UTC", MissedRunPolicy::RunAll);
+
+    let missed = schedule
+        .missed_runs(WAKE, &ScheduleState::default(), at("2024-01-04T00:00:00Z"))
+        .unwrap();
+
+    assert_eq!(missed, MissedRuns::default());
+}
+
+#[test]
+fn the_run_at_the_handled_time_is_not_missed() {
+    let schedule = schedule("0 3 * * *", "UTC", MissedRunPolicy::RunAll);
+    let state = handled_until(&schedule, "2024-01-01T03:00:00Z");
+
+    let missed = schedule
+        .missed_runs(WAKE, &state, at("2024-01-02T03:00:00Z"))
+        .unwrap();
+
+    assert_eq!(missed, MissedRuns { missed: 1, runs: 1 });
+}
+
+#[test]
+fn changed_cron_expressions_start_over() {
+    let old = schedule("0 3 * * *", "UTC", MissedRunPolicy::RunAll);
+    let new = schedule("0 4 * * *", "UTC", MissedRunPolicy::RunAll);
+    let state = handled_until(&old, "2024-01-01T00:00:00Z");
+
+    let missed = new
+        .missed_runs(WAKE, &state, at("2024-01-04T00:00:00Z"))
+        .unwrap();
+
+    assert_eq!(missed, MissedRuns::default());
+}
+
+#[test]
+fn five_field_expressions_fire_on_the_minute() {
+    let schedule = schedule("30 2 * * *", "UTC", MissedRunPolicy::Skip);
+
+    let next = schedule.next_fire(at("2024-01-01T00:00:00Z"));
+
+    assert_eq!(next, Some(at("2024-01-01T02:30:00Z")));
+}
+
+#[test]
+fn six_field_expressions_start_with_seconds() {
+    let schedule = schedule("15 30 2 * * *", "UTC", MissedRunPolicy::Skip);
+
+    let next = schedule.next_fire(at("2024-01-01T00:00:00Z"));
+
+    assert_eq!(next, Some(at("2024-01-01T02:30:15Z")));
+}
+
+#[test]
+fn fires_in_the_configured_timezone() {
+    let schedule = schedule("0 3 * * *", "Europe/Stockholm", MissedRunPolicy::Skip);
+
+    let winter = schedule.next_fire(at("2024-01-01T00:00:00Z"));
+    let summer = schedule.next_fire(at("2024-07-01T00:00:00Z"));
+
+    assert_eq!(winter, Some(at("2024-01-01T02:00:00Z")));
+    assert_eq!(summer, Some(at("2024-07-01T01:00:00Z")));
+}
+
+#[test]
+fn missed_runs_follow_the_timezone() {
+    let schedule = schedule("0 3 * * *", "Europe/Stockholm", MissedRunPolicy::RunAll);
+    let state = handled_until(&schedule, "2024-01-01T02:30:00Z");
+
+    let missed = schedule
+        .missed_runs(WAKE, &state, at("2024-01-02T01:59:59Z"))
+        .unwrap();
+
+    assert_eq!(missed, MissedRuns::default());
+}
+
+#[test]
+fn invalid_schedules_are_errors() {
+    let bad_cron = schedule("not cron", "UTC", MissedRunPolicy::Skip);
+    let bad_timezone = schedule("0 3 * * *", "Mars/Olympus", MissedRunPolicy::Skip);
+    let now = at("2024-01-01T00:00:00Z");
+
+    assert!(bad_cron
+        .missed_runs(WAKE, &ScheduleState::default(), now)
+        .is_err());
+    assert!(bad_timezone
+        .missed_runs(WAKE, &ScheduleState::default(), now)
+        .is_err());
+}
@@ -0,0 +1,328 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use mac_address::MacAddress;
+use tokio::{sync::watch, time::Instant};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use wake_runner::{
+    client::{
+        address_cache::{AddressCache, DEFAULT_TTL},
+        discovery::{discover_runner, receive_announcements},
+        wake_up::{wake_up, LanWakeUp, WakeBackoff},
+    },
+    net::{
+        discovery::{Announcement, UDP_DISCOVERY_PORT},
+        interface_filter::{FilteredNetwork, InterfaceFilter},
+        network::{DatagramSocket, DatagramTransport, Network},
+        simulated::{SimulatedHost, SimulatedLan},
+        wake_on_lan::{MagicPacket, SendStrategy},
+    },
+    server::discovery::{discovery_server, discovery_server_reporting},
+};
+
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+fn mac(last: u8) -> MacAddress {
+    MacAddress::new([0x02, 0, 0, 0, 0, last])
+}
+
+fn lan() -> SimulatedLan {
+    SimulatedLan::new("10.0.0.0/24".parse().unwrap())
+}
+
+/// Runs a discovery server answering with `http_port` until the token is dropped
+fn start_runner<N: Network + Clone + 'static>(host: &N, http_port: u16) -> DropGuard {
+    let shutdown = CancellationToken::new();
+    let host = host.clone();
+    let token = shutdown.clone();
+    tokio::spawn(async move { discovery_server(&host, &token, http_port).await });
+    shutdown.drop_guard()
+}
+
+fn lan_wake_up(client: &SimulatedHost, target: MacAddress) -> LanWakeUp<SimulatedHost> {
+    LanWakeUp {
+        network: client.clone(),
+        magic_packets: vec![MagicPacket::new(target.bytes())],
+        strategies: vec![SendStrategy::LocalBroadcast],
+        interface_name: None,
+    }
+}
+
+fn backoff(boot_timeout_secs: u64) -> WakeBackoff {
+    WakeBackoff {
+        jitter: 0.0,
+        max_attempts: 20,
+        boot_timeout_secs,
+        ..WakeBackoff::default()
+    }
+}
+
+fn address(host: &SimulatedHost, port: u16) -> SocketAddr {
+    SocketAddr::new(host.ip().into(), port)
+}
+
+#[tokio::test(start_paused = true)]
+async fn discovers_the_runner_owning_the_mac() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runners: Vec<_> = (2..5).map(|last| lan.add_host(mac(last))).collect();
+    let _guards: Vec<_> = runners
+        .iter()
+        .enumerate()
+        .map(|(i, runner)| start_runner(runner, 8000 + i as u16))
+        .collect();
+    tokio::task::yield_now().await;
+
+    let found = discover_runner(&client, mac(3), None, PROBE_TIMEOUT).await;
+
+    assert_eq!(found, Some(address(&runners[1], 8001)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn answered_requests_are_counted() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let (answered_requests, answered) = watch::channel(0);
+    let shutdown = CancellationToken::new();
+    let _guard = shutdown.clone().drop_guard();
+    let server_runner = runner.clone();
+    tokio::spawn(async move {
+        discovery_server_reporting(&server_runner, &shutdown, 8000, &answered_requests).await
+    });
+    tokio::task::yield_now().await;
+
+    discover_runner(&client, mac(9), None, PROBE_TIMEOUT).await;
+    assert_eq!(*answered.borrow(), 0);
+
+    let found = discover_runner(&client, mac(2), None, PROBE_TIMEOUT).await;
+    assert_eq!(found, Some(address(&runner, 8000)));
+    assert!(*answered.borrow() >= 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn unknown_mac_is_not_answered() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+
+    let found = discover_runner(&client, mac(9), None, PROBE_TIMEOUT).await;
+
+    assert_eq!(found, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn probes_only_the_selected_interface() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+
+    let on_other = discover_runner(&client, mac(2), Some("wlan0"), PROBE_TIMEOUT).await;
+    let on_eth0 = discover_runner(&client, mac(2), Some("eth0"), PROBE_TIMEOUT).await;
+
+    assert_eq!(on_other, None);
+    assert_eq!(on_eth0, Some(address(&runner, 8000)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn retries_through_lost_discovery_requests() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+
+    let dropped = Arc::new(AtomicUsize::new(0));
+    {
+        let dropped = dropped.clone();
+        lan.set_filter(move |datagram| {
+            let is_discovery = datagram.to.port() == UDP_DISCOVERY_PORT;
+            !(is_discovery && dropped.fetch_add(1, Ordering::SeqCst) < 5)
+        });
+    }
+
+    let found = wake_up(&lan_wake_up(&client, mac(2)), &backoff(30), None).await;
+
+    assert_eq!(found, Some(address(&runner, 8000)));
+    assert!(dropped.load(Ordering::SeqCst) > 5);
+}
+
+#[tokio::test(start_paused = true)]
+async fn wakes_a_runner_that_boots_late() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_sleeping_host(mac(2), Duration::from_secs(20));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+    let started = Instant::now();
+
+    let found = wake_up(&lan_wake_up(&client, mac(2)), &backoff(30), None).await;
+
+    assert_eq!(found, Some(address(&runner, 8000)));
+    assert!(runner.is_up());
+    assert!(started.elapsed() >= Duration::from_secs(20));
+}
+
+#[tokio::test(start_paused = true)]
+async fn gives_up_on_a_runner_that_boots_too_late() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_sleeping_host(mac(2), Duration::from_secs(60));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+
+    let found = wake_up(&lan_wake_up(&client, mac(2)), &backoff(30), None).await;
+
+    assert_eq!(found, None);
+    assert!(!runner.is_up());
+}
+
+#[tokio::test(start_paused = true)]
+async fn wakes_through_lost_magic_packets() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_sleeping_host(mac(2), Duration::from_secs(5));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+
+    let lost = Arc::new(AtomicUsize::new(0));
+    {
+        let lost = lost.clone();
+        lan.set_filter(move |datagram| {
+            let is_magic_packet = MagicPacket::parse(datagram.payload).is_ok();
+            !(is_magic_packet && lost.fetch_add(1, Ordering::SeqCst) < 3)
+        });
+    }
+
+    let found = wake_up(&lan_wake_up(&client, mac(2)), &backoff(30), None).await;
+
+    assert_eq!(found, Some(address(&runner, 8000)));
+    assert!(lost.load(Ordering::SeqCst) > 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn sleeping_runner_stays_down_without_magic_packets() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_sleeping_host(mac(2), Duration::from_secs(1));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+
+    let found = discover_runner(&client, mac(2), None, PROBE_TIMEOUT).await;
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    assert_eq!(found, None);
+    assert!(!runner.is_up());
+}
+
+#[tokio::test(start_paused = true)]
+async fn client_only_probes_allowed_interfaces() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+
+    let excluded = FilteredNetwork {
+        network: client.clone(),
+        filter: InterfaceFilter {
+            exclude: vec!["eth*".to_string()],
+            ..InterfaceFilter::default()
+        },
+    };
+    let included = FilteredNetwork {
+        network: client.clone(),
+        filter: InterfaceFilter {
+            include: vec!["et?0".to_string()],
+            include_networks: vec!["10.0.0.0/8".parse().unwrap()],
+            require_up: true,
+            ..InterfaceFilter::default()
+        },
+    };
+
+    let through_excluded = discover_runner(&excluded, mac(2), None, PROBE_TIMEOUT).await;
+    let through_included = discover_runner(&included, mac(2), None, PROBE_TIMEOUT).await;
+
+    assert_eq!(through_excluded, None);
+    assert_eq!(through_included, Some(address(&runner, 8000)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn runner_ignores_discovery_from_excluded_networks() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let filtered_runner = FilteredNetwork {
+        network: runner.clone(),
+        filter: InterfaceFilter {
+            exclude_networks: vec!["10.0.0.0/24".parse().unwrap()],
+            ..InterfaceFilter::default()
+        },
+    };
+    let _guard = start_runner(&filtered_runner, 8000);
+    tokio::task::yield_now().await;
+
+    let found = discover_runner(&client, mac(2), None, PROBE_TIMEOUT).await;
+
+    assert_eq!(found, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn runner_follows_address_changes_and_announces_them() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+    let listener = client
+        .bind(SocketAddr::from(([0, 0, 0, 0], UDP_DISCOVERY_PORT)))
+        .await
+        .unwrap();
+
+    runner.set_ip("10.0.0.50".parse().unwrap());
+
+    let mut buf = [0u8; 1024];
+    let (len, from) = listener.recv_from(&mut buf).await.unwrap();
+    assert_eq!(from.ip(), runner.ip());
+    assert_eq!(
+        Announcement::parse(&buf[..len]),
+        Some(Announcement {
+            port: 8000,
+            mac_addresses: vec![mac(2)],
+        })
+    );
+
+    let found = discover_runner(&client, mac(2), None, PROBE_TIMEOUT).await;
+    assert_eq!(found, Some(address(&runner, 8000)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn client_records_announced_addresses() {
+    let lan = lan();
+    let client = lan.add_host(mac(1));
+    let runner = lan.add_host(mac(2));
+    let _guard = start_runner(&runner, 8000);
+    tokio::task::yield_now().await;
+    let receiving = tokio::spawn(async move {
+        let mut cache = AddressCache::default();
+        let announced = receive_announcements(&client, &[mac(2)], &mut cache).await;
+        (announced, cache)
+    });
+    tokio::task::yield_now().await;
+
+    runner.set_ip("10.0.0.50".parse().unwrap());
+
+    let (announced, cache) = receiving.await.unwrap();
+    assert_eq!(announced, Some(address(&runner, 8000)));
+    assert_eq!(cache.get(mac(2), DEFAULT_TTL), announced);
+}
@@ -0,0 +1,150 @@
+use std::net::SocketAddr;
+
+use mac_address::MacAddress;
+use wake_runner::net::{
+    sniffer::{inspect_frame, PacketSource, DEFAULT_PORTS},
+    wake_on_lan::{InvalidMagicPacket, MagicPacket, SecureOnPassword},
+};
+
+const TARGET: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+const SOURCE: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+
+fn ethernet_header(ether_type: u16) -> Vec<u8> {
+    let mut frame = vec![0xFF; 6];
+    frame.extend_from_slice(&SOURCE);
+    frame.extend_from_slice(&ether_type.to_be_bytes());
+    frame
+}
+
+/// Ethernet, a 20 byte v4 header and udp from 10.0.0.1:40000 to `port`
+fn udp_v4_frame(port: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = ethernet_header(0x0800);
+    let mut ip = vec![0u8; 20];
+    ip[0] = 0x45;
+    ip[9] = 17;
+    ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+    ip[16..20].copy_from_slice(&[255, 255, 255, 255]);
+    frame.extend_from_slice(&ip);
+
+    frame.extend_from_slice(&40000u16.to_be_bytes());
+    frame.extend_from_slice(&port.to_be_bytes());
+    frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn parses_plain_and_secureon_packets() {
+    let plain = MagicPacket::parse(&MagicPacket::new(TARGET).to_bytes()).unwrap();
+    assert_eq!(plain.mac_address(), TARGET);
+    assert_eq!(plain.password(), None);
+
+    for password in [
+        SecureOnPassword::Short([1, 2, 3, 4]),
+        SecureOnPassword::Long([1, 2, 3, 4, 5, 6]),
+    ] {
+        let bytes = MagicPacket::new(TARGET).with_password(password).to_bytes();
+        let parsed = MagicPacket::parse(&bytes).unwrap();
+        assert_eq!(parsed.mac_address(), TARGET);
+        assert_eq!(parsed.password(), Some(password));
+    }
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    let bytes = MagicPacket::new(TARGET).to_bytes();
+
+    let mut header = bytes.clone();
+    header[3] = 0xFE;
+    assert_eq!(
+        MagicPacket::parse(&header),
+        Err(InvalidMagicPacket::InvalidHeader)
+    );
+
+    // Repetitions start after the 6 byte header, every one is 6 bytes
+    let mut repetition = bytes.clone();
+    repetition[6 + 6 * 11] ^= 0xFF;
+    assert_eq!(
+        MagicPacket::parse(&repetition),
+        Err(InvalidMagicPacket::MismatchedRepetition(11))
+    );
+
+    let mut password = bytes.clone();
+    password.extend_from_slice(&[1, 2, 3, 4, 5]);
+    assert_eq!(
+        MagicPacket::parse(&password),
+        Err(InvalidMagicPacket::InvalidPasswordLength(5))
+    );
+}
+
+#[test]
+fn rejects_truncated_packets() {
+    let bytes = MagicPacket::new(TARGET).to_bytes();
+
+    for len in [0, 5, 6, 50, 101] {
+        assert_eq!(
+            MagicPacket::parse(&bytes[..len]),
+            Err(InvalidMagicPacket::TooShort(len))
+        );
+    }
+}
+
+#[test]
+fn finds_udp_packets_on_watched_ports() {
+    let payload = MagicPacket::new(TARGET).to_bytes();
+
+    let packet = inspect_frame(&udp_v4_frame(9, &payload), &DEFAULT_PORTS).unwrap();
+    assert_eq!(
+        packet.source,
+        PacketSource::Udp {
+            from: SocketAddr::from(([10, 0, 0, 1], 40000)),
+            port: 9,
+        }
+    );
+    assert_eq!(packet.magic_packet.unwrap().mac_address(), TARGET);
+
+    assert!(inspect_frame(&udp_v4_frame(23032, &payload), &DEFAULT_PORTS).is_none());
+}
+
+#[test]
+fn reports_invalid_packets_on_watched_ports() {
+    let packet = inspect_frame(&udp_v4_frame(7, b"not a magic packet"), &DEFAULT_PORTS).unwrap();
+
+    assert_eq!(packet.magic_packet, Err(InvalidMagicPacket::InvalidHeader));
+}
+
+#[test]
+fn finds_raw_and_vlan_tagged_frames() {
+    let source = MacAddress::new(SOURCE);
+    let raw = MagicPacket::new(TARGET)
+        .over_ethernet()
+        .to_ethernet_frame(SOURCE);
+    let packet = inspect_frame(&raw, &DEFAULT_PORTS).unwrap();
+    assert_eq!(packet.source, PacketSource::Ethernet { from: source });
+    assert_eq!(packet.magic_packet.unwrap().mac_address(), TARGET);
+
+    // 802.1Q tag between the source address and the EtherType
+    let mut tagged = raw[..12].to_vec();
+    tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x05]);
+    tagged.extend_from_slice(&raw[12..]);
+    let packet = inspect_frame(&tagged, &DEFAULT_PORTS).unwrap();
+    assert_eq!(packet.magic_packet.unwrap().mac_address(), TARGET);
+}
+
+#[test]
+fn truncated_frames_are_skipped() {
+    let frame = udp_v4_frame(9, &MagicPacket::new(TARGET).to_bytes());
+
+    // Cut inside the ethernet header, the ip header and the udp header
+    for len in [0, 10, 13, 20, 34, 38] {
+        assert!(inspect_frame(&frame[..len], &DEFAULT_PORTS).is_none());
+    }
+
+    // The udp length is clamped to what was captured
+    let packet = inspect_frame(&frame[..frame.len() - 10], &DEFAULT_PORTS).unwrap();
+    assert!(matches!(
+        packet.magic_packet,
+        Err(InvalidMagicPacket::TooShort(_))
+    ));
+}
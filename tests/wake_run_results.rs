@@ -0,0 +1,33 @@
+use chrono::{Duration, TimeZone, Utc};
+use wake_runner::server::wake::{
+    output::OutputState, record_wake_run_result, WakeRunResult, WakeRunResultMap,
+    MAX_WAKE_RUN_RESULTS,
+};
+
+fn result(index: usize) -> WakeRunResult {
+    WakeRunResult {
+        id: format!("run-{index}"),
+        name: "backup".to_string(),
+        exit_code: Some(0),
+        steps: vec![],
+        termination_reason: None,
+        restarts: 0,
+        output: OutputState::default(),
+        finished_at: Utc.timestamp_opt(1_700_000_000, 0).unwrap() + Duration::seconds(index as i64),
+    }
+}
+
+#[test]
+fn oldest_results_are_dropped_past_the_limit() {
+    let mut results = WakeRunResultMap::new();
+    for index in 0..MAX_WAKE_RUN_RESULTS + 5 {
+        record_wake_run_result(&mut results, result(index));
+    }
+
+    assert_eq!(results.len(), MAX_WAKE_RUN_RESULTS);
+    for index in 0..5 {
+        assert!(!results.contains_key(&format!("run-{index}")));
+    }
+    assert!(results.contains_key("run-5"));
+    assert!(results.contains_key(&format!("run-{}", MAX_WAKE_RUN_RESULTS + 4)));
+}
@@ -0,0 +1,170 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::mpsc, time::Instant};
+use wake_runner::client::wake_up::{wake_up, WakeBackoff, WakeProgress, WakeUpNetwork};
+
+/// A host that answers discovery once `boot_time` has passed after the first magic packet
+#[derive(Clone)]
+struct FakeHost {
+    boot_time: Option<Duration>,
+    first_packet: Arc<Mutex<Option<Instant>>>,
+    packets: Arc<AtomicUsize>,
+    probes: Arc<AtomicUsize>,
+}
+
+impl FakeHost {
+    fn new(boot_time: Option<Duration>) -> FakeHost {
+        FakeHost {
+            boot_time,
+            first_packet: Default::default(),
+            packets: Default::default(),
+            probes: Default::default(),
+        }
+    }
+
+    fn packets(&self) -> usize {
+        self.packets.load(Ordering::SeqCst)
+    }
+
+    fn is_up(&self) -> bool {
+        let first_packet = *self.first_packet.lock().unwrap();
+        match (first_packet, self.boot_time) {
+            (Some(first_packet), Some(boot_time)) => first_packet.elapsed() >= boot_time,
+            _ => false,
+        }
+    }
+}
+
+fn runner_address() -> SocketAddr {
+    "192.0.2.10:4000".parse().unwrap()
+}
+
+impl WakeUpNetwork for FakeHost {
+    async fn send_magic_packets(&self) {
+        self.packets.fetch_add(1, Ordering::SeqCst);
+        self.first_packet
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    async fn discover(&self, timeout: Duration) -> Option<SocketAddr> {
+        self.probes.fetch_add(1, Ordering::SeqCst);
+        if self.is_up() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            return Some(runner_address());
+        }
+        tokio::time::sleep(timeout).await;
+        None
+    }
+}
+
+fn backoff() -> WakeBackoff {
+    WakeBackoff {
+        initial_delay_ms: 100,
+        multiplier: 2.0,
+        max_delay_ms: 1_000,
+        jitter: 0.0,
+        max_attempts: 20,
+        boot_timeout_secs: 30,
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn finds_runner_after_it_boots() {
+    let host = FakeHost::new(Some(Duration::from_secs(5)));
+    let started = Instant::now();
+
+    let address = wake_up(&host, &backoff(), None).await;
+
+    assert_eq!(address, Some(runner_address()));
+    assert!(started.elapsed() >= Duration::from_secs(5));
+    assert!(started.elapsed() < Duration::from_secs(7));
+}
+
+#[tokio::test(start_paused = true)]
+async fn gives_up_after_boot_timeout() {
+    let host = FakeHost::new(None);
+    let started = Instant::now();
+
+    let address = wake_up(&host, &backoff(), None).await;
+
+    assert_eq!(address, None);
+    assert_eq!(started.elapsed(), Duration::from_secs(30));
+    assert_eq!(host.packets(), 20);
+}
+
+#[test]
+fn delays_grow_up_to_the_cap() {
+    let delays: Vec<_> = backoff().delays().take(6).collect();
+
+    assert_eq!(
+        delays,
+        [100, 200, 400, 800, 1_000, 1_000].map(Duration::from_millis)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn stops_sending_when_dropped() {
+    let host = FakeHost::new(None);
+
+    let backoff = backoff();
+    let outer_timeout =
+        tokio::time::timeout(Duration::from_secs(2), wake_up(&host, &backoff, None)).await;
+    assert!(outer_timeout.is_err());
+
+    let packets = host.packets();
+    let probes = host.probes.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_secs(60)).await;
+
+    assert_eq!(host.packets(), packets);
+    assert_eq!(host.probes.load(Ordering::SeqCst), probes);
+}
+
+#[tokio::test(start_paused = true)]
+async fn stops_sending_when_task_is_aborted() {
+    let host = FakeHost::new(None);
+    let task = {
+        let host = host.clone();
+        tokio::spawn(async move { wake_up(&host, &backoff(), None).await })
+    };
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    task.abort();
+    let _ = task.await;
+
+    let packets = host.packets();
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert_eq!(host.packets(), packets);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reports_progress() {
+    let host = FakeHost::new(Some(Duration::from_secs(1)));
+    let (progress, mut events) = mpsc::unbounded_channel();
+
+    wake_up(&host, &backoff(), Some(&progress)).await;
+    drop(progress);
+
+    let mut reported = vec![];
+    while let Some(event) = events.recv().await {
+        reported.push(event);
+    }
+
+    assert_eq!(
+        reported.first(),
+        Some(&WakeProgress::DiscoveryAttempt { attempt: 1 })
+    );
+    assert!(reported.contains(&WakeProgress::PacketSent { attempt: 1 }));
+    assert_eq!(
+        reported.last(),
+        Some(&WakeProgress::RunnerFound(runner_address()))
+    );
+}
@@ -0,0 +1,154 @@
+use std::{os::unix::process::CommandExt, process::Stdio, time::Duration};
+
+use tokio::{process::Child, time::Instant};
+use tokio_util::sync::CancellationToken;
+use wake_runner::server::wake::watchdog::{
+    piped_output, supervise, OutputStream, Termination, TerminationReason, Watchdog,
+};
+
+/// `sh -c script` in its own process group, like wakes are started
+fn spawn(script: &str) -> (Child, Vec<OutputStream>) {
+    let mut command = std::process::Command::new("sh");
+    command.args(["-c", script]);
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    command.process_group(0);
+    let mut child = tokio::process::Command::from(command).spawn().unwrap();
+    let output = piped_output(&mut child);
+    (child, output)
+}
+
+fn termination(signals: &[&str], grace_secs: u64) -> Termination {
+    Termination {
+        signals: signals.iter().map(|signal| signal.to_string()).collect(),
+        grace_secs,
+    }
+}
+
+#[tokio::test]
+async fn process_finishing_in_time_is_left_alone() {
+    let (mut child, child_output) = spawn("echo done; exit 4");
+    let watchdog = Watchdog {
+        timeout: Some(Duration::from_secs(5)),
+        idle_output_timeout: Some(Duration::from_secs(5)),
+    };
+
+    let mut output = vec![];
+    let (exit_status, reason) = supervise(
+        &mut child,
+        child_output,
+        |_, chunk| output.extend_from_slice(chunk),
+        watchdog,
+        &Termination::default(),
+        &CancellationToken::new(),
+    )
+    .await;
+
+    assert_eq!(exit_status.unwrap().code(), Some(4));
+    assert_eq!(reason, None);
+    assert_eq!(output, b"done\n");
+}
+
+#[tokio::test]
+async fn wall_clock_timeout_terminates_the_process() {
+    let (mut child, child_output) = spawn("sleep 30");
+    let watchdog = Watchdog {
+        timeout: Some(Duration::from_millis(200)),
+        idle_output_timeout: None,
+    };
+
+    let started = Instant::now();
+    let (exit_status, reason) = supervise(
+        &mut child,
+        child_output,
+        |_, _| {},
+        watchdog,
+        &Termination::default(),
+        &CancellationToken::new(),
+    )
+    .await;
+
+    assert_eq!(reason, Some(TerminationReason::Timeout));
+    assert!(!exit_status.unwrap().success());
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn silent_process_is_terminated_while_a_chatty_one_keeps_running() {
+    let watchdog = Watchdog {
+        timeout: None,
+        idle_output_timeout: Some(Duration::from_millis(500)),
+    };
+
+    let (mut chatty, chatty_output) = spawn("for i in 1 2 3 4 5 6; do echo $i; sleep 0.2; done");
+    let (exit_status, reason) = supervise(
+        &mut chatty,
+        chatty_output,
+        |_, _| {},
+        watchdog,
+        &Termination::default(),
+        &CancellationToken::new(),
+    )
+    .await;
+    assert!(exit_status.unwrap().success());
+    assert_eq!(reason, None);
+
+    let (mut silent, silent_output) = spawn("echo started; sleep 30");
+    let (_, reason) = supervise(
+        &mut silent,
+        silent_output,
+        |_, _| {},
+        watchdog,
+        &Termination::default(),
+        &CancellationToken::new(),
+    )
+    .await;
+    assert_eq!(reason, Some(TerminationReason::IdleOutput));
+}
+
+#[tokio::test]
+async fn cancelled_stop_terminates_the_process() {
+    let (mut child, child_output) = spawn("sleep 30");
+    let stop = CancellationToken::new();
+    stop.cancel();
+
+    let (exit_status, reason) = supervise(
+        &mut child,
+        child_output,
+        |_, _| {},
+        Watchdog::default(),
+        &Termination::default(),
+        &stop,
+    )
+    .await;
+
+    assert_eq!(reason, Some(TerminationReason::Stopped));
+    assert!(!exit_status.unwrap().success());
+}
+
+#[tokio::test]
+async fn ignored_signals_escalate_to_sigkill() {
+    // The shell ignores SIGTERM, so does the sleep it starts
+    let (mut child, child_output) = spawn("trap '' TERM; sleep 30");
+    let watchdog = Watchdog {
+        timeout: Some(Duration::from_millis(100)),
+        idle_output_timeout: None,
+    };
+
+    let started = Instant::now();
+    let (exit_status, reason) = supervise(
+        &mut child,
+        child_output,
+        |_, _| {},
+        watchdog,
+        &termination(&["SIGTERM"], 1),
+        &CancellationToken::new(),
+    )
+    .await;
+
+    assert_eq!(reason, Some(TerminationReason::Timeout));
+    assert_eq!(exit_status.unwrap().code(), None);
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+}